@@ -0,0 +1,53 @@
+use axum::{
+    async_trait,
+    extract::{rejection::{JsonRejection, PathRejection}, FromRequest, FromRequestParts, Path, Request},
+    http::{request::Parts, StatusCode},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use crate::error::AppError;
+
+/// `Json` whose rejections are reported in the standard API envelope
+pub struct ApiJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ApiJson<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        Json::<T>::from_request(req, state).await
+            .map(|Json(value)| ApiJson(value))
+            .map_err(|rejection: JsonRejection| rejection_error(rejection.status(), rejection.body_text()))
+    }
+}
+
+/// `Path` whose rejections are reported in the standard API envelope
+pub struct ApiPath<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for ApiPath<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Path::<T>::from_request_parts(parts, state).await
+            .map(|Path(value)| ApiPath(value))
+            .map_err(|rejection: PathRejection| rejection_error(rejection.status(), rejection.body_text()))
+    }
+}
+
+/// Maps an extractor rejection to the error clients see; oversized bodies keep their own code
+pub fn rejection_error(status: StatusCode, message: String) -> AppError {
+    match status {
+        StatusCode::PAYLOAD_TOO_LARGE => AppError::ContentTooLarge(message),
+        status if status.is_server_error() => AppError::InternalError(message),
+        _ => AppError::InvalidRequest(message),
+    }
+}
@@ -1,3 +1,4 @@
+pub mod extract;
 pub mod routes;
 pub mod models;
-pub mod response; 
\ No newline at end of file
+pub mod response;
\ No newline at end of file
@@ -1,17 +1,221 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use crate::api::response::ApiResponse;
+use crate::error::{AppError, Result};
+use crate::metadata::PageMetadata;
+use crate::scraper::{ExtractionStrategy, PageLink};
+use crate::structured::StructuredItem;
+use crate::usage::SummaryUsage;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, Default)]
 pub struct ScrapeRequest {
+    /// Page to fetch. When the content is supplied, only used to resolve
+    /// relative links and may be left empty.
+    #[serde(default)]
     pub url: String,
+    /// Page HTML supplied by the caller instead of fetching `url`
+    pub html: Option<String>,
+    /// Plain text supplied by the caller, summarized as is
+    pub text: Option<String>,
+    /// Markdown supplied by the caller, summarized as is
+    pub markdown: Option<String>,
+    /// RSS or Atom feed to digest; a `url` that turns out to be a feed is digested too
+    pub feed_url: Option<String>,
+    /// Number of feed entries to summarize, newest first
+    pub max_items: Option<usize>,
 }
 
-#[derive(Serialize)]
+/// What a scrape request asks to summarize
+#[derive(Clone, Copy)]
+pub enum ScrapeInput<'a> {
+    Url(&'a str),
+    Feed(&'a str),
+    Html(&'a str),
+    Text(&'a str),
+    Markdown(&'a str),
+}
+
+impl ScrapeRequest {
+    /// Checks that the request names exactly one source of content
+    pub fn input(&self) -> Result<ScrapeInput<'_>> {
+        if let Some(feed_url) = &self.feed_url {
+            if !self.url.trim().is_empty() || self.html.is_some() || self.text.is_some() || self.markdown.is_some() {
+                return Err(AppError::InvalidRequest(
+                    "feed_url cannot be combined with url, html, text or markdown".to_string(),
+                ));
+            }
+            return Ok(ScrapeInput::Feed(feed_url));
+        }
+
+        match (&self.html, &self.text, &self.markdown) {
+            (None, None, None) if self.url.trim().is_empty() => Err(AppError::InvalidRequest(
+                "Either url or one of html, text or markdown is required".to_string(),
+            )),
+            (None, None, None) => Ok(ScrapeInput::Url(&self.url)),
+            (Some(html), None, None) => Ok(ScrapeInput::Html(html)),
+            (None, Some(text), None) => Ok(ScrapeInput::Text(text)),
+            (None, None, Some(markdown)) => Ok(ScrapeInput::Markdown(markdown)),
+            _ => Err(AppError::InvalidRequest(
+                "Only one of html, text or markdown may be given".to_string(),
+            )),
+        }
+    }
+
+    /// The URL to show in logs, or the kind of supplied content
+    pub fn label(&self) -> &str {
+        match self.input() {
+            Ok(ScrapeInput::Url(url) | ScrapeInput::Feed(url)) => url,
+            Ok(ScrapeInput::Html(_)) if !self.url.is_empty() => &self.url,
+            Ok(ScrapeInput::Html(_)) => "<supplied html>",
+            Ok(ScrapeInput::Text(_)) => "<supplied text>",
+            Ok(ScrapeInput::Markdown(_)) => "<supplied markdown>",
+            Err(_) => "<invalid request>",
+        }
+    }
+
+    /// The URL reported back in the response
+    pub fn response_url(&self) -> &str {
+        self.feed_url.as_deref().unwrap_or(&self.url)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BatchScrapeRequest {
+    pub requests: Vec<ScrapeRequest>,
+    /// Maximum number of URLs processed at the same time
+    pub concurrency: Option<usize>,
+}
+
+#[derive(Serialize, Clone)]
 pub struct ScrapeResponse {
     pub url: String,
+    /// Normalized form of the URL used for caching
+    pub canonical_url: String,
     #[serde(rename = "summary_markdown")]
     pub summary: String,
+    /// The extracted page content converted to Markdown
+    pub content_markdown: Option<String>,
     pub scraped_at: DateTime<Utc>,
     pub word_count: usize,
+    /// HTTP status the page was served with
+    pub upstream_status: Option<u16>,
+    /// URL the page was served from after following redirects
+    pub final_url: Option<String>,
+    /// How the main content of the page was located
+    pub extraction_strategy: Option<ExtractionStrategy>,
+    /// Title, description, OpenGraph tags and other information about the page
+    pub metadata: Option<PageMetadata>,
+    /// schema.org items from JSON-LD, microdata and RDFa Lite
+    pub structured_data: Option<Vec<StructuredItem>>,
+    /// For feed digests, the summarized entries; `summary` then holds the combined digest
+    pub feed_items: Option<Vec<DigestItem>>,
+    /// LLM tokens and cost of the summary, also when it is served from the cache
+    pub usage: Option<SummaryUsage>,
     pub status: String,
-} 
\ No newline at end of file
+}
+
+/// One entry of a feed digest
+#[derive(Serialize, Clone)]
+pub struct DigestItem {
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub published_at: Option<DateTime<Utc>>,
+    pub summary_markdown: Option<String>,
+    pub word_count: usize,
+    pub usage: Option<SummaryUsage>,
+    /// Why the entry could not be summarized
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ExtractRequest {
+    pub url: String,
+    /// Whether `content` is Markdown (the default) or plain text
+    pub format: Option<ExtractFormat>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractFormat {
+    #[default]
+    Markdown,
+    Text,
+}
+
+/// The extracted content of a page, without a summary
+#[derive(Serialize)]
+pub struct ExtractResponse {
+    pub url: String,
+    /// The page's declared canonical URL, or the normalized request URL
+    pub canonical_url: String,
+    pub final_url: String,
+    pub upstream_status: u16,
+    pub format: ExtractFormat,
+    pub content: String,
+    pub word_count: usize,
+    pub extraction_strategy: ExtractionStrategy,
+    pub metadata: PageMetadata,
+    pub structured_data: Vec<StructuredItem>,
+    /// Absolute links found in the main content
+    pub links: Vec<PageLink>,
+    pub extracted_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct BatchScrapeResponse {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// One entry per request, in request order
+    pub results: Vec<BatchItemResult>,
+}
+
+/// Outcome for a single URL of a batch, wrapped in its own response envelope
+#[derive(Serialize)]
+pub struct BatchItemResult {
+    pub url: String,
+    #[serde(flatten)]
+    pub response: ApiResponse<ScrapeResponse>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str, html: Option<&str>, text: Option<&str>, markdown: Option<&str>) -> ScrapeRequest {
+        ScrapeRequest {
+            url: url.to_string(),
+            html: html.map(str::to_string),
+            text: text.map(str::to_string),
+            markdown: markdown.map(str::to_string),
+            ..ScrapeRequest::default()
+        }
+    }
+
+    #[test]
+    fn input_requires_exactly_one_source() {
+        assert!(matches!(request("", None, None, None).input(), Err(AppError::InvalidRequest(_))));
+        assert!(matches!(request(" ", None, None, None).input(), Err(AppError::InvalidRequest(_))));
+        assert!(matches!(request("", Some("<p>a</p>"), Some("a"), None).input(), Err(AppError::InvalidRequest(_))));
+        assert!(matches!(request("", None, Some("a"), Some("# a")).input(), Err(AppError::InvalidRequest(_))));
+        assert!(matches!(request("https://a.example/", None, None, None).input(), Ok(ScrapeInput::Url("https://a.example/"))));
+        assert!(matches!(request("", None, Some("a"), None).input(), Ok(ScrapeInput::Text("a"))));
+        assert!(matches!(request("", None, None, Some("# a")).input(), Ok(ScrapeInput::Markdown("# a"))));
+    }
+
+    #[test]
+    fn url_next_to_html_is_only_the_base_for_links() {
+        let req = request("https://a.example/post", Some("<p>a</p>"), None, None);
+        assert!(matches!(req.input(), Ok(ScrapeInput::Html("<p>a</p>"))));
+        assert_eq!(req.label(), "https://a.example/post");
+        assert_eq!(request("", Some("<p>a</p>"), None, None).label(), "<supplied html>");
+    }
+
+    #[test]
+    fn feed_url_cannot_be_combined_with_other_sources() {
+        let feed = |req: ScrapeRequest| ScrapeRequest { feed_url: Some("https://a.example/feed".to_string()), ..req };
+        assert!(matches!(feed(request("", None, None, None)).input(), Ok(ScrapeInput::Feed("https://a.example/feed"))));
+        assert!(matches!(feed(request("https://a.example/", None, None, None)).input(), Err(AppError::InvalidRequest(_))));
+        assert!(matches!(feed(request("", None, Some("a"), None)).input(), Err(AppError::InvalidRequest(_))));
+    }
+}
@@ -1,7 +1,9 @@
 use serde::Serialize;
 use axum::Json;
-use axum::http::StatusCode;
+use axum::http::{header::{RETRY_AFTER, WWW_AUTHENTICATE}, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
 use chrono::Utc;
+use crate::error::{AppError, ErrorBody};
 
 #[derive(Serialize)]
 pub struct ApiResponse<T> {
@@ -15,38 +17,60 @@ pub struct ResponseMeta {
     pub status_code: u16,
     pub timestamp: String,
     pub message: Option<String>,
+    /// Code, retryability and upstream details of an error
+    pub error: Option<ErrorBody>,
 }
 
-pub fn success<T: Serialize>(data: T) -> (StatusCode, Json<ApiResponse<T>>) {
-    let meta = ResponseMeta {
-        status: "success".to_string(),
-        status_code: StatusCode::OK.as_u16(),
-        timestamp: Utc::now().to_rfc3339(),
-        message: None,
-    };
-
-    (
-        StatusCode::OK,
-        Json(ApiResponse {
+impl<T> ApiResponse<T> {
+    /// Builds a success envelope without wrapping it in an HTTP response
+    pub fn success(status: StatusCode, data: T) -> Self {
+        ApiResponse {
             data: Some(data),
-            meta,
-        }),
-    )
+            meta: ResponseMeta {
+                status: "success".to_string(),
+                status_code: status.as_u16(),
+                timestamp: Utc::now().to_rfc3339(),
+                message: None,
+                error: None,
+            },
+        }
+    }
+
+    /// Builds an error envelope without wrapping it in an HTTP response
+    pub fn error(err: &AppError) -> Self {
+        ApiResponse {
+            data: None,
+            meta: ResponseMeta {
+                status: "error".to_string(),
+                status_code: err.status_code().as_u16(),
+                timestamp: Utc::now().to_rfc3339(),
+                message: Some(err.message().to_string()),
+                error: Some(err.body()),
+            },
+        }
+    }
+}
+
+pub fn success<T: Serialize>(data: T) -> (StatusCode, Json<ApiResponse<T>>) {
+    success_with_status(StatusCode::OK, data)
 }
 
-pub fn error<T>(status: StatusCode, message: String) -> (StatusCode, Json<ApiResponse<T>>) {
-    let meta = ResponseMeta {
-        status: "error".to_string(),
-        status_code: status.as_u16(),
-        timestamp: Utc::now().to_rfc3339(),
-        message: Some(message),
-    };
+pub fn success_with_status<T: Serialize>(status: StatusCode, data: T) -> (StatusCode, Json<ApiResponse<T>>) {
+    (status, Json(ApiResponse::success(status, data)))
+}
 
-    (
-        status,
-        Json(ApiResponse {
-            data: None,
-            meta,
-        }),
-    )
-} 
\ No newline at end of file
+/// Errors render as the standard envelope, with `Retry-After` when the upstream asked for a delay
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let envelope = Json(ApiResponse::<()>::error(&self));
+        let mut response = (self.status_code(), envelope).into_response();
+        let headers = response.headers_mut();
+        if let Some(seconds) = self.details().and_then(|details| details.retry_after) {
+            headers.insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        if matches!(self, AppError::Unauthorized(_)) {
+            headers.insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
@@ -1,23 +1,77 @@
 use axum::{
-    routing::post,
+    middleware,
+    routing::{get, post},
     Router,
-    extract::{Json, State},
-    response::IntoResponse,
+    extract::{
+        multipart::{MultipartError, MultipartRejection}, DefaultBodyLimit, Multipart, State,
+    },
+    Extension,
+    http::{Method, StatusCode, Uri},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
 };
+use futures::future::join_all;
+use futures::stream::{self, Stream};
 use tower_http::cors::{CorsLayer, Any};
-use chrono::Utc;
+use std::convert::Infallible;
 use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+use uuid::Uuid;
 
-use crate::error::{Result, AppError};
-use crate::api::models::{ScrapeRequest, ScrapeResponse};
-use crate::api::response;
-use crate::scraper::{fetch_html, extract_body, format_html, build_prompt};
-use crate::llm::call_openrouter;
-use crate::{AppState, CachedResponse};
+use crate::auth::{require_scope, ApiClient, ScopeGuard};
+use crate::config::ApiScope;
+use crate::encoding::decode_html;
+use crate::error::{AppError, Result};
+use crate::api::models::{
+    BatchItemResult, BatchScrapeRequest, BatchScrapeResponse, ExtractRequest, ScrapeRequest, ScrapeResponse,
+};
+use crate::jobs::CancelError;
+use crate::limits::{rate_limit, Billing};
+use crate::api::extract::{rejection_error, ApiJson, ApiPath};
+use crate::api::response::{self, ApiResponse};
+use crate::pipeline::{process_extract_request, process_scrape_request, PipelineEvent};
+use crate::AppState;
 
 pub fn create_router(app_state: AppState) -> Router {
-    Router::new()
+    let max_input_bytes = app_state.config.extract.max_input_bytes;
+    let guard = |scope| middleware::from_fn_with_state(
+        ScopeGuard { keys: app_state.keys.clone(), scope },
+        require_scope,
+    );
+    let limited = || middleware::from_fn_with_state(app_state.limits.clone(), rate_limit);
+
+    let scrape_routes = Router::new()
         .route("/api/scrape", post(scrape_handler))
+        .route("/api/scrape/upload", post(scrape_upload_handler))
+        .route("/api/scrape/stream", post(scrape_stream_handler))
+        .route("/api/scrape/batch", post(scrape_batch_handler))
+        .route("/api/jobs", post(create_job_handler))
+        // The last layer runs first, so the rate limit knows which client is calling
+        .route_layer(limited())
+        .route_layer(guard(ApiScope::Scrape));
+    // Polling or cancelling a job does no new work, so it is not rate limited
+    let job_routes = Router::new()
+        .route("/api/jobs/:id", get(get_job_handler).delete(cancel_job_handler))
+        .route_layer(guard(ApiScope::Scrape));
+    let extract_routes = Router::new()
+        .route("/api/extract", post(extract_handler))
+        .route_layer(limited())
+        .route_layer(guard(ApiScope::Extract));
+    let admin_routes = Router::new()
+        .route("/api/usage", get(usage_handler))
+        .route_layer(limited())
+        .route_layer(guard(ApiScope::Admin));
+
+    Router::new()
+        .merge(scrape_routes)
+        .merge(job_routes)
+        .merge(extract_routes)
+        .merge(admin_routes)
+        .method_not_allowed_fallback(method_not_allowed_handler)
+        .fallback(not_found_handler)
+        .layer(DefaultBodyLimit::max(max_input_bytes))
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -29,195 +83,503 @@ pub fn create_router(app_state: AppState) -> Router {
 
 async fn scrape_handler(
     State(state): State<AppState>,
-    Json(req): Json<ScrapeRequest>,
-) -> impl IntoResponse {
-    println!("Processing request for URL: {}", req.url);
+    Extension(client): Extension<ApiClient>,
+    ApiJson(req): ApiJson<ScrapeRequest>,
+) -> Result<impl IntoResponse> {
+    println!("Processing request for {}", req.label());
     let start_time = std::time::Instant::now();
+    let billing = Billing::new(state.limits.clone(), client);
     
     // Set an overall timeout for the entire handler
     let result = tokio::time::timeout(
-        Duration::from_secs(90), // Overall handler timeout of 90 seconds
-        process_scrape_request(&state, &req)
-    ).await;
+        state.config.request_timeout,
+        process_scrape_request(&state, &req, &billing, None)
+    ).await.unwrap_or_else(|_| Err(timed_out()));
     
     let elapsed = start_time.elapsed();
     println!("Request Processing took: {:?}", elapsed);
     
     match result {
-        Ok(result) => match result {
-            Ok(response_data) => {
-                println!("Successfully processed URL: {}", req.url);
-                response::success(response_data)
-            },
-            Err(err) => {
-                let (status, msg) = match &err {
-                    AppError::FetchError(msg) => {
-                        println!("Fetch error: {}", msg);
-                        (axum::http::StatusCode::BAD_REQUEST, msg.clone())
-                    },
-                    AppError::ParseError(msg) => {
-                        println!("Parse error: {}", msg);
-                        (axum::http::StatusCode::UNPROCESSABLE_ENTITY, msg.clone())
-                    },
-                    AppError::LlmError(msg) => {
-                        println!("LLM error: {}", msg);
-                        (axum::http::StatusCode::INTERNAL_SERVER_ERROR, msg.clone())
-                    },
-                    AppError::ConfigError(msg) => {
-                        println!("Config error: {}", msg);
-                        (axum::http::StatusCode::INTERNAL_SERVER_ERROR, msg.clone())
-                    },
-                };
-                
-                response::error(status, msg)
-            }
+        Ok(response_data) => {
+            println!("Successfully processed {}", req.label());
+            Ok(response::success(response_data))
         },
-        Err(_) => {
-            println!("Request timed out after {:?}", elapsed);
-            response::error(
-                axum::http::StatusCode::REQUEST_TIMEOUT, 
-                "Request processing timed out".to_string()
-            )
+        Err(err) => {
+            println!("Request failed for {} ({}): {}", req.label(), err.code(), err);
+            Err(err)
         },
     }
 }
 
-async fn process_scrape_request(state: &AppState, req: &ScrapeRequest) -> Result<ScrapeResponse> {
-    // Check cache first
-    let cache_key = &req.url;
-    
-    // Try to get from cache with lock scope to minimize lock contention
-    {
-        let cache = state.cache.lock().unwrap();
-        if let Some(cached) = cache.get(cache_key) {
-            // Only use cache if it's less than 24 hours old
-            let cache_age = Utc::now() - cached.timestamp;
-            if cache_age < chrono::Duration::hours(24) {
-                println!("Cache hit for URL: {}", req.url);
-                return Ok(ScrapeResponse {
-                    url: req.url.clone(),
-                    summary: cached.summary.clone(),
-                    scraped_at: Utc::now(),
-                    word_count: cached.word_count,
-                    status: "success (cached)".to_string(),
-                });
-            }
-        }
-    }
+/// The error for requests that exceed their handler's time limit
+fn timed_out() -> AppError {
+    AppError::Timeout("Request processing timed out".to_string())
+}
 
-    println!("Fetching HTML for URL: {}", req.url);
-    let fetch_start = std::time::Instant::now();
-    
-    // Fetch with even shorter timeout - 5 seconds
-    let html_result = tokio::time::timeout(
-        Duration::from_secs(5), 
-        fetch_html(&req.url)
-    ).await;
-    
-    let html = match html_result {
-        Ok(result) => {
-            match result {
-                Ok(html) => {
-                    println!("HTML fetch successful in {:?}", fetch_start.elapsed());
-                    html
-                },
-                Err(e) => {
-                    println!("HTML fetch error: {}", e);
-                    return Err(AppError::FetchError(format!("Failed to fetch HTML: {}", e)));
-                }
-            }
+/// Summarizes an uploaded HTML, Markdown or plain text file.
+///
+/// Takes a multipart form with a `file` field and optional `url` and `format`
+/// fields; without `format`, the kind of file is taken from its content type
+/// or file name.
+async fn scrape_upload_handler(
+    State(state): State<AppState>,
+    client: Extension<ApiClient>,
+    multipart: std::result::Result<Multipart, MultipartRejection>,
+) -> Response {
+    let upload = match multipart {
+        Ok(mut multipart) => read_upload(&mut multipart).await,
+        Err(rejection) => Err(rejection_error(rejection.status(), rejection.body_text())),
+    };
+    match upload {
+        Ok(req) => scrape_handler(State(state), client, ApiJson(req)).await.into_response(),
+        Err(err) => {
+            println!("Rejected upload: {}", err);
+            err.into_response()
         },
-        Err(_) => {
-            println!("HTML fetch timed out after 5 seconds");
-            return Err(AppError::FetchError("HTML fetch timed out after 5 seconds".to_string()));
+    }
+}
+
+async fn read_upload(multipart: &mut Multipart) -> Result<ScrapeRequest> {
+    let invalid = |e: MultipartError| rejection_error(e.status(), e.body_text());
+
+    let mut req = ScrapeRequest::default();
+    let mut format = None;
+    let mut file = None;
+    while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+        match field.name() {
+            Some("url") => req.url = field.text().await.map_err(invalid)?,
+            Some("format") => format = Some(field.text().await.map_err(invalid)?.trim().to_ascii_lowercase()),
+            Some("file") => {
+                let content_type = field.content_type().map(str::to_string);
+                let detected = upload_format(content_type.as_deref(), field.file_name());
+                let bytes = field.bytes().await.map_err(invalid)?;
+                file = Some((detected, content_type, bytes));
+            },
+            _ => {},
         }
+    }
+
+    let Some((detected, content_type, bytes)) = file else {
+        return Err(AppError::InvalidRequest("The form has no file field".to_string()));
     };
-    
-    println!("🔍 Extracting and formatting HTML content");
-    let raw_body = extract_body(&html)
-        .ok_or_else(|| {
-            println!("No <body> tag found in HTML");
-            crate::error::AppError::ParseError("No <body> tag found in the HTML".to_string())
-        })?;
-    
-    let formatted = format_html(&raw_body);
-    println!("Content size: {} chars (using full content)", formatted.len());
-    
-    let prompt = build_prompt(&formatted);
-    println!("Built prompt with length: {} chars", prompt.len());
+    // HTML files may be saved in a legacy encoding declared only in their <meta> tag
+    let text = || String::from_utf8_lossy(&bytes).into_owned();
+    match format.as_deref().or(detected) {
+        Some("html") => req.html = Some(decode_html(&bytes, content_type.as_deref(), None).0),
+        Some("text") => req.text = Some(text()),
+        Some("markdown") => req.markdown = Some(text()),
+        Some(other) => return Err(AppError::InvalidRequest(format!(
+            "Unsupported format {:?}; expected html, text or markdown", other,
+        ))),
+        None => return Err(AppError::InvalidRequest(
+            "Cannot tell the kind of file; set the format field to html, text or markdown".to_string(),
+        )),
+    }
+    Ok(req)
+}
 
-    // Calculate word count
-    let word_count = formatted.split_whitespace().count();
-    println!("Word count: {}", word_count);
+/// Guesses whether an uploaded file is HTML, Markdown or plain text
+fn upload_format(content_type: Option<&str>, file_name: Option<&str>) -> Option<&'static str> {
+    let mime = content_type.unwrap_or_default().split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    match mime.as_str() {
+        "text/html" | "application/xhtml+xml" => return Some("html"),
+        "text/markdown" | "text/x-markdown" => return Some("markdown"),
+        "text/plain" => return Some("text"),
+        _ => {},
+    }
 
-    println!("Calling LLM API...");
-    let llm_start = std::time::Instant::now();
-    
-    // Try with an even shorter timeout for the LLM
-    let summary_result = call_openrouter(
-        &state.config.openrouter_api_key, 
-        &prompt, 
-        Some(&req.url), 
-        None
-    ).await;
-    
-    let summary = match summary_result {
-        Ok(summary) => {
-            println!("LLM API call successful in {:?}", llm_start.elapsed());
-            summary
+    let extension = file_name?.rsplit_once('.')?.1.to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" | "xhtml" => Some("html"),
+        "md" | "markdown" => Some("markdown"),
+        "txt" | "text" => Some("text"),
+        _ => None,
+    }
+}
+
+/// Fetches a page and returns its extracted content without summarizing it,
+/// so it works even when no LLM is configured
+async fn extract_handler(
+    State(state): State<AppState>,
+    ApiJson(req): ApiJson<ExtractRequest>,
+) -> Result<impl IntoResponse> {
+    println!("Extracting content for URL: {}", req.url);
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(30),
+        process_extract_request(&state, &req)
+    ).await.unwrap_or_else(|_| Err(timed_out()));
+
+    match result {
+        Ok(response_data) => Ok(response::success(response_data)),
+        Err(err) => {
+            println!("Extraction failed for {} ({}): {}", req.url, err.code(), err);
+            Err(err)
         },
-        Err(e) => {
-            println!("LLM API error: {}", e);
-            return Err(AppError::LlmError(format!("LLM API error: {}", e)));
-        }
-    };
+    }
+}
 
-    println!("Formatting summary...");
-    // Ensure proper Markdown formatting
-    let formatted_summary = ensure_markdown_formatting(&summary);
-    
-    // No truncation happening now, so use the summary directly
-    let final_summary = formatted_summary;
-
-    println!("Storing result in cache");
-    // Store in cache
-    {
-        let mut cache = state.cache.lock().unwrap();
-        cache.insert(req.url.clone(), CachedResponse {
-            summary: final_summary.clone(),
-            word_count,
-            timestamp: Utc::now(),
-        });
+/// Scrapes many URLs with bounded concurrency.
+///
+/// Every URL goes through the same cached pipeline as `/api/scrape`; a failing
+/// URL only produces an error entry and never fails the whole batch.
+async fn scrape_batch_handler(
+    State(state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    ApiJson(batch): ApiJson<BatchScrapeRequest>,
+) -> Result<impl IntoResponse> {
+    let limits = &state.config.batch;
+    if batch.requests.len() > limits.max_urls {
+        return Err(AppError::ContentTooLarge(
+            format!("Batch contains {} URLs; the limit is {}", batch.requests.len(), limits.max_urls),
+        ));
     }
 
-    println!("Request completed successfully for URL: {}", req.url);
-    Ok(ScrapeResponse {
-        url: req.url.clone(),
-        summary: final_summary,
-        scraped_at: Utc::now(),
-        word_count,
-        status: "success".to_string(),
-    })
+    let concurrency = batch.concurrency
+        .unwrap_or(limits.default_concurrency)
+        .clamp(1, limits.max_concurrency);
+    println!("Processing batch of {} URLs with concurrency {}", batch.requests.len(), concurrency);
+    let start_time = std::time::Instant::now();
+
+    let permits = Semaphore::new(concurrency);
+    let billing = Billing::new(state.limits.clone(), client);
+    let tasks = batch.requests.into_iter().map(|req| {
+        let (state, billing, permits) = (&state, &billing, &permits);
+        async move {
+            let _permit = permits.acquire().await.expect("batch semaphore is never closed");
+            let response = match tokio::time::timeout(
+                state.config.request_timeout,
+                process_scrape_request(state, &req, billing, None)
+            ).await.unwrap_or_else(|_| Err(timed_out())) {
+                Ok(data) => ApiResponse::success(StatusCode::OK, data),
+                Err(err) => {
+                    println!("Batch item failed for {} ({}): {}", req.label(), err.code(), err);
+                    ApiResponse::<ScrapeResponse>::error(&err)
+                },
+            };
+            BatchItemResult { url: req.response_url().to_string(), response }
+        }
+    });
+
+    let results = join_all(tasks).await;
+    let succeeded = results.iter().filter(|item| item.response.data.is_some()).count();
+    println!("Batch processing took: {:?} ({} of {} succeeded)", start_time.elapsed(), succeeded, results.len());
+
+    Ok(response::success(BatchScrapeResponse {
+        total: results.len(),
+        succeeded,
+        failed: results.len() - succeeded,
+        results,
+    }))
 }
 
-/// Ensures the text is properly formatted as Markdown.
-/// This function does some basic validation and formatting to improve the Markdown structure.
-fn ensure_markdown_formatting(text: &str) -> String {
-    let text = text.trim();
-    
-    // Make sure it has a title/heading
-    if !text.starts_with('#') {
-        // Try to find a good title from the first line or use "Website Summary"
-        let first_line = text.lines().next().unwrap_or("Website Summary");
-        let title = if first_line.len() > 50 {
-            "Website Summary"
-        } else {
-            first_line
+/// Streams pipeline progress as server-sent events.
+///
+/// The pipeline runs in its own task so that the result is still cached if the
+/// client disconnects before the summary is complete.
+async fn scrape_stream_handler(
+    State(state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    ApiJson(req): ApiJson<ScrapeRequest>,
+) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>> {
+    println!("Processing streaming request for {}", req.label());
+    let (tx, rx) = mpsc::unbounded_channel();
+    let billing = Billing::new(state.limits.clone(), client);
+
+    tokio::spawn(async move {
+        let start_time = std::time::Instant::now();
+        let result = tokio::time::timeout(
+            state.config.request_timeout,
+            process_scrape_request(&state, &req, &billing, Some(&tx))
+        ).await.unwrap_or_else(|_| Err(timed_out()));
+        println!("Streaming request processing took: {:?}", start_time.elapsed());
+
+        let event = match result {
+            Ok(response) => PipelineEvent::Done { response: Box::new(response) },
+            Err(err) => {
+                println!("Streaming request failed ({}): {}", err.code(), err);
+                PipelineEvent::Error { status_code: err.status_code().as_u16(), error: err.body() }
+            },
         };
-        
-        return format!("# {}\n\n{}", title, text);
+        let _ = tx.send(event);
+    });
+
+    let events = stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        let sse_event = Event::default()
+            .event(event.name())
+            .json_data(&event)
+            .unwrap_or_else(|e| Event::default().event("error").data(e.to_string()));
+        Some((Ok(sse_event), rx))
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Queues a scrape job and returns its id immediately
+async fn create_job_handler(
+    State(state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    ApiJson(req): ApiJson<ScrapeRequest>,
+) -> Result<impl IntoResponse> {
+    req.input()?;
+    let status = state.jobs.submit(state.clone(), client, req);
+    Ok(response::success_with_status(StatusCode::ACCEPTED, status))
+}
+
+async fn get_job_handler(
+    State(state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<impl IntoResponse> {
+    match state.jobs.get(&id, &client) {
+        Some(status) => Ok(response::success(status)),
+        None => Err(AppError::NotFound(format!("Job {} not found", id))),
     }
-    
-    // Text already has Markdown formatting, return as is
-    text.to_string()
-} 
\ No newline at end of file
+}
+
+async fn cancel_job_handler(
+    State(state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    ApiPath(id): ApiPath<Uuid>,
+) -> Result<impl IntoResponse> {
+    match state.jobs.cancel(&id, &client) {
+        Ok(status) => Ok(response::success(status)),
+        Err(CancelError::NotFound) => Err(AppError::NotFound(format!("Job {} not found", id))),
+        Err(CancelError::AlreadyFinished(job_state)) => {
+            Err(AppError::Conflict(format!("Job {} already finished ({:?})", id, job_state)))
+        },
+    }
+}
+
+/// Reports the LLM tokens and cost of every client for today and this month
+async fn usage_handler(State(state): State<AppState>) -> impl IntoResponse {
+    response::success(state.limits.spend())
+}
+
+/// Unknown paths get the standard envelope too
+async fn not_found_handler(uri: Uri) -> AppError {
+    AppError::NotFound(format!("No route for {}", uri.path()))
+}
+
+async fn method_not_allowed_handler(method: Method, uri: Uri) -> AppError {
+    AppError::MethodNotAllowed(format!("{} is not supported for {}", method, uri.path()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+    use std::sync::atomic::Ordering;
+    use crate::testing::{self, FakeLlm};
+
+    async fn post(base: &str, path: &str, body: Value) -> (u16, Value) {
+        let response = reqwest::Client::new().post(format!("{}{}", base, path)).json(&body).send().await.unwrap();
+        (response.status().as_u16(), response.json().await.unwrap())
+    }
+
+    /// A local URL whose server accepts connections but never answers
+    async fn hanging_url() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+        format!("http://{}/slow", addr)
+    }
+
+    fn texts(count: usize) -> Vec<Value> {
+        (0..count).map(|i| json!({ "text": format!("Text number {}", i) })).collect()
+    }
+
+    #[tokio::test]
+    async fn batch_reports_failed_and_timed_out_items_next_to_successes() {
+        let mut config = testing::config();
+        config.request_timeout = Duration::from_millis(300);
+        let base = testing::serve(testing::state_with(config, Arc::new(FakeLlm::replying("A summary")))).await;
+
+        let requests = json!([{ "text": "Some text" }, { "url": hanging_url().await }, { "url": "not a url" }]);
+        let (status, body) = post(&base, "/api/scrape/batch", json!({ "requests": requests })).await;
+        assert_eq!(status, 200);
+
+        let data = &body["data"];
+        assert_eq!((data["total"].as_u64(), data["succeeded"].as_u64(), data["failed"].as_u64()), (Some(3), Some(1), Some(2)));
+        let results = data["results"].as_array().unwrap();
+        assert!(results[0]["data"]["summary_markdown"].as_str().unwrap().contains("A summary"));
+        assert_eq!(results[1]["meta"]["error"]["code"], "GATEWAY_TIMEOUT");
+        assert_eq!(results[2]["meta"]["error"]["code"], "INVALID_URL");
+    }
+
+    #[tokio::test]
+    async fn batch_rejects_more_than_the_maximum_urls() {
+        let mut config = testing::config();
+        config.batch.max_urls = 2;
+        let base = testing::serve(testing::state_with(config, Arc::new(FakeLlm::replying("A summary")))).await;
+
+        let (status, body) = post(&base, "/api/scrape/batch", json!({ "requests": texts(3) })).await;
+        assert_eq!(status, 413);
+        assert_eq!(body["meta"]["error"]["code"], "CONTENT_TOO_LARGE");
+    }
+
+    #[tokio::test]
+    async fn batch_concurrency_is_clamped_to_the_maximum() {
+        let mut config = testing::config();
+        config.batch.max_concurrency = 2;
+        let llm = Arc::new(FakeLlm::replying("A summary").with_delay(Duration::from_millis(50)));
+        let base = testing::serve(testing::state_with(config, llm.clone())).await;
+
+        let (status, body) = post(&base, "/api/scrape/batch", json!({ "requests": texts(6), "concurrency": 100 })).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["data"]["succeeded"], 6);
+        assert_eq!(llm.peak.load(Ordering::SeqCst), 2);
+    }
+
+    /// Posts a multipart form of text fields and one file to the upload endpoint
+    async fn upload(base: &str, fields: &[(&str, &str)], file: (&str, Option<&str>, &[u8])) -> (u16, Value) {
+        let boundary = "upload-test-boundary";
+        let mut body = Vec::new();
+        for (name, value) in fields {
+            body.extend(format!("--{}\r\ncontent-disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n", boundary, name, value).bytes());
+        }
+        let (file_name, content_type, bytes) = file;
+        body.extend(format!("--{}\r\ncontent-disposition: form-data; name=\"file\"; filename=\"{}\"\r\n", boundary, file_name).bytes());
+        if let Some(content_type) = content_type {
+            body.extend(format!("content-type: {}\r\n", content_type).bytes());
+        }
+        body.extend(b"\r\n");
+        body.extend(bytes);
+        body.extend(format!("\r\n--{}--\r\n", boundary).bytes());
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/scrape/upload", base))
+            .header("content-type", format!("multipart/form-data; boundary={}", boundary))
+            .body(body)
+            .send().await.unwrap();
+        (response.status().as_u16(), response.json().await.unwrap())
+    }
+
+    #[test]
+    fn upload_format_prefers_the_content_type_over_the_extension() {
+        assert_eq!(upload_format(Some("text/html; charset=utf-8"), Some("notes.txt")), Some("html"));
+        assert_eq!(upload_format(Some("TEXT/MARKDOWN"), None), Some("markdown"));
+        assert_eq!(upload_format(Some("text/plain"), Some("page.html")), Some("text"));
+        assert_eq!(upload_format(Some("application/octet-stream"), Some("README.MD")), Some("markdown"));
+        assert_eq!(upload_format(None, Some("page.htm")), Some("html"));
+        assert_eq!(upload_format(None, Some("notes.txt")), Some("text"));
+        assert_eq!(upload_format(None, Some("archive.zip")), None);
+        assert_eq!(upload_format(None, Some("README")), None);
+        assert_eq!(upload_format(None, None), None);
+    }
+
+    #[tokio::test]
+    async fn uploaded_html_is_decoded_from_the_charset_in_its_meta_tag() {
+        let base = testing::serve(testing::state(Arc::new(FakeLlm::replying("A summary")))).await;
+        let mut html = b"<html><head><meta charset=\"windows-1252\"><title>Caf".to_vec();
+        html.extend(b"\xe9</title></head><body><article><p>Some article text about the caf\xe9.</p></article></body></html>");
+
+        let (status, body) = upload(&base, &[], ("page.html", None, &html)).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["data"]["metadata"]["title"], "Café");
+        assert!(body["data"]["canonical_url"].as_str().unwrap().starts_with("content:sha256:"));
+    }
+
+    #[tokio::test]
+    async fn upload_format_field_overrides_detection() {
+        let base = testing::serve(testing::state(Arc::new(FakeLlm::replying("A summary")))).await;
+        let file = ("page.html", Some("text/html"), b"<p>Shown as is</p>".as_slice());
+
+        let (status, body) = upload(&base, &[("format", "Text")], file).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["data"]["extraction_strategy"], "verbatim");
+        assert_eq!(body["data"]["content_markdown"], "<p>Shown as is</p>");
+    }
+
+    #[tokio::test]
+    async fn upload_of_unknown_kind_is_rejected() {
+        let base = testing::serve(testing::state(Arc::new(FakeLlm::replying("A summary")))).await;
+
+        let (status, body) = upload(&base, &[], ("data.bin", Some("application/octet-stream"), b"\x00\x01")).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["meta"]["error"]["code"], "INVALID_REQUEST");
+
+        let (status, _) = upload(&base, &[("format", "pdf")], ("data.bin", None, b"\x00\x01")).await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn wrong_methods_get_the_error_envelope() {
+        let base = testing::serve(testing::state(Arc::new(FakeLlm::replying("A summary")))).await;
+
+        let response = reqwest::get(format!("{}/api/scrape", base)).await.unwrap();
+        assert_eq!(response.status().as_u16(), 405);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["data"], Value::Null);
+        assert_eq!(body["meta"]["status_code"], 405);
+        assert_eq!(body["meta"]["error"]["code"], "METHOD_NOT_ALLOWED");
+        assert_eq!(body["meta"]["message"], "GET is not supported for /api/scrape");
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_rate_get_429_with_retry_after() {
+        let mut config = testing::config();
+        config.limits.defaults.requests_per_minute = 1;
+        let base = testing::serve(testing::state_with(config, Arc::new(FakeLlm::replying("A summary")))).await;
+        let client = reqwest::Client::new();
+
+        let response = client.post(format!("{}/api/scrape", base)).json(&json!({ "text": "Some text" })).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["x-ratelimit-remaining"], "0");
+
+        let response = client.post(format!("{}/api/scrape", base)).json(&json!({ "text": "Other text" })).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 429);
+        let retry_after: u64 = response.headers()["retry-after"].to_str().unwrap().parse().unwrap();
+        assert!((1..=60).contains(&retry_after));
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["meta"]["error"]["code"], "RATE_LIMITED");
+        assert_eq!(body["meta"]["error"]["details"]["retry_after"], retry_after);
+    }
+
+    #[tokio::test]
+    async fn polling_a_job_is_not_rate_limited() {
+        let mut config = testing::config();
+        config.limits.defaults.requests_per_minute = 1;
+        let base = testing::serve(testing::state_with(config, Arc::new(FakeLlm::replying("A summary")))).await;
+
+        let (status, body) = post(&base, "/api/jobs", json!({ "text": "Some text" })).await;
+        assert_eq!(status, 202);
+        let job = body["data"]["id"].as_str().unwrap().to_string();
+        for _ in 0..5 {
+            let response = reqwest::get(format!("{}/api/jobs/{}", base, job)).await.unwrap();
+            assert_eq!(response.status().as_u16(), 200);
+        }
+        let (status, _) = post(&base, "/api/jobs", json!({ "text": "Other text" })).await;
+        assert_eq!(status, 429);
+    }
+
+    #[tokio::test]
+    async fn summaries_fail_once_the_quota_is_used_up() {
+        let mut config = testing::config();
+        // The fake LLM reports 15 tokens per completion
+        config.limits.defaults.daily_tokens = 15;
+        let base = testing::serve(testing::state_with(config, Arc::new(FakeLlm::replying("A summary")))).await;
+        let client = reqwest::Client::new();
+
+        let response = client.post(format!("{}/api/scrape", base)).json(&json!({ "text": "Some text" })).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["x-quota-daily-remaining"], "0");
+
+        let response = client.post(format!("{}/api/scrape", base)).json(&json!({ "text": "Other text" })).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 429);
+        assert!(response.headers().contains_key("retry-after"));
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["meta"]["error"]["code"], "QUOTA_EXCEEDED");
+
+        // The cached summary costs nothing and is still served
+        let (status, _) = post(&base, "/api/scrape", json!({ "text": "Some text" })).await;
+        assert_eq!(status, 200);
+    }
+}
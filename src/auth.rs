@@ -0,0 +1,190 @@
+use axum::{
+    extract::{Request, State},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::config::{ApiScope, AuthConfig, ClientLimits, LimitsConfig};
+use crate::error::{AppError, Result};
+
+// Header for clients that cannot send `Authorization: Bearer`
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// The client behind a request, attached to it by the authentication middleware
+#[derive(Clone, Debug)]
+pub struct ApiClient {
+    /// Name of the API key, or `anonymous` when authentication is off. Jobs
+    /// belong to the client that submitted them.
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub limits: ClientLimits,
+}
+
+impl ApiClient {
+    /// Every request is made by this client when authentication is off, so
+    /// they all share one set of limits
+    fn anonymous(limits: ClientLimits) -> Self {
+        ApiClient { name: "anonymous".to_string(), scopes: vec![ApiScope::Admin], limits }
+    }
+
+    /// Admin keys may do everything
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.iter().any(|granted| *granted == scope || *granted == ApiScope::Admin)
+    }
+}
+
+/// The configured API keys, looked up by the SHA-256 of the presented key
+pub struct KeyStore {
+    keys: HashMap<String, ApiClient>,
+    enabled: bool,
+    default_limits: ClientLimits,
+}
+
+impl KeyStore {
+    pub fn new(config: &AuthConfig, limits: &LimitsConfig) -> Self {
+        let keys: HashMap<String, ApiClient> = config.keys.iter()
+            .map(|key| {
+                let client = ApiClient {
+                    name: key.name.clone(),
+                    scopes: key.scopes.clone(),
+                    limits: key.limits(&limits.defaults),
+                };
+                (key.sha256.clone(), client)
+            })
+            .collect();
+
+        let enabled = !config.disabled;
+        if enabled {
+            println!("API key authentication enabled with {} keys", keys.len());
+        } else {
+            println!("WARNING: API key authentication is disabled by AUTH_DISABLED; every request is served as an anonymous admin");
+        }
+        KeyStore { keys, enabled, default_limits: limits.defaults }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The client a presented key belongs to
+    pub fn authenticate(&self, key: &str) -> Option<&ApiClient> {
+        self.keys.get(&hash_key(key))
+    }
+}
+
+/// Hex-encoded SHA-256 of an API key, the form keys are configured in
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// State of the authentication middleware for one group of routes
+#[derive(Clone)]
+pub struct ScopeGuard {
+    pub keys: Arc<KeyStore>,
+    pub scope: ApiScope,
+}
+
+/// Rejects requests without a valid API key for the guarded scope, and
+/// attaches the `ApiClient` to those it lets through
+pub async fn require_scope(State(guard): State<ScopeGuard>, mut request: Request, next: Next) -> Result<Response> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let client = if guard.keys.is_enabled() {
+        let client = presented_key(&request)
+            .ok_or_else(|| {
+                println!("Rejected {} {}: no API key", method, path);
+                AppError::Unauthorized("An API key is required".to_string())
+            })?
+            .and_then(|key| guard.keys.authenticate(key))
+            .ok_or_else(|| {
+                println!("Rejected {} {}: unknown API key", method, path);
+                AppError::Unauthorized("Invalid API key".to_string())
+            })?;
+        if !client.has_scope(guard.scope) {
+            println!("Rejected {} {} for key '{}': missing scope {}", method, path, client.name, guard.scope);
+            return Err(AppError::Forbidden(format!("The API key lacks the {} scope", guard.scope)));
+        }
+        client.clone()
+    } else {
+        ApiClient::anonymous(guard.keys.default_limits)
+    };
+
+    println!("{} {} by key '{}'", method, path, client.name);
+    let name = client.name.clone();
+    request.extensions_mut().insert(client);
+    let response = next.run(request).await;
+    println!("{} {} by key '{}' finished with {}", method, path, name, response.status());
+    Ok(response)
+}
+
+/// The key from `Authorization: Bearer` or `X-API-Key`. The outer `None` means
+/// no key was sent; the inner one a header that is not valid text.
+fn presented_key(request: &Request) -> Option<Option<&str>> {
+    if let Some(value) = request.headers().get(AUTHORIZATION) {
+        let value = value.to_str().ok();
+        return Some(value.and_then(|value| {
+            let (scheme, key) = value.trim().split_once(' ')?;
+            scheme.eq_ignore_ascii_case("bearer").then(|| key.trim())
+        }));
+    }
+    request.headers().get(API_KEY_HEADER).map(|value| value.to_str().ok().map(str::trim))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiKeyConfig;
+
+    fn limits() -> LimitsConfig {
+        LimitsConfig { defaults: ClientLimits { requests_per_minute: 60, burst: 10, daily_tokens: 0, monthly_tokens: 0 } }
+    }
+
+    fn key(name: &str, secret: &str, scopes: Vec<ApiScope>) -> ApiKeyConfig {
+        ApiKeyConfig {
+            name: name.to_string(),
+            sha256: hash_key(secret),
+            scopes,
+            requests_per_minute: Some(5),
+            burst: None,
+            daily_tokens: None,
+            monthly_tokens: None,
+        }
+    }
+
+    #[test]
+    fn authenticates_configured_keys_by_hash() {
+        let config = AuthConfig { keys: vec![key("ci", "secret", vec![ApiScope::Scrape])], disabled: false };
+        let store = KeyStore::new(&config, &limits());
+        assert!(store.is_enabled());
+
+        let client = store.authenticate("secret").unwrap();
+        assert_eq!(client.name, "ci");
+        assert_eq!(client.limits.requests_per_minute, 5);
+        assert_eq!(client.limits.burst, 10);
+        assert!(store.authenticate("other").is_none());
+    }
+
+    #[test]
+    fn disabling_auth_must_be_explicit() {
+        let store = KeyStore::new(&AuthConfig { keys: Vec::new(), disabled: false }, &limits());
+        assert!(store.is_enabled());
+        assert!(store.authenticate("").is_none());
+
+        let store = KeyStore::new(&AuthConfig { keys: Vec::new(), disabled: true }, &limits());
+        assert!(!store.is_enabled());
+    }
+
+    #[test]
+    fn admin_scope_grants_everything() {
+        let admin = ApiClient { name: "ops".to_string(), scopes: vec![ApiScope::Admin], limits: limits().defaults };
+        let scraper = ApiClient { name: "ci".to_string(), scopes: vec![ApiScope::Scrape], limits: limits().defaults };
+        assert!(admin.has_scope(ApiScope::Extract));
+        assert!(scraper.has_scope(ApiScope::Scrape));
+        assert!(!scraper.has_scope(ApiScope::Extract));
+        assert!(!scraper.has_scope(ApiScope::Admin));
+    }
+}
@@ -0,0 +1,163 @@
+use async_trait::async_trait;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Duration;
+use super::{is_fresh, CacheStore, CachedResponse};
+
+/// In-memory LRU cache bounded by entry count and total size
+pub struct MemoryCache {
+    inner: Mutex<Inner>,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+struct Inner {
+    entries: LruCache<String, CachedResponse>,
+    bytes: usize,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize, max_bytes: usize, ttl: Duration) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        MemoryCache {
+            inner: Mutex::new(Inner {
+                entries: LruCache::new(capacity),
+                bytes: 0,
+            }),
+            max_bytes,
+            ttl,
+        }
+    }
+}
+
+impl Inner {
+    fn entry_size(key: &str, value: &CachedResponse) -> usize {
+        key.len() + value.size_bytes()
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(old) = self.entries.pop(key) {
+            self.bytes -= Self::entry_size(key, &old);
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryCache {
+    async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut inner = self.inner.lock().unwrap();
+        let cached = inner.entries.get(key)?.clone();
+        if !is_fresh(cached.timestamp, self.ttl) {
+            inner.remove(key);
+            return None;
+        }
+        Some(cached)
+    }
+
+    async fn put(&self, key: &str, value: CachedResponse) {
+        let size = Inner::entry_size(key, &value);
+        if size > self.max_bytes {
+            println!("Not caching {}: entry of {} bytes exceeds the cache size limit", key, size);
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(key);
+
+        // Evict least recently used entries until the new one fits
+        while inner.bytes + size > self.max_bytes || inner.entries.len() >= inner.entries.cap().get() {
+            match inner.entries.pop_lru() {
+                Some((old_key, old)) => inner.bytes -= Inner::entry_size(&old_key, &old),
+                None => break,
+            }
+        }
+
+        inner.entries.put(key.to_string(), value);
+        inner.bytes += size;
+    }
+
+    async fn remove(&self, key: &str) {
+        self.inner.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn entry(summary: &str) -> CachedResponse {
+        CachedResponse {
+            canonical_url: String::new(),
+            summary: summary.to_string(),
+            content_markdown: None,
+            word_count: 1,
+            upstream_status: None,
+            final_url: None,
+            extraction_strategy: None,
+            metadata: None,
+            structured_data: None,
+            usage: None,
+            timestamp: Utc::now(),
+        }
+    }
+
+    fn bytes(cache: &MemoryCache) -> usize {
+        cache.inner.lock().unwrap().bytes
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_entry_by_count() {
+        let cache = MemoryCache::new(2, usize::MAX, Duration::from_secs(60));
+        cache.put("a", entry("A")).await;
+        cache.put("b", entry("B")).await;
+
+        // Reading `a` makes `b` the least recently used entry
+        assert!(cache.get("a").await.is_some());
+        cache.put("c", entry("C")).await;
+
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("c").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_entries_by_size() {
+        let size = Inner::entry_size("a", &entry("xxxx"));
+        let cache = MemoryCache::new(10, size * 2, Duration::from_secs(60));
+        cache.put("a", entry("xxxx")).await;
+        cache.put("b", entry("yyyy")).await;
+        assert!(cache.get("a").await.is_some());
+
+        cache.put("c", entry("zzzz")).await;
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_none());
+        assert_eq!(bytes(&cache), size * 2);
+    }
+
+    #[tokio::test]
+    async fn replacing_an_entry_counts_only_the_new_size() {
+        let cache = MemoryCache::new(10, usize::MAX, Duration::from_secs(60));
+        cache.put("a", entry("short")).await;
+        cache.put("a", entry("a much longer summary")).await;
+        assert_eq!(bytes(&cache), Inner::entry_size("a", &entry("a much longer summary")));
+
+        cache.remove("a").await;
+        assert_eq!(bytes(&cache), 0);
+    }
+
+    #[tokio::test]
+    async fn skips_entries_larger_than_the_size_limit() {
+        let small = Inner::entry_size("a", &entry("x"));
+        let cache = MemoryCache::new(10, small * 3, Duration::from_secs(60));
+        cache.put("a", entry("x")).await;
+        cache.put("b", entry("y")).await;
+
+        cache.put("huge", entry(&"z".repeat(small * 3))).await;
+        assert!(cache.get("huge").await.is_none());
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("b").await.is_some());
+        assert_eq!(bytes(&cache), small * 2);
+    }
+}
@@ -0,0 +1,139 @@
+pub mod memory;
+pub mod sqlite;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use crate::config::{CacheBackend, CacheConfig};
+use crate::error::Result;
+use crate::limits::UsageStore;
+use crate::metadata::PageMetadata;
+use crate::scraper::ExtractionStrategy;
+use crate::structured::StructuredItem;
+use crate::usage::SummaryUsage;
+
+pub use memory::MemoryCache;
+pub use sqlite::SqliteCache;
+
+/// Structure to store cached responses
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    /// Canonical URL of the page; empty for entries written before it was recorded
+    #[serde(default)]
+    pub canonical_url: String,
+    pub summary: String,
+    #[serde(default)]
+    pub content_markdown: Option<String>,
+    pub word_count: usize,
+    #[serde(default)]
+    pub upstream_status: Option<u16>,
+    #[serde(default)]
+    pub final_url: Option<String>,
+    #[serde(default)]
+    pub extraction_strategy: Option<ExtractionStrategy>,
+    #[serde(default)]
+    pub metadata: Option<PageMetadata>,
+    #[serde(default)]
+    pub structured_data: Option<Vec<StructuredItem>>,
+    /// LLM tokens and cost of the summary
+    #[serde(default)]
+    pub usage: Option<SummaryUsage>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl CachedResponse {
+    /// Approximate number of bytes the entry occupies, used for size limits
+    pub fn size_bytes(&self) -> usize {
+        self.summary.len()
+            + self.content_markdown.as_ref().map_or(0, String::len)
+            + self.metadata.as_ref().map_or(0, json_len)
+            + self.structured_data.as_ref().map_or(0, json_len)
+            + std::mem::size_of::<Self>()
+    }
+}
+
+/// Length of a value serialized as JSON
+fn json_len<T: Serialize>(value: &T) -> usize {
+    serde_json::to_vec(value).map_or(0, |json| json.len())
+}
+
+/// Storage for summaries keyed by URL.
+///
+/// Stores apply the configured TTL themselves: `get` never returns an entry
+/// older than the TTL. Failures are logged and treated as cache misses so
+/// that a broken cache never fails a request.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    async fn get(&self, key: &str) -> Option<CachedResponse>;
+
+    async fn put(&self, key: &str, value: CachedResponse);
+
+    async fn remove(&self, key: &str);
+}
+
+/// The stores opened for the configured cache backend
+pub struct Stores {
+    pub cache: Arc<dyn CacheStore>,
+    /// Keeps the LLM token counts of API keys across restarts; the memory
+    /// backend has none
+    pub usage: Option<Arc<dyn UsageStore>>,
+}
+
+/// Builds the cache backend selected in the configuration
+pub fn build_store(config: &CacheConfig) -> Result<Stores> {
+    let stores = match config.backend {
+        CacheBackend::Memory => {
+            println!("Using in-memory cache (capacity={}, max_bytes={})", config.capacity, config.max_bytes);
+            Stores { cache: Arc::new(MemoryCache::new(config.capacity, config.max_bytes, config.ttl)), usage: None }
+        },
+        CacheBackend::Sqlite => {
+            println!("Using SQLite cache at {}", config.path.display());
+            let store = Arc::new(SqliteCache::open(&config.path, config.ttl)?);
+            Stores { cache: store.clone(), usage: Some(store) }
+        },
+    };
+    Ok(stores)
+}
+
+/// Whether an entry stored at `timestamp` is still within `ttl`
+pub(crate) fn is_fresh(timestamp: DateTime<Utc>, ttl: std::time::Duration) -> bool {
+    match chrono::Duration::from_std(ttl) {
+        Ok(ttl) => Utc::now() - timestamp < ttl,
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Map, Value};
+    use crate::structured::StructuredFormat;
+
+    #[test]
+    fn size_includes_metadata_and_structured_data() {
+        let mut entry = CachedResponse {
+            canonical_url: String::new(),
+            summary: "summary".to_string(),
+            content_markdown: None,
+            word_count: 1,
+            upstream_status: None,
+            final_url: None,
+            extraction_strategy: None,
+            metadata: None,
+            structured_data: None,
+            usage: None,
+            timestamp: Utc::now(),
+        };
+        let bare = entry.size_bytes();
+
+        entry.metadata = Some(PageMetadata { description: Some("d".repeat(1000)), ..PageMetadata::default() });
+        let with_metadata = entry.size_bytes();
+        assert!(with_metadata >= bare + 1000);
+
+        let mut properties = Map::new();
+        properties.insert("articleBody".to_string(), Value::String("b".repeat(5000)));
+        entry.structured_data = Some(vec![StructuredItem { format: StructuredFormat::JsonLd, types: vec!["Article".to_string()], properties }]);
+        assert!(entry.size_bytes() >= with_metadata + 5000);
+    }
+}
@@ -0,0 +1,228 @@
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use chrono::{NaiveDate, Utc};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::error::{AppError, Result};
+use crate::limits::{DailyUsage, UsageStore};
+use super::{is_fresh, CacheStore, CachedResponse};
+
+// Expired rows are purged once every this many writes
+const PURGE_INTERVAL: u64 = 100;
+
+/// Cache persisted in an embedded SQLite database so it survives restarts.
+/// The same database keeps the LLM token counts of API keys.
+///
+/// SQLite calls block, so they run on tokio's blocking thread pool instead of
+/// the async workers.
+pub struct SqliteCache {
+    conn: Arc<Mutex<Connection>>,
+    ttl: Duration,
+    writes: AtomicU64,
+}
+
+impl SqliteCache {
+    pub fn open(path: &Path, ttl: Duration) -> Result<Self> {
+        let conn = Connection::open(path)
+            .map_err(|e| AppError::ConfigError(format!("Failed to open cache database {}: {}", path.display(), e)))?;
+
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS cache (
+                 key TEXT PRIMARY KEY,
+                 value TEXT NOT NULL,
+                 created_at INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS usage (
+                 client TEXT NOT NULL,
+                 day TEXT NOT NULL,
+                 tokens INTEGER NOT NULL,
+                 cost_usd REAL NOT NULL,
+                 PRIMARY KEY (client, day)
+             );",
+        ).map_err(|e| AppError::ConfigError(format!("Failed to initialize cache database: {}", e)))?;
+
+        purge_expired(&conn, ttl);
+        Ok(SqliteCache {
+            conn: Arc::new(Mutex::new(conn)),
+            ttl,
+            writes: AtomicU64::new(0),
+        })
+    }
+
+    /// Runs `operation` on the connection from a blocking thread
+    async fn blocking<T: Send + 'static>(&self, operation: impl FnOnce(&Connection) -> T + Send + 'static) -> Option<T> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || operation(&conn.lock().unwrap()))
+            .await
+            .map_err(|e| println!("Cache task failed: {}", e))
+            .ok()
+    }
+}
+
+fn purge_expired(conn: &Connection, ttl: Duration) {
+    let cutoff = Utc::now().timestamp() - ttl.as_secs() as i64;
+    match conn.execute("DELETE FROM cache WHERE created_at < ?1", params![cutoff]) {
+        Ok(0) => {},
+        Ok(purged) => println!("Purged {} expired cache entries", purged),
+        Err(e) => println!("Failed to purge expired cache entries: {}", e),
+    }
+}
+
+fn delete(conn: &Connection, key: &str) {
+    if let Err(e) = conn.execute("DELETE FROM cache WHERE key = ?1", params![key]) {
+        println!("Cache delete failed for {}: {}", key, e);
+    }
+}
+
+/// Reads a fresh entry, deleting it when it is expired or unreadable
+fn read(conn: &Connection, key: &str, ttl: Duration) -> Option<CachedResponse> {
+    let value: Option<String> = conn
+        .query_row("SELECT value FROM cache WHERE key = ?1", params![key], |row| row.get(0))
+        .optional()
+        .unwrap_or_else(|e| {
+            println!("Cache read failed for {}: {}", key, e);
+            None
+        });
+
+    let cached: CachedResponse = match serde_json::from_str(&value?) {
+        Ok(cached) => cached,
+        Err(e) => {
+            println!("Discarding unreadable cache entry for {}: {}", key, e);
+            delete(conn, key);
+            return None;
+        }
+    };
+
+    if !is_fresh(cached.timestamp, ttl) {
+        delete(conn, key);
+        return None;
+    }
+    Some(cached)
+}
+
+#[async_trait]
+impl CacheStore for SqliteCache {
+    async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let key = key.to_string();
+        let ttl = self.ttl;
+        self.blocking(move |conn| read(conn, &key, ttl)).await.flatten()
+    }
+
+    async fn put(&self, key: &str, value: CachedResponse) {
+        let json = match serde_json::to_string(&value) {
+            Ok(json) => json,
+            Err(e) => {
+                println!("Failed to serialize cache entry for {}: {}", key, e);
+                return;
+            }
+        };
+
+        let purge = (self.writes.fetch_add(1, Ordering::Relaxed) + 1).is_multiple_of(PURGE_INTERVAL);
+        let key = key.to_string();
+        let ttl = self.ttl;
+        self.blocking(move |conn| {
+            if let Err(e) = conn.execute(
+                "INSERT OR REPLACE INTO cache (key, value, created_at) VALUES (?1, ?2, ?3)",
+                params![key, json, value.timestamp.timestamp()],
+            ) {
+                println!("Cache write failed for {}: {}", key, e);
+                return;
+            }
+            if purge {
+                purge_expired(conn, ttl);
+            }
+        }).await;
+    }
+
+    async fn remove(&self, key: &str) {
+        let key = key.to_string();
+        self.blocking(move |conn| delete(conn, &key)).await;
+    }
+}
+
+impl UsageStore for SqliteCache {
+    fn add(&self, usage: &DailyUsage) -> Result<()> {
+        self.conn.lock().unwrap()
+            .execute(
+                "INSERT INTO usage (client, day, tokens, cost_usd) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (client, day) DO UPDATE SET
+                     tokens = tokens + excluded.tokens,
+                     cost_usd = cost_usd + excluded.cost_usd",
+                params![usage.client, usage.day.to_string(), usage.tokens as i64, usage.cost_usd],
+            )
+            .map(|_| ())
+            .map_err(|e| AppError::InternalError(format!("Failed to save token counts: {}", e)))
+    }
+
+    fn load(&self, since: NaiveDate) -> Result<Vec<DailyUsage>> {
+        let failed = |e: rusqlite::Error| AppError::InternalError(format!("Failed to read token counts: {}", e));
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare("SELECT client, day, tokens, cost_usd FROM usage WHERE day >= ?1")
+            .map_err(failed)?;
+        let rows = statement
+            .query_map(params![since.to_string()], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?, row.get::<_, f64>(3)?))
+            })
+            .map_err(failed)?;
+
+        let mut usage = Vec::new();
+        for row in rows {
+            let (client, day, tokens, cost_usd) = row.map_err(failed)?;
+            match day.parse() {
+                Ok(day) => usage.push(DailyUsage { client, day, tokens: tokens as u64, cost_usd }),
+                Err(_) => println!("Skipping token counts of key '{}' with an unreadable day {:?}", client, day),
+            }
+        }
+        Ok(usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(summary: &str) -> CachedResponse {
+        CachedResponse {
+            canonical_url: "https://example.com/".to_string(),
+            summary: summary.to_string(),
+            content_markdown: None,
+            word_count: 2,
+            upstream_status: Some(200),
+            final_url: None,
+            extraction_strategy: None,
+            metadata: None,
+            structured_data: None,
+            usage: None,
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_reads_and_removes_entries() {
+        let path = std::env::temp_dir().join(format!("cache-{}.db", uuid::Uuid::new_v4()));
+        let cache = SqliteCache::open(&path, Duration::from_secs(60)).unwrap();
+
+        cache.put("key", entry("A summary")).await;
+        assert_eq!(cache.get("key").await.unwrap().summary, "A summary");
+
+        cache.remove("key").await;
+        assert!(cache.get("key").await.is_none());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_not_returned() {
+        let path = std::env::temp_dir().join(format!("cache-{}.db", uuid::Uuid::new_v4()));
+        let cache = SqliteCache::open(&path, Duration::from_secs(60)).unwrap();
+
+        let mut stale = entry("Old summary");
+        stale.timestamp = Utc::now() - chrono::Duration::minutes(5);
+        cache.put("key", stale).await;
+        assert!(cache.get("key").await.is_none());
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,119 @@
+use reqwest::Url;
+use crate::error::{AppError, Result};
+
+// Query parameters that only track where a visitor came from
+const TRACKING_PARAMS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "mc_cid", "mc_eid", "igshid", "yclid",
+];
+
+/// Returns the canonical form of a URL used for cache keys and deduplication.
+///
+/// The host is lowercased, default ports and fragments are dropped, tracking
+/// parameters (`utm_*`, `fbclid`, `gclid`, ...) are removed and the remaining
+/// query parameters are sorted.
+pub fn canonicalize_url(raw: &str) -> Result<String> {
+    let url = Url::parse(raw.trim())
+        .map_err(|e| AppError::InvalidUrl(format!("Invalid URL '{}': {}", raw, e)))?;
+    Ok(canonicalize(url).to_string())
+}
+
+/// Resolves the `<link rel="canonical">` target declared by a page against
+/// the URL the page was fetched from.
+///
+/// The declared URL is only honored when it stays on the same site (ignoring a
+/// leading `www.`), so a page cannot claim another site's cache entry.
+pub fn resolve_declared_canonical(page_url: &str, declared: &str) -> Option<String> {
+    let base = Url::parse(page_url).ok()?;
+    let declared = base.join(declared.trim()).ok()?;
+
+    if !matches!(declared.scheme(), "http" | "https") {
+        return None;
+    }
+
+    let site = |url: &Url| url.host_str().map(|host| host.trim_start_matches("www.").to_ascii_lowercase());
+    if site(&base) != site(&declared) {
+        println!("Ignoring cross-site canonical link {} on {}", declared, page_url);
+        return None;
+    }
+
+    Some(canonicalize(declared).to_string())
+}
+
+fn canonicalize(mut url: Url) -> Url {
+    // Parsing already lowercases the host and drops default ports
+    url.set_fragment(None);
+
+    let mut params: Vec<(String, String)> = url.query_pairs()
+        .filter(|(key, _)| !is_tracking_param(key))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    params.sort();
+
+    if params.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(&params);
+    }
+
+    url
+}
+
+fn is_tracking_param(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    key.starts_with("utm_") || TRACKING_PARAMS.contains(&key.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowercases_host_and_drops_default_port_and_fragment() {
+        assert_eq!(
+            canonicalize_url("  HTTPS://Example.COM:443/Path#section ").unwrap(),
+            "https://example.com/Path"
+        );
+        assert_eq!(canonicalize_url("http://example.com:8080/").unwrap(), "http://example.com:8080/");
+    }
+
+    #[test]
+    fn removes_tracking_parameters_and_sorts_the_rest() {
+        assert_eq!(
+            canonicalize_url("https://example.com/a?utm_source=x&b=2&FBCLID=1&a=1&UTM_Medium=y&gclid=z").unwrap(),
+            "https://example.com/a?a=1&b=2"
+        );
+        assert_eq!(canonicalize_url("https://example.com/a?utm_source=x").unwrap(), "https://example.com/a");
+    }
+
+    #[test]
+    fn equivalent_urls_share_a_canonical_form() {
+        assert_eq!(
+            canonicalize_url("https://example.com/a?b=2&a=1#top").unwrap(),
+            canonicalize_url("https://EXAMPLE.com/a?a=1&b=2&utm_campaign=launch").unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        assert!(matches!(canonicalize_url("not a url"), Err(AppError::InvalidUrl(_))));
+    }
+
+    #[test]
+    fn resolves_relative_declared_canonical_on_the_same_site() {
+        assert_eq!(
+            resolve_declared_canonical("https://www.example.com/a/b?utm_source=x", "/article?id=1#c").as_deref(),
+            Some("https://www.example.com/article?id=1")
+        );
+        assert_eq!(
+            resolve_declared_canonical("https://www.example.com/a", "https://example.com/a").as_deref(),
+            Some("https://example.com/a")
+        );
+    }
+
+    #[test]
+    fn ignores_cross_site_and_non_http_canonicals() {
+        assert_eq!(resolve_declared_canonical("https://example.com/a", "https://other.com/a"), None);
+        assert_eq!(resolve_declared_canonical("https://example.com/a", "ftp://example.com/a"), None);
+        assert_eq!(resolve_declared_canonical("not a url", "/a"), None);
+    }
+}
@@ -0,0 +1,219 @@
+/// Splits extracted page text into chunks that fit the model context window.
+///
+/// Text is split on paragraph boundaries first, then on sentence boundaries,
+/// and only as a last resort on whitespace inside an over-long sentence. Each
+/// chunk after the first starts with up to `overlap` characters taken from the
+/// end of the previous chunk so that context is not lost at the seams. Both
+/// limits count characters, not bytes.
+pub fn chunk_text(text: &str, max_chars: usize, overlap: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let text = text.trim();
+    if text.is_empty() {
+        return Vec::new();
+    }
+    if char_len(text) <= max_chars {
+        return vec![text.to_string()];
+    }
+
+    let segments = split_segments(text, max_chars);
+
+    let mut chunks = Vec::new();
+    let mut current: Vec<Segment> = Vec::new();
+    let mut current_len = 0;
+
+    for segment in segments {
+        let added_len = char_len(segment.text) + 2;
+        if current_len + added_len > max_chars && !current.is_empty() {
+            chunks.push(join_segments(&current));
+
+            // Carry the tail of the previous chunk over as overlap
+            let mut tail = Vec::new();
+            let mut tail_len = 0;
+            for prev in current.iter().rev() {
+                let prev_len = char_len(prev.text) + 2;
+                if tail_len + prev_len > overlap || tail_len + prev_len + added_len > max_chars {
+                    break;
+                }
+                tail_len += prev_len;
+                tail.push(*prev);
+            }
+            tail.reverse();
+
+            current = tail;
+            current_len = tail_len;
+        }
+
+        current.push(segment);
+        current_len += added_len;
+    }
+
+    if !current.is_empty() {
+        chunks.push(join_segments(&current));
+    }
+
+    chunks
+}
+
+/// A piece of text no longer than the chunk size, remembering whether it ended a paragraph
+#[derive(Clone, Copy)]
+struct Segment<'a> {
+    text: &'a str,
+    paragraph_end: bool,
+}
+
+fn split_segments(text: &str, max_chars: usize) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        if char_len(paragraph) <= max_chars {
+            segments.push(Segment { text: paragraph, paragraph_end: true });
+            continue;
+        }
+
+        let pieces: Vec<&str> = split_sentences(paragraph)
+            .into_iter()
+            .flat_map(|sentence| split_hard(sentence, max_chars))
+            .collect();
+
+        let last = pieces.len().saturating_sub(1);
+        for (i, piece) in pieces.into_iter().enumerate() {
+            segments.push(Segment { text: piece, paragraph_end: i == last });
+        }
+    }
+
+    segments
+}
+
+fn join_segments(segments: &[Segment]) -> String {
+    let mut result = String::new();
+    for (i, segment) in segments.iter().enumerate() {
+        if i > 0 {
+            result.push_str(if segments[i - 1].paragraph_end { "\n\n" } else { " " });
+        }
+        result.push_str(segment.text);
+    }
+    result
+}
+
+/// Splits a paragraph after sentence-ending punctuation followed by whitespace
+fn split_sentences(paragraph: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = paragraph.char_indices().peekable();
+
+    while let Some((_, c)) = chars.next() {
+        if matches!(c, '.' | '!' | '?' | '。' | '！' | '？')
+            && let Some(&(next_i, next_c)) = chars.peek()
+            && next_c.is_whitespace()
+        {
+            let sentence = paragraph[start..next_i].trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence);
+            }
+            start = next_i;
+        }
+    }
+
+    let rest = paragraph[start..].trim();
+    if !rest.is_empty() {
+        sentences.push(rest);
+    }
+
+    sentences
+}
+
+/// Splits text longer than `max_chars` on the last whitespace before the limit,
+/// or after `max_chars` characters when there is no whitespace at all
+fn split_hard(text: &str, max_chars: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+
+    // Byte offset of the first character past the limit, while there is one
+    while let Some((limit, _)) = rest.char_indices().nth(max_chars) {
+        let cut = match rest[..limit].rfind(char::is_whitespace) {
+            Some(pos) if pos > 0 => pos,
+            _ => limit,
+        };
+
+        pieces.push(rest[..cut].trim());
+        rest = rest[cut..].trim_start();
+    }
+
+    if !rest.is_empty() {
+        pieces.push(rest);
+    }
+
+    pieces
+}
+
+/// Length of `text` in characters, the unit of the chunking limits
+pub fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        assert_eq!(chunk_text("  Hello world.  ", 100, 10), vec!["Hello world."]);
+        assert!(chunk_text("   ", 100, 10).is_empty());
+    }
+
+    #[test]
+    fn splits_on_paragraphs_within_limit() {
+        let text = "First paragraph here.\n\nSecond paragraph here.\n\nThird paragraph here.";
+        let chunks = chunk_text(text, 50, 0);
+        assert_eq!(chunks, vec!["First paragraph here.\n\nSecond paragraph here.", "Third paragraph here."]);
+    }
+
+    #[test]
+    fn splits_long_paragraph_on_sentences() {
+        let text = "One sentence here. Another sentence there. A third one follows.";
+        let chunks = chunk_text(text, 45, 0);
+        assert_eq!(chunks, vec!["One sentence here. Another sentence there.", "A third one follows."]);
+    }
+
+    #[test]
+    fn carries_overlap_from_previous_chunk() {
+        let text = "Alpha para.\n\nBeta para.\n\nGamma para.";
+        let chunks = chunk_text(text, 26, 13);
+        assert_eq!(chunks, vec!["Alpha para.\n\nBeta para.", "Beta para.\n\nGamma para."]);
+    }
+
+    #[test]
+    fn limits_count_characters_not_bytes() {
+        // 30 three-byte characters fit a 30-character chunk
+        let text = "日".repeat(30);
+        assert_eq!(chunk_text(&text, 30, 0), vec![text.clone()]);
+
+        // Without whitespace the text is cut after exactly `max_chars` characters
+        let chunks = chunk_text(&"日".repeat(70), 30, 0);
+        assert_eq!(chunks.iter().map(|chunk| char_len(chunk)).collect::<Vec<_>>(), vec![30, 30, 10]);
+    }
+
+    #[test]
+    fn overlap_is_measured_in_characters() {
+        // Each paragraph is 10 characters but 30 bytes; 12 characters of overlap carry one over
+        let paragraph = |c: &str| c.repeat(10);
+        let text = [paragraph("あ"), paragraph("い"), paragraph("う")].join("\n\n");
+        let chunks = chunk_text(&text, 24, 12);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1], format!("{}\n\n{}", paragraph("い"), paragraph("う")));
+    }
+
+    #[test]
+    fn hard_split_prefers_whitespace() {
+        let chunks = chunk_text("aaaa bbbb cccc", 9, 0);
+        assert_eq!(chunks, vec!["aaaa", "bbbb cccc"]);
+    }
+
+    #[test]
+    fn every_chunk_respects_the_limit() {
+        let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(200);
+        for chunk in chunk_text(&text, 300, 50) {
+            assert!(char_len(&chunk) <= 300, "chunk of {} chars", char_len(&chunk));
+        }
+    }
+}
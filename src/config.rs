@@ -1,33 +1,704 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fmt;
 use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use crate::error::{AppError, Result};
 
+/// The LLM backends the service can talk to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LlmProviderKind {
+    OpenRouter,
+    OpenAi,
+    Anthropic,
+    Local,
+}
+
+impl FromStr for LlmProviderKind {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "openrouter" => Ok(LlmProviderKind::OpenRouter),
+            "openai" => Ok(LlmProviderKind::OpenAi),
+            "anthropic" => Ok(LlmProviderKind::Anthropic),
+            "local" | "ollama" | "llamacpp" | "llama.cpp" => Ok(LlmProviderKind::Local),
+            other => Err(AppError::ConfigError(format!("Unknown LLM provider: {}", other))),
+        }
+    }
+}
+
+impl fmt::Display for LlmProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LlmProviderKind::OpenRouter => "openrouter",
+            LlmProviderKind::OpenAi => "openai",
+            LlmProviderKind::Anthropic => "anthropic",
+            LlmProviderKind::Local => "local",
+        };
+        f.write_str(name)
+    }
+}
+
+impl LlmProviderKind {
+    fn default_base_url(self) -> &'static str {
+        match self {
+            LlmProviderKind::OpenRouter => "https://openrouter.ai/api/v1",
+            LlmProviderKind::OpenAi => "https://api.openai.com/v1",
+            LlmProviderKind::Anthropic => "https://api.anthropic.com/v1",
+            LlmProviderKind::Local => "http://localhost:11434/v1",
+        }
+    }
+
+    fn default_model(self) -> &'static str {
+        match self {
+            LlmProviderKind::OpenRouter => "google/gemini-2.0-flash-exp:free",
+            LlmProviderKind::OpenAi => "gpt-4o-mini",
+            LlmProviderKind::Anthropic => "claude-3-5-haiku-latest",
+            LlmProviderKind::Local => "llama3.1",
+        }
+    }
+
+    fn default_max_tokens(self) -> u32 {
+        match self {
+            LlmProviderKind::OpenRouter => 80000,
+            _ => 4096,
+        }
+    }
+
+    /// Whether the provider refuses requests without an API key
+    pub fn requires_api_key(self) -> bool {
+        self.api_key_var().is_some()
+    }
+
+    /// Vendor-specific environment variable holding the API key
+    fn api_key_var(self) -> Option<&'static str> {
+        match self {
+            LlmProviderKind::OpenRouter => Some("OPENROUTER_API_KEY"),
+            LlmProviderKind::OpenAi => Some("OPENAI_API_KEY"),
+            LlmProviderKind::Anthropic => Some("ANTHROPIC_API_KEY"),
+            LlmProviderKind::Local => None,
+        }
+    }
+}
+
+/// Settings for the LLM provider used to summarize content
+#[derive(Clone)]
+pub struct LlmConfig {
+    pub provider: LlmProviderKind,
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+    pub max_tokens: u32,
+    pub temperature: f32,
+}
+
+impl LlmConfig {
+    fn load() -> Result<Self> {
+        let provider = match env::var("LLM_PROVIDER") {
+            Ok(value) => value.parse::<LlmProviderKind>()?,
+            Err(_) => LlmProviderKind::OpenRouter,
+        };
+
+        // LLM_API_KEY takes precedence over the vendor-specific variable
+        let api_key = env::var("LLM_API_KEY").ok()
+            .or_else(|| provider.api_key_var().and_then(|var| env::var(var).ok()))
+            .filter(|key| !key.is_empty());
+
+        let base_url = env::var("LLM_BASE_URL").unwrap_or_else(|_| provider.default_base_url().to_string());
+        let model = env::var("LLM_MODEL").unwrap_or_else(|_| provider.default_model().to_string());
+        let max_tokens = parse_var("LLM_MAX_TOKENS", provider.default_max_tokens())?;
+        let temperature = parse_var("LLM_TEMPERATURE", 0.1)?;
+
+        Ok(LlmConfig {
+            provider,
+            base_url,
+            api_key,
+            model,
+            max_tokens,
+            temperature,
+        })
+    }
+}
+
+/// USD prices of a model per million tokens
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// Model prices used to compute what summaries cost
+#[derive(Clone, Default)]
+pub struct PricingConfig {
+    /// Prices keyed by lowercased model name
+    pub prices: HashMap<String, ModelPrice>,
+}
+
+impl PricingConfig {
+    fn load() -> Result<Self> {
+        let mut prices = HashMap::new();
+
+        // A JSON object mapping model names to {"prompt", "completion"} prices
+        if let Ok(path) = env::var("LLM_PRICES_FILE") {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| AppError::ConfigError(format!("Failed to read LLM_PRICES_FILE {}: {}", path, e)))?;
+            let file_prices: HashMap<String, ModelPrice> = serde_json::from_str(&content)
+                .map_err(|e| AppError::ConfigError(format!("Invalid LLM_PRICES_FILE {}: {}", path, e)))?;
+            prices.extend(file_prices);
+        }
+
+        // Comma-separated `model=prompt/completion` entries; model names may contain `/` and `:`
+        if let Ok(value) = env::var("LLM_PRICES") {
+            for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+                let invalid = || AppError::ConfigError(format!(
+                    "Invalid LLM_PRICES entry '{}': expected model=prompt/completion", entry,
+                ));
+                let (model, price) = entry.rsplit_once('=').ok_or_else(invalid)?;
+                let (prompt, completion) = price.split_once('/').ok_or_else(invalid)?;
+                let price = ModelPrice {
+                    prompt: prompt.trim().parse().map_err(|_| invalid())?,
+                    completion: completion.trim().parse().map_err(|_| invalid())?,
+                };
+                prices.insert(model.trim().to_string(), price);
+            }
+        }
+
+        for (model, price) in &prices {
+            if !(price.prompt >= 0.0 && price.completion >= 0.0) {
+                return Err(AppError::ConfigError(format!("Prices for model '{}' must not be negative", model)));
+            }
+        }
+
+        Ok(PricingConfig {
+            prices: prices.into_iter().map(|(model, price)| (model.to_ascii_lowercase(), price)).collect(),
+        })
+    }
+
+    /// The price of a model. Dated snapshots such as `gpt-4o-mini-2024-07-18`
+    /// fall back to the price of the undated name; other unknown models have none.
+    pub fn price(&self, model: &str) -> Option<ModelPrice> {
+        let model = model.to_ascii_lowercase();
+        self.prices.get(&model)
+            .or_else(|| strip_date_suffix(&model).and_then(|undated| self.prices.get(undated)))
+            .copied()
+    }
+}
+
+/// The model name without a trailing `-YYYY-MM-DD` or `-YYYYMMDD` snapshot date
+fn strip_date_suffix(model: &str) -> Option<&str> {
+    let digits = |part: &str, len: usize| part.len() == len && part.bytes().all(|b| b.is_ascii_digit());
+    let (rest, last) = model.rsplit_once('-')?;
+    if digits(last, 8) {
+        return Some(rest);
+    }
+    let (rest, month) = rest.rsplit_once('-')?;
+    let (name, year) = rest.rsplit_once('-')?;
+    (digits(year, 4) && digits(month, 2) && digits(last, 2)).then_some(name)
+}
+
+/// Settings for map-reduce summarization of long pages
+#[derive(Clone)]
+pub struct SummarizerConfig {
+    /// Maximum number of characters sent to the model in one prompt
+    pub chunk_size: usize,
+    /// Characters repeated from the end of one chunk at the start of the next
+    pub chunk_overlap: usize,
+    /// Maximum number of chunk summaries requested in parallel
+    pub max_concurrency: usize,
+}
+
+impl SummarizerConfig {
+    fn load() -> Result<Self> {
+        let chunk_size = parse_var("CHUNK_SIZE_CHARS", 24_000usize)?;
+        let chunk_overlap = parse_var("CHUNK_OVERLAP_CHARS", 500usize)?;
+        let max_concurrency = parse_var("SUMMARY_CONCURRENCY", 4usize)?;
+
+        if chunk_size == 0 || chunk_overlap >= chunk_size {
+            return Err(AppError::ConfigError(
+                "CHUNK_SIZE_CHARS must be positive and larger than CHUNK_OVERLAP_CHARS".to_string(),
+            ));
+        }
+
+        Ok(SummarizerConfig {
+            chunk_size,
+            chunk_overlap,
+            max_concurrency: max_concurrency.max(1),
+        })
+    }
+}
+
+/// Settings for the asynchronous job API
+#[derive(Clone)]
+pub struct JobConfig {
+    /// Number of jobs processed concurrently; further jobs wait in the queue
+    pub workers: usize,
+    /// Maximum run time of a single job once it has started
+    pub timeout: Duration,
+    /// How long finished jobs remain available for polling
+    pub retention: Duration,
+}
+
+impl JobConfig {
+    fn load() -> Result<Self> {
+        let workers = parse_var("JOB_WORKERS", 4usize)?;
+        let timeout = parse_var("JOB_TIMEOUT_SECS", 600u64)?;
+        let retention = parse_var("JOB_RETENTION_SECS", 3600u64)?;
+
+        Ok(JobConfig {
+            workers: workers.max(1),
+            timeout: Duration::from_secs(timeout),
+            retention: Duration::from_secs(retention),
+        })
+    }
+}
+
+/// Limits for the batch scrape endpoint
+#[derive(Clone)]
+pub struct BatchConfig {
+    /// Concurrency used when the request does not specify one
+    pub default_concurrency: usize,
+    /// Upper bound on the concurrency a request may ask for
+    pub max_concurrency: usize,
+    /// Maximum number of URLs accepted in one batch
+    pub max_urls: usize,
+}
+
+impl BatchConfig {
+    fn load() -> Result<Self> {
+        let default_concurrency = parse_var("BATCH_CONCURRENCY", 4usize)?;
+        let max_concurrency = parse_var("BATCH_MAX_CONCURRENCY", 16usize)?;
+        let max_urls = parse_var("BATCH_MAX_URLS", 500usize)?;
+
+        Ok(BatchConfig {
+            default_concurrency: default_concurrency.max(1),
+            max_concurrency: max_concurrency.max(1),
+            max_urls,
+        })
+    }
+}
+
+/// Settings for feed digests
+#[derive(Clone)]
+pub struct FeedConfig {
+    /// Entries summarized when the request does not say how many
+    pub default_items: usize,
+    /// Upper bound on the entries a request may ask for
+    pub max_items: usize,
+    /// Entries summarized at the same time
+    pub concurrency: usize,
+}
+
+impl FeedConfig {
+    fn load() -> Result<Self> {
+        let default_items = parse_var("FEED_DEFAULT_ITEMS", 10usize)?;
+        let max_items = parse_var("FEED_MAX_ITEMS", 50usize)?;
+        let concurrency = parse_var("FEED_CONCURRENCY", 4usize)?;
+
+        Ok(FeedConfig {
+            default_items: default_items.max(1),
+            max_items: max_items.max(1),
+            concurrency: concurrency.max(1),
+        })
+    }
+}
+
+/// Where cached summaries are stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheBackend {
+    Memory,
+    Sqlite,
+}
+
+impl FromStr for CacheBackend {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "memory" => Ok(CacheBackend::Memory),
+            "sqlite" => Ok(CacheBackend::Sqlite),
+            other => Err(AppError::ConfigError(format!("Unknown cache backend: {}", other))),
+        }
+    }
+}
+
+/// Settings for the summary cache
+#[derive(Clone)]
+pub struct CacheConfig {
+    pub backend: CacheBackend,
+    /// How long a cached summary is served before the page is summarized again
+    pub ttl: Duration,
+    /// Maximum number of entries kept by the in-memory cache
+    pub capacity: usize,
+    /// Maximum total size of the in-memory cache
+    pub max_bytes: usize,
+    /// Database file used by the SQLite cache
+    pub path: PathBuf,
+}
+
+impl CacheConfig {
+    fn load() -> Result<Self> {
+        let backend = match env::var("CACHE_BACKEND") {
+            Ok(value) => value.parse::<CacheBackend>()?,
+            Err(_) => CacheBackend::Memory,
+        };
+        let ttl = parse_var("CACHE_TTL_SECS", 24 * 60 * 60u64)?;
+        let capacity = parse_var("CACHE_CAPACITY", 10_000usize)?;
+        let max_bytes = parse_var("CACHE_MAX_BYTES", 64 * 1024 * 1024usize)?;
+        let path = env::var("CACHE_PATH").unwrap_or_else(|_| "cache.sqlite3".to_string());
+
+        Ok(CacheConfig {
+            backend,
+            ttl: Duration::from_secs(ttl),
+            capacity,
+            max_bytes,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// Settings for fetching user-supplied URLs
+#[derive(Clone)]
+pub struct FetchConfig {
+    /// Non-public destinations that may still be fetched: IP addresses, CIDR
+    /// ranges, host names or `*.domain` wildcards
+    pub allowlist: Vec<String>,
+    /// Largest response body read from a fetched page
+    pub max_body_bytes: usize,
+}
+
+impl FetchConfig {
+    fn load() -> Result<Self> {
+        let allowlist = env::var("SSRF_ALLOWLIST")
+            .map(|value| value.split(',').map(|entry| entry.trim().to_string()).collect())
+            .unwrap_or_default();
+
+        let max_body_bytes = parse_var("MAX_FETCH_BYTES", 20 * 1024 * 1024usize)?.max(1);
+
+        Ok(FetchConfig { allowlist, max_body_bytes })
+    }
+}
+
+/// Settings for extracting the readable text of a page
+#[derive(Clone)]
+pub struct ExtractConfig {
+    /// CSS selector list for additional elements to drop, on top of the built-in noise selectors
+    pub extra_noise_selectors: Option<String>,
+    /// Largest request body accepted, which bounds supplied HTML and uploaded files
+    pub max_input_bytes: usize,
+}
+
+impl ExtractConfig {
+    fn load() -> Result<Self> {
+        let extra_noise_selectors = env::var("EXTRA_NOISE_SELECTORS")
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let max_input_bytes = parse_var("MAX_INPUT_BYTES", 10 * 1024 * 1024usize)?;
+
+        Ok(ExtractConfig { extra_noise_selectors, max_input_bytes })
+    }
+}
+
+/// What an API key may be used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    /// Summaries: `/api/scrape` and its variants, batches and jobs
+    Scrape,
+    /// `/api/extract`
+    Extract,
+    /// Every endpoint, including administrative ones
+    Admin,
+}
+
+impl FromStr for ApiScope {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "scrape" => Ok(ApiScope::Scrape),
+            "extract" => Ok(ApiScope::Extract),
+            "admin" => Ok(ApiScope::Admin),
+            other => Err(AppError::ConfigError(format!("Unknown API key scope: {}", other))),
+        }
+    }
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ApiScope::Scrape => "scrape",
+            ApiScope::Extract => "extract",
+            ApiScope::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A client API key, identified by name and stored only as its SHA-256 hash
+#[derive(Clone, Debug, Deserialize)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    /// Hex-encoded SHA-256 of the key
+    pub sha256: String,
+    pub scopes: Vec<ApiScope>,
+    /// Limits that differ from the defaults in `LimitsConfig`
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    #[serde(default)]
+    pub burst: Option<u32>,
+    #[serde(default)]
+    pub daily_tokens: Option<u64>,
+    #[serde(default)]
+    pub monthly_tokens: Option<u64>,
+}
+
+impl ApiKeyConfig {
+    /// The limits of this key, falling back to `defaults` where it sets none
+    pub fn limits(&self, defaults: &ClientLimits) -> ClientLimits {
+        ClientLimits {
+            requests_per_minute: self.requests_per_minute.unwrap_or(defaults.requests_per_minute),
+            burst: self.burst.unwrap_or(defaults.burst).max(1),
+            daily_tokens: self.daily_tokens.unwrap_or(defaults.daily_tokens),
+            monthly_tokens: self.monthly_tokens.unwrap_or(defaults.monthly_tokens),
+        }
+    }
+}
+
+/// API keys accepted by the HTTP API. At least one key is required unless
+/// authentication is explicitly turned off.
+#[derive(Clone)]
+pub struct AuthConfig {
+    pub keys: Vec<ApiKeyConfig>,
+    /// Serves every request without a key, as an anonymous admin
+    pub disabled: bool,
+}
+
+impl AuthConfig {
+    fn load() -> Result<Self> {
+        let mut keys = Vec::new();
+
+        // Inline keys as comma-separated `name:scope+scope:sha256` entries
+        if let Ok(value) = env::var("API_KEYS") {
+            for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+                let invalid = || AppError::ConfigError(format!(
+                    "Invalid API_KEYS entry '{}': expected name:scopes:sha256", entry.split(':').next().unwrap_or_default(),
+                ));
+                let mut parts = entry.split(':');
+                let (Some(name), Some(scopes), Some(sha256), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+                    return Err(invalid());
+                };
+                let scopes = scopes.split('+').map(str::parse).collect::<Result<Vec<ApiScope>>>()?;
+                keys.push(ApiKeyConfig {
+                    name: name.trim().to_string(),
+                    sha256: sha256.trim().to_string(),
+                    scopes,
+                    requests_per_minute: None,
+                    burst: None,
+                    daily_tokens: None,
+                    monthly_tokens: None,
+                });
+            }
+        }
+
+        // A JSON array of {"name", "sha256", "scopes"} objects, optionally with limits
+        if let Ok(path) = env::var("API_KEYS_FILE") {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| AppError::ConfigError(format!("Failed to read API_KEYS_FILE {}: {}", path, e)))?;
+            let file_keys: Vec<ApiKeyConfig> = serde_json::from_str(&content)
+                .map_err(|e| AppError::ConfigError(format!("Invalid API_KEYS_FILE {}: {}", path, e)))?;
+            keys.extend(file_keys);
+        }
+
+        let mut names = HashSet::new();
+        for key in &mut keys {
+            key.sha256 = key.sha256.trim().trim_start_matches("sha256:").to_ascii_lowercase();
+            if key.name.is_empty() {
+                return Err(AppError::ConfigError("API keys need a name".to_string()));
+            }
+            if key.sha256.len() != 64 || !key.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(AppError::ConfigError(format!("API key '{}' needs a hex-encoded SHA-256 hash", key.name)));
+            }
+            if key.scopes.is_empty() {
+                return Err(AppError::ConfigError(format!("API key '{}' has no scopes", key.name)));
+            }
+            if !names.insert(key.name.clone()) {
+                return Err(AppError::ConfigError(format!("Duplicate API key name '{}'", key.name)));
+            }
+        }
+
+        let disabled = parse_var("AUTH_DISABLED", false)?;
+        if keys.is_empty() && !disabled {
+            return Err(AppError::ConfigError(
+                "No API keys configured: set API_KEYS or API_KEYS_FILE, or AUTH_DISABLED=true to serve requests without keys".to_string(),
+            ));
+        }
+
+        Ok(AuthConfig { keys, disabled })
+    }
+}
+
+/// Request rate and LLM token limits of one client; zero turns a limit off
+#[derive(Clone, Copy, Debug)]
+pub struct ClientLimits {
+    /// Requests per minute, refilled continuously
+    pub requests_per_minute: u32,
+    /// Requests that may be sent back to back before the rate applies
+    pub burst: u32,
+    /// LLM tokens per UTC day
+    pub daily_tokens: u64,
+    /// LLM tokens per UTC calendar month
+    pub monthly_tokens: u64,
+}
+
+/// Limits applied to every API key that does not set its own
+#[derive(Clone)]
+pub struct LimitsConfig {
+    pub defaults: ClientLimits,
+}
+
+impl LimitsConfig {
+    fn load() -> Result<Self> {
+        let requests_per_minute = parse_var("RATE_LIMIT_PER_MINUTE", 60u32)?;
+        let burst = parse_var("RATE_LIMIT_BURST", 10u32)?;
+        let daily_tokens = parse_var("DAILY_TOKEN_QUOTA", 0u64)?;
+        let monthly_tokens = parse_var("MONTHLY_TOKEN_QUOTA", 0u64)?;
+
+        Ok(LimitsConfig {
+            defaults: ClientLimits {
+                requests_per_minute,
+                burst: burst.max(1),
+                daily_tokens,
+                monthly_tokens,
+            },
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub server_addr: SocketAddr,
-    pub openrouter_api_key: String,
+    /// Time limit of a scrape request, and of every URL of a batch
+    pub request_timeout: Duration,
+    pub llm: LlmConfig,
+    pub pricing: PricingConfig,
+    pub summarizer: SummarizerConfig,
+    pub jobs: JobConfig,
+    pub batch: BatchConfig,
+    pub feed: FeedConfig,
+    pub cache: CacheConfig,
+    pub fetch: FetchConfig,
+    pub extract: ExtractConfig,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
 }
 
 impl Config {
     pub fn load() -> Result<Self> {
         // Load environment variables from .env file if it exists
         dotenv::dotenv().ok();
-        
-        // Load OpenRouter API key
-        let openrouter_api_key = env::var("OPENROUTER_API_KEY")?;
-        
+
+        // Load LLM provider settings
+        let llm = LlmConfig::load()?;
+        let pricing = PricingConfig::load()?;
+        let summarizer = SummarizerConfig::load()?;
+        let jobs = JobConfig::load()?;
+        let batch = BatchConfig::load()?;
+        let feed = FeedConfig::load()?;
+        let cache = CacheConfig::load()?;
+        let fetch = FetchConfig::load()?;
+        let extract = ExtractConfig::load()?;
+        let auth = AuthConfig::load()?;
+        let limits = LimitsConfig::load()?;
+
         // Load server configuration with defaults
         let host = env::var("HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
         let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
         let port = port.parse::<u16>().map_err(|e| AppError::ConfigError(format!("Invalid port: {}", e)))?;
         let ip = IpAddr::from_str(&host).map_err(|e| AppError::ConfigError(format!("Invalid host address: {}", e)))?;
-        
+
         let server_addr = SocketAddr::new(ip, port);
-        
+        let request_timeout = Duration::from_secs(parse_var("REQUEST_TIMEOUT_SECS", 90u64)?.max(1));
+
         Ok(Config {
             server_addr,
-            openrouter_api_key,
+            request_timeout,
+            llm,
+            pricing,
+            summarizer,
+            jobs,
+            batch,
+            feed,
+            cache,
+            fetch,
+            extract,
+            auth,
+            limits,
         })
     }
-} 
\ No newline at end of file
+}
+
+/// Parses an optional environment variable, falling back to `default` when unset
+fn parse_var<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match env::var(name) {
+        Ok(value) => value.trim().parse::<T>()
+            .map_err(|e| AppError::ConfigError(format!("Invalid {}: {}", name, e))),
+        Err(_) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_provider_names_and_aliases() {
+        assert_eq!("OpenRouter".parse::<LlmProviderKind>().unwrap(), LlmProviderKind::OpenRouter);
+        assert_eq!(" openai ".parse::<LlmProviderKind>().unwrap(), LlmProviderKind::OpenAi);
+        assert_eq!("anthropic".parse::<LlmProviderKind>().unwrap(), LlmProviderKind::Anthropic);
+        for alias in ["local", "ollama", "llamacpp", "llama.cpp"] {
+            assert_eq!(alias.parse::<LlmProviderKind>().unwrap(), LlmProviderKind::Local);
+        }
+        assert!(matches!("gemini".parse::<LlmProviderKind>(), Err(AppError::ConfigError(_))));
+    }
+
+    #[test]
+    fn provider_names_round_trip() {
+        for kind in [LlmProviderKind::OpenRouter, LlmProviderKind::OpenAi, LlmProviderKind::Anthropic, LlmProviderKind::Local] {
+            assert_eq!(kind.to_string().parse::<LlmProviderKind>().unwrap(), kind);
+        }
+    }
+
+    #[test]
+    fn prices_dated_snapshots_by_their_undated_name_only() {
+        let price = |prompt| ModelPrice { prompt, completion: prompt * 4.0 };
+        let pricing = PricingConfig {
+            prices: [("gpt-4o".to_string(), price(2.5)), ("claude-3-5-haiku".to_string(), price(0.8))].into(),
+        };
+        let prompt_price = |model| pricing.price(model).map(|price| price.prompt);
+
+        assert_eq!(prompt_price("GPT-4o"), Some(2.5));
+        assert_eq!(prompt_price("gpt-4o-2024-08-06"), Some(2.5));
+        assert_eq!(prompt_price("claude-3-5-haiku-20241022"), Some(0.8));
+        // A different model that merely starts with a configured name has no price
+        assert_eq!(prompt_price("gpt-4o-mini-2024-07-18"), None);
+        assert_eq!(prompt_price("gpt-4o-mini"), None);
+        assert_eq!(prompt_price("gpt-4o-2024-0806"), None);
+    }
+
+    #[test]
+    fn only_local_provider_works_without_api_key() {
+        assert!(LlmProviderKind::OpenRouter.requires_api_key());
+        assert!(LlmProviderKind::OpenAi.requires_api_key());
+        assert!(LlmProviderKind::Anthropic.requires_api_key());
+        assert!(!LlmProviderKind::Local.requires_api_key());
+    }
+}
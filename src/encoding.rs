@@ -0,0 +1,314 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1252, X_USER_DEFINED};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+// The HTML spec only looks for <meta charset> in the start of the document
+const PRESCAN_BYTES: usize = 1024;
+
+/// How the character encoding of an HTML document was determined
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EncodingSource {
+    ByteOrderMark,
+    /// The charset parameter of the `Content-Type` header
+    Header,
+    /// A `<meta charset>` or `<meta http-equiv="Content-Type">` tag
+    Meta,
+    /// Guessed from the bytes, when nothing declares an encoding
+    Detected,
+}
+
+/// The character encoding an HTML document was decoded from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DetectedEncoding {
+    /// Canonical name of the encoding, e.g. `UTF-8` or `Shift_JIS`
+    pub name: String,
+    pub source: EncodingSource,
+}
+
+/// Decodes an HTML document, determining its encoding in the order of the HTML
+/// spec: byte order mark, `Content-Type` charset, `<meta>` prescan and finally
+/// detection from the content. The top-level domain of `page_url` helps the
+/// detection tell apart encodings that share byte patterns.
+pub fn decode_html(bytes: &[u8], content_type: Option<&str>, page_url: Option<&str>) -> (String, DetectedEncoding) {
+    let (encoding, source) = if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        (encoding, EncodingSource::ByteOrderMark)
+    } else if let Some(encoding) = content_type.and_then(header_charset) {
+        (encoding, EncodingSource::Header)
+    } else if let Some(encoding) = prescan_meta(&bytes[..bytes.len().min(PRESCAN_BYTES)]) {
+        (encoding, EncodingSource::Meta)
+    } else {
+        (detect(bytes, page_url), EncodingSource::Detected)
+    };
+
+    let (html, _, had_errors) = encoding.decode(bytes);
+    if had_errors {
+        println!("Invalid {} byte sequences replaced while decoding", encoding.name());
+    }
+    (html.into_owned(), DetectedEncoding { name: encoding.name().to_string(), source })
+}
+
+/// The encoding named by the charset parameter of a `Content-Type` header
+fn header_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches(['"', '\'']).as_bytes()))
+}
+
+fn detect(bytes: &[u8], page_url: Option<&str>) -> &'static Encoding {
+    let host = page_url
+        .and_then(|url| Url::parse(url).ok())
+        .and_then(|url| url.domain().map(str::to_ascii_lowercase));
+    let tld = host.as_deref().and_then(|host| host.rsplit('.').next());
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess(tld.map(str::as_bytes), true)
+}
+
+/// Looks for an encoding declared by a `<meta>` tag, following the prescan
+/// algorithm of the HTML spec. Comments and the attributes of other tags are
+/// skipped so that a `<meta>` inside them is not picked up.
+fn prescan_meta(bytes: &[u8]) -> Option<&'static Encoding> {
+    let mut position = 0;
+    while position < bytes.len() {
+        let rest = &bytes[position..];
+        if rest.starts_with(b"<!--") {
+            // The dashes of the opening `<!--` may close the comment, as in `<!-->`
+            position += 2 + find(&rest[2..], b"-->")? + 3;
+            continue;
+        }
+
+        if starts_with_ignore_case(rest, b"<meta") && rest.get(5).is_some_and(|&b| is_space(b) || b == b'/') {
+            position += 5;
+            if let Some(encoding) = meta_encoding(bytes, &mut position) {
+                return Some(encoding);
+            }
+        } else if rest.len() > 2 && rest[0] == b'<' && (rest[1].is_ascii_alphabetic()
+            || (rest[1] == b'/' && rest[2].is_ascii_alphabetic()))
+        {
+            // Any other tag: skip its name, then its attributes, whose values may contain `>`
+            position += rest.iter().position(|&b| is_space(b) || b == b'>')?;
+            while next_attribute(bytes, &mut position).is_some() {}
+        } else if rest.starts_with(b"<!") || rest.starts_with(b"</") || rest.starts_with(b"<?") {
+            position += rest.iter().position(|&b| b == b'>')?;
+        }
+        position += 1;
+    }
+    None
+}
+
+/// Reads the attributes of a `<meta>` tag and returns the encoding it declares, if any
+fn meta_encoding(bytes: &[u8], position: &mut usize) -> Option<&'static Encoding> {
+    let mut seen = Vec::new();
+    let mut got_pragma = false;
+    let mut need_pragma = None;
+    let mut charset = None;
+
+    while let Some((name, value)) = next_attribute(bytes, position) {
+        if seen.contains(&name) {
+            continue;
+        }
+        match name.as_str() {
+            "http-equiv" => got_pragma |= value.eq_ignore_ascii_case("content-type"),
+            "content" if charset.is_none() => {
+                if let Some(encoding) = content_charset(value.as_bytes()) {
+                    charset = Some(encoding);
+                    need_pragma = Some(true);
+                }
+            },
+            "charset" => {
+                charset = Encoding::for_label(value.as_bytes());
+                need_pragma = Some(false);
+            },
+            _ => {},
+        }
+        seen.push(name);
+    }
+
+    match need_pragma? {
+        true if !got_pragma => None,
+        // A document that could be read to find the tag cannot be UTF-16
+        _ => charset.map(|encoding| match encoding {
+            encoding if encoding == UTF_16BE || encoding == UTF_16LE => UTF_8,
+            encoding if encoding == X_USER_DEFINED => WINDOWS_1252,
+            encoding => encoding,
+        }),
+    }
+}
+
+/// Reads the next attribute of a tag as a lowercased name and its value.
+/// Returns `None` at the end of the tag or the input.
+fn next_attribute(bytes: &[u8], position: &mut usize) -> Option<(String, String)> {
+    let at = |position: usize| bytes.get(position).copied();
+
+    while at(*position).is_some_and(|b| is_space(b) || b == b'/') {
+        *position += 1;
+    }
+    if at(*position)? == b'>' {
+        return None;
+    }
+
+    let mut name = Vec::new();
+    loop {
+        let byte = at(*position)?;
+        match byte {
+            b'=' if !name.is_empty() => break,
+            b'/' | b'>' => return Some((lossy(&name), String::new())),
+            byte if is_space(byte) => {
+                while at(*position).is_some_and(is_space) {
+                    *position += 1;
+                }
+                if at(*position)? != b'=' {
+                    return Some((lossy(&name), String::new()));
+                }
+                break;
+            },
+            byte => name.push(byte.to_ascii_lowercase()),
+        }
+        *position += 1;
+    }
+
+    // Skip the `=` and any spaces after it
+    *position += 1;
+    while at(*position).is_some_and(is_space) {
+        *position += 1;
+    }
+
+    let mut value = Vec::new();
+    match at(*position)? {
+        quote @ (b'"' | b'\'') => {
+            *position += 1;
+            loop {
+                let byte = at(*position)?;
+                *position += 1;
+                if byte == quote {
+                    break;
+                }
+                value.push(byte.to_ascii_lowercase());
+            }
+        },
+        b'>' => {},
+        _ => {
+            while let Some(byte) = at(*position).filter(|&b| !is_space(b) && b != b'>') {
+                value.push(byte.to_ascii_lowercase());
+                *position += 1;
+            }
+            at(*position)?;
+        },
+    }
+    Some((lossy(&name), lossy(&value)))
+}
+
+/// The encoding named in the `content` of `<meta http-equiv="Content-Type">`,
+/// e.g. `text/html; charset=shift_jis`
+fn content_charset(content: &[u8]) -> Option<&'static Encoding> {
+    let mut rest = content;
+    loop {
+        let start = find(&rest.to_ascii_lowercase(), b"charset")? + 7;
+        rest = trim_spaces(&rest[start..]);
+        let Some(after) = rest.strip_prefix(b"=") else { continue };
+        rest = trim_spaces(after);
+
+        let label = match rest.first() {
+            Some(&quote @ (b'"' | b'\'')) => {
+                let end = rest[1..].iter().position(|&b| b == quote)?;
+                &rest[1..1 + end]
+            },
+            Some(_) => {
+                let end = rest.iter().position(|&b| is_space(b) || b == b';').unwrap_or(rest.len());
+                &rest[..end]
+            },
+            None => return None,
+        };
+        return Encoding::for_label(label);
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn starts_with_ignore_case(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes.len() >= prefix.len() && bytes[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn trim_spaces(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|&b| !is_space(b)).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b'\t' | b'\n' | b'\x0C' | b'\r' | b' ')
+}
+
+fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1251};
+
+    fn encoding_of(html: &[u8], content_type: Option<&str>) -> (String, EncodingSource) {
+        let (_, detected) = decode_html(html, content_type, None);
+        (detected.name, detected.source)
+    }
+
+    #[test]
+    fn byte_order_mark_wins_over_declarations() {
+        let html = b"\xEF\xBB\xBF<meta charset=\"windows-1251\"><p>hi</p>";
+        assert_eq!(encoding_of(html, Some("text/html; charset=iso-8859-2")), ("UTF-8".to_string(), EncodingSource::ByteOrderMark));
+    }
+
+    #[test]
+    fn header_charset_wins_over_meta() {
+        let html = b"<meta charset=\"windows-1251\"><p>hi</p>";
+        assert_eq!(encoding_of(html, Some("text/html; charset=\"Shift_JIS\"")), ("Shift_JIS".to_string(), EncodingSource::Header));
+        assert_eq!(header_charset("text/html;CHARSET=utf-8"), Some(UTF_8));
+        assert_eq!(header_charset("text/html; charset=bogus"), None);
+    }
+
+    #[test]
+    fn finds_meta_charset_and_http_equiv() {
+        assert_eq!(prescan_meta(b"<html><head><meta charset='windows-1251'>"), Some(WINDOWS_1251));
+        assert_eq!(
+            prescan_meta(b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=shift_jis\">"),
+            Some(SHIFT_JIS)
+        );
+        // `content` alone only counts together with the http-equiv pragma
+        assert_eq!(prescan_meta(b"<meta content=\"text/html; charset=shift_jis\">"), None);
+    }
+
+    #[test]
+    fn prescan_skips_comments_and_attribute_values() {
+        assert_eq!(prescan_meta(b"<!-- <meta charset=\"shift_jis\"> --><meta charset=\"windows-1251\">"), Some(WINDOWS_1251));
+        assert_eq!(prescan_meta(b"<div title='<meta charset=shift_jis>'><meta charset=utf-8>"), Some(UTF_8));
+    }
+
+    #[test]
+    fn meta_cannot_declare_utf16_or_user_defined() {
+        assert_eq!(prescan_meta(b"<meta charset=\"utf-16\">"), Some(UTF_8));
+        assert_eq!(prescan_meta(b"<meta charset=\"x-user-defined\">"), Some(WINDOWS_1252));
+    }
+
+    #[test]
+    fn meta_beyond_the_prescan_window_is_ignored() {
+        let mut html = vec![b' '; PRESCAN_BYTES];
+        html.extend_from_slice(b"<meta charset=\"windows-1251\"><p>plain ascii</p>");
+        let (_, source) = encoding_of(&html, None);
+        assert_eq!(source, EncodingSource::Detected);
+    }
+
+    #[test]
+    fn detects_undeclared_encodings() {
+        let (text, _, _) = WINDOWS_1251.encode("<p>Привет, как дела? Это простой текст на русском языке.</p>");
+        let (html, detected) = decode_html(&text, Some("text/html"), Some("https://example.ru/"));
+        assert_eq!(detected.source, EncodingSource::Detected);
+        assert_eq!(detected.name, "windows-1251");
+        assert!(html.contains("Привет"));
+    }
+}
@@ -1,50 +1,300 @@
-use axum::{
-    response::{IntoResponse, Response},
-    Json,
-    http::StatusCode,
-};
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
 use serde::Serialize;
+use std::borrow::Cow;
 
-#[derive(Serialize)]
-pub struct ErrorResponse {
-    error: String,
+/// What an upstream site or LLM provider answered, attached to the errors it
+/// caused; for rejected requests, only when they may be sent again
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ErrorDetails {
+    /// URL of the upstream page, or the name of the LLM provider
+    pub upstream: Option<String>,
+    /// HTTP status the upstream responded with
+    pub upstream_status: Option<u16>,
+    /// Seconds to wait before retrying, when the upstream said so
+    pub retry_after: Option<u64>,
 }
 
-#[derive(Debug, thiserror::Error)]
+/// An error in the form API clients receive it
+#[derive(Serialize, Debug, Clone)]
+pub struct ErrorBody {
+    /// Stable machine-readable code, e.g. `FETCH_TIMEOUT` or `UPSTREAM_404`
+    pub code: String,
+    pub message: String,
+    /// Whether the same request may succeed when sent again later
+    pub retryable: bool,
+    pub details: Option<ErrorDetails>,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
 pub enum AppError {
     #[error("Failed to fetch data: {0}")]
     FetchError(String),
-    
+
+    #[error("Fetch timed out: {0}")]
+    FetchTimeout(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("URL not allowed: {0}")]
+    BlockedUrl(String),
+
+    #[error("Content too large: {0}")]
+    ContentTooLarge(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Rate limited: {0}")]
+    RateLimited(String, ErrorDetails),
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String, ErrorDetails),
+
+    #[error("Upstream client error: {0}")]
+    UpstreamClientError(String, ErrorDetails),
+
+    #[error("Upstream server error: {0}")]
+    UpstreamServerError(String, ErrorDetails),
+
+    #[error("Upstream rate limited: {0}")]
+    UpstreamRateLimited(String, ErrorDetails),
+
+    #[error("Blocked by upstream: {0}")]
+    UpstreamBlocked(String, ErrorDetails),
+
     #[error("LLM processing error: {0}")]
-    LlmError(String),
-    
+    LlmError(String, ErrorDetails),
+
+    #[error("LLM rate limited: {0}")]
+    LlmRateLimited(String, ErrorDetails),
+
+    #[error("LLM timed out: {0}")]
+    LlmTimeout(String),
+
+    #[error("LLM unavailable: {0}")]
+    LlmUnavailable(String),
+
     #[error("Error parsing content: {0}")]
     ParseError(String),
-    
+
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+
     #[error("Configuration error: {0}")]
     ConfigError(String),
+
+    #[error("Internal error: {0}")]
+    InternalError(String),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            AppError::FetchError(msg) => (StatusCode::BAD_REQUEST, msg),
-            AppError::LlmError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            AppError::ParseError(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
-            AppError::ConfigError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
+impl AppError {
+    /// An LLM error without an upstream response to describe
+    pub fn llm(message: impl Into<String>) -> Self {
+        AppError::LlmError(message.into(), ErrorDetails::default())
+    }
+
+    /// HTTP status returned to API clients for this error
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::FetchError(_) => StatusCode::BAD_GATEWAY,
+            AppError::FetchTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidUrl(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::BlockedUrl(_) => StatusCode::FORBIDDEN,
+            AppError::ContentTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::RateLimited(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::QuotaExceeded(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::UpstreamClientError(..) => StatusCode::FAILED_DEPENDENCY,
+            AppError::UpstreamServerError(..) => StatusCode::BAD_GATEWAY,
+            AppError::UpstreamRateLimited(..) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::UpstreamBlocked(..) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::LlmError(..) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::LlmRateLimited(..) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::LlmTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::LlmUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::ParseError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::ConfigError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable machine-readable code; upstream client and server errors carry
+    /// the upstream status, as in `UPSTREAM_404`
+    pub fn code(&self) -> Cow<'static, str> {
+        let code = match self {
+            AppError::FetchError(_) => "FETCH_FAILED",
+            AppError::FetchTimeout(_) => "FETCH_TIMEOUT",
+            AppError::InvalidRequest(_) => "INVALID_REQUEST",
+            AppError::InvalidUrl(_) => "INVALID_URL",
+            AppError::Unauthorized(_) => "UNAUTHORIZED",
+            AppError::Forbidden(_) => "INSUFFICIENT_SCOPE",
+            AppError::BlockedUrl(_) => "URL_BLOCKED",
+            AppError::ContentTooLarge(_) => "CONTENT_TOO_LARGE",
+            AppError::NotFound(_) => "NOT_FOUND",
+            AppError::MethodNotAllowed(_) => "METHOD_NOT_ALLOWED",
+            AppError::Conflict(_) => "CONFLICT",
+            AppError::RateLimited(..) => "RATE_LIMITED",
+            AppError::QuotaExceeded(..) => "QUOTA_EXCEEDED",
+            AppError::UpstreamClientError(_, details) | AppError::UpstreamServerError(_, details) => {
+                return match details.upstream_status {
+                    Some(status) => Cow::Owned(format!("UPSTREAM_{}", status)),
+                    None => Cow::Borrowed("UPSTREAM_ERROR"),
+                };
+            },
+            AppError::UpstreamRateLimited(..) => "UPSTREAM_RATE_LIMITED",
+            AppError::UpstreamBlocked(..) => "UPSTREAM_BLOCKED",
+            AppError::LlmError(..) => "LLM_ERROR",
+            AppError::LlmRateLimited(..) => "LLM_RATE_LIMITED",
+            AppError::LlmTimeout(_) => "LLM_TIMEOUT",
+            AppError::LlmUnavailable(_) => "LLM_UNAVAILABLE",
+            AppError::ParseError(_) => "UNPROCESSABLE_CONTENT",
+            AppError::Timeout(_) => "GATEWAY_TIMEOUT",
+            AppError::ConfigError(_) => "CONFIG_ERROR",
+            AppError::InternalError(_) => "INTERNAL_ERROR",
         };
+        Cow::Borrowed(code)
+    }
+
+    /// Whether the same request may succeed when sent again later. Errors
+    /// caused by the request itself or by the page's content are final.
+    pub fn retryable(&self) -> bool {
+        match self {
+            AppError::FetchError(_)
+            | AppError::FetchTimeout(_)
+            | AppError::UpstreamServerError(..)
+            | AppError::UpstreamRateLimited(..)
+            | AppError::RateLimited(..)
+            | AppError::QuotaExceeded(..)
+            | AppError::LlmRateLimited(..)
+            | AppError::LlmTimeout(_)
+            | AppError::Timeout(_)
+            | AppError::InternalError(_) => true,
+            // Authentication and bad-request failures repeat; everything else from the provider is transient
+            AppError::LlmError(_, details) => !matches!(details.upstream_status, Some(400 | 401 | 403 | 404)),
+            // 408 Request Timeout and 425 Too Early ask for a retry
+            AppError::UpstreamClientError(_, details) => matches!(details.upstream_status, Some(408 | 425)),
+            AppError::InvalidRequest(_)
+            | AppError::InvalidUrl(_)
+            | AppError::Unauthorized(_)
+            | AppError::Forbidden(_)
+            | AppError::BlockedUrl(_)
+            | AppError::ContentTooLarge(_)
+            | AppError::NotFound(_)
+            | AppError::MethodNotAllowed(_)
+            | AppError::Conflict(_)
+            | AppError::UpstreamBlocked(..)
+            | AppError::LlmUnavailable(_)
+            | AppError::ParseError(_)
+            | AppError::ConfigError(_) => false,
+        }
+    }
+
+    /// Whether the LLM failed, rather than the page or the request
+    pub fn is_llm_failure(&self) -> bool {
+        matches!(
+            self,
+            AppError::LlmError(..) | AppError::LlmRateLimited(..) | AppError::LlmTimeout(_) | AppError::LlmUnavailable(_)
+        )
+    }
+
+    /// The error message without the variant prefix
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::FetchError(msg)
+            | AppError::FetchTimeout(msg)
+            | AppError::InvalidRequest(msg)
+            | AppError::InvalidUrl(msg)
+            | AppError::Unauthorized(msg)
+            | AppError::Forbidden(msg)
+            | AppError::BlockedUrl(msg)
+            | AppError::ContentTooLarge(msg)
+            | AppError::NotFound(msg)
+            | AppError::MethodNotAllowed(msg)
+            | AppError::Conflict(msg)
+            | AppError::RateLimited(msg, _)
+            | AppError::QuotaExceeded(msg, _)
+            | AppError::UpstreamClientError(msg, _)
+            | AppError::UpstreamServerError(msg, _)
+            | AppError::UpstreamRateLimited(msg, _)
+            | AppError::UpstreamBlocked(msg, _)
+            | AppError::LlmError(msg, _)
+            | AppError::LlmRateLimited(msg, _)
+            | AppError::LlmTimeout(msg)
+            | AppError::LlmUnavailable(msg)
+            | AppError::ParseError(msg)
+            | AppError::Timeout(msg)
+            | AppError::ConfigError(msg)
+            | AppError::InternalError(msg) => msg,
+        }
+    }
 
-        let body = Json(ErrorResponse {
-            error: error_message,
-        });
+    /// What the upstream answered, for errors caused by an upstream response,
+    /// and when to retry requests rejected by a rate limit or quota
+    pub fn details(&self) -> Option<&ErrorDetails> {
+        match self {
+            AppError::RateLimited(_, details) | AppError::QuotaExceeded(_, details) => Some(details),
+            AppError::UpstreamClientError(_, details)
+            | AppError::UpstreamServerError(_, details)
+            | AppError::UpstreamRateLimited(_, details)
+            | AppError::UpstreamBlocked(_, details)
+            | AppError::LlmError(_, details)
+            | AppError::LlmRateLimited(_, details) => {
+                (details.upstream.is_some() || details.upstream_status.is_some()).then_some(details)
+            },
+            _ => None,
+        }
+    }
 
-        (status, body).into_response()
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code().into_owned(),
+            message: self.message().to_string(),
+            retryable: self.retryable(),
+            details: self.details().cloned(),
+        }
     }
 }
 
+/// Seconds to wait according to a `Retry-After` header, given either as a
+/// number of seconds or as an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<u64> {
+    let value = value.trim();
+    value.parse::<u64>().ok().or_else(|| {
+        let at = DateTime::parse_from_rfc2822(value).ok()?;
+        Some((at.with_timezone(&Utc) - Utc::now()).num_seconds().max(0) as u64)
+    })
+}
+
 impl From<reqwest::Error> for AppError {
     fn from(err: reqwest::Error) -> Self {
-        AppError::FetchError(err.to_string())
+        if err.is_timeout() {
+            AppError::FetchTimeout(err.to_string())
+        } else {
+            AppError::FetchError(err.to_string())
+        }
     }
 }
 
@@ -54,4 +304,32 @@ impl From<std::env::VarError> for AppError {
     }
 }
 
-pub type Result<T> = std::result::Result<T, AppError>; 
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, AppError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_timeout_is_a_gateway_timeout() {
+        let err = AppError::Timeout("Request processing timed out".to_string());
+        assert_eq!(err.status_code(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(err.code(), "GATEWAY_TIMEOUT");
+        assert!(err.retryable());
+    }
+
+    #[test]
+    fn method_not_allowed_is_final() {
+        let err = AppError::MethodNotAllowed("GET is not supported for /api/scrape".to_string());
+        assert_eq!(err.status_code(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(err.code(), "METHOD_NOT_ALLOWED");
+        assert!(!err.retryable());
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        assert_eq!(parse_retry_after(" 120 "), Some(120));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
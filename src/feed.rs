@@ -0,0 +1,306 @@
+use chrono::{DateTime, Utc};
+use std::cmp::Reverse;
+use feed_rs::model::{Entry, Text};
+use reqwest::Url;
+use scraper::Html;
+use crate::api::models::DigestItem;
+use crate::error::{AppError, Result};
+use crate::metadata::PageMetadata;
+use crate::text::clean_chars;
+
+// Media types only ever used for feeds; feeds served as generic XML are recognized by their root element
+const FEED_MEDIA_TYPES: &[&str] = &["application/rss+xml", "application/atom+xml", "application/feed+json"];
+const FEED_ROOT_ELEMENTS: &[&str] = &["rss", "feed", "rdf:RDF"];
+
+// Only the start of a response is inspected for the root element
+const SNIFF_BYTES: usize = 1024;
+
+/// Whether a response is an RSS, Atom or JSON feed, judged by its media type or root element
+pub fn is_feed(content_type: Option<&str>, bytes: &[u8]) -> bool {
+    let mime = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    if FEED_MEDIA_TYPES.contains(&mime.as_str()) {
+        return true;
+    }
+
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(SNIFF_BYTES)]);
+    root_element(&head).is_some_and(|name| FEED_ROOT_ELEMENTS.contains(&name))
+}
+
+/// Name of the first element of an XML document, skipping the declaration,
+/// processing instructions, comments and the doctype
+fn root_element(mut xml: &str) -> Option<&str> {
+    loop {
+        xml = xml.trim_start_matches('\u{FEFF}').trim_start();
+        let skip_to = if xml.starts_with("<?") {
+            "?>"
+        } else if xml.starts_with("<!--") {
+            "-->"
+        } else if xml.starts_with("<!") {
+            ">"
+        } else {
+            break;
+        };
+        let end = xml.find(skip_to)?;
+        xml = &xml[end + skip_to.len()..];
+    }
+
+    let name = xml.strip_prefix('<')?;
+    let end = name.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+    Some(&name[..end])
+}
+
+/// A parsed feed with its entries, newest first
+pub struct ParsedFeed {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub lang: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub entries: Vec<FeedEntry>,
+}
+
+pub struct FeedEntry {
+    pub title: Option<String>,
+    /// Absolute link to the entry's page
+    pub url: Option<String>,
+    pub published_at: Option<DateTime<Utc>>,
+    /// The entry's content or summary as carried by the feed, usually HTML
+    pub content: Option<String>,
+}
+
+impl ParsedFeed {
+    pub fn metadata(&self) -> PageMetadata {
+        PageMetadata {
+            title: self.title.clone(),
+            description: self.description.clone(),
+            modified_at: self.updated_at.map(|updated| updated.to_rfc3339()),
+            lang: self.lang.clone(),
+            ..PageMetadata::default()
+        }
+    }
+}
+
+/// Parses an RSS, Atom or JSON feed. Relative links are resolved against `feed_url`.
+pub fn parse_feed(bytes: &[u8], feed_url: &str) -> Result<ParsedFeed> {
+    let feed = feed_rs::parser::Builder::new()
+        .base_uri(Some(feed_url))
+        .build()
+        .parse(bytes)
+        .map_err(|e| AppError::ParseError(format!("Invalid feed: {}", e)))?;
+    let base = Url::parse(feed_url).ok();
+
+    let mut entries: Vec<FeedEntry> = feed.entries.into_iter()
+        .map(|entry| feed_entry(entry, base.as_ref()))
+        .collect();
+    // Newest first; undated entries go last, in feed order
+    entries.sort_by_key(|entry| Reverse(entry.published_at));
+
+    Ok(ParsedFeed {
+        title: feed.title.as_ref().and_then(plain_text),
+        description: feed.description.as_ref().and_then(plain_text),
+        lang: feed.language,
+        updated_at: feed.updated,
+        entries,
+    })
+}
+
+fn feed_entry(entry: Entry, base: Option<&Url>) -> FeedEntry {
+    let link = entry.links.iter()
+        .find(|link| link.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+        .or_else(|| entry.links.first());
+    let url = link
+        .and_then(|link| match base {
+            Some(base) => base.join(link.href.trim()).ok(),
+            None => Url::parse(link.href.trim()).ok(),
+        })
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+        .map(|url| url.to_string());
+
+    let content = entry.content
+        .and_then(|content| content.body)
+        .or_else(|| entry.summary.map(|summary| summary.content))
+        .filter(|content| !content.trim().is_empty());
+
+    FeedEntry {
+        title: entry.title.as_ref().and_then(plain_text),
+        url,
+        published_at: entry.published.or(entry.updated),
+        content,
+    }
+}
+
+/// The text of a feed text construct, with markup removed from HTML titles
+fn plain_text(text: &Text) -> Option<String> {
+    let content = if text.content_type.to_string().contains("html") {
+        Html::parse_fragment(&text.content).root_element().text().collect::<String>()
+    } else {
+        text.content.clone()
+    };
+    let content = clean_chars(&content).split_whitespace().collect::<Vec<_>>().join(" ");
+    (!content.is_empty()).then_some(content)
+}
+
+/// Combines per-entry summaries into one Markdown digest, one section per entry
+pub fn render_digest(feed: &ParsedFeed, items: &[DigestItem]) -> String {
+    let mut digest = format!("# {}\n", feed.title.as_deref().unwrap_or("Feed digest"));
+
+    for item in items {
+        let title = item.title.as_deref().unwrap_or("Untitled");
+        let heading = match &item.url {
+            Some(url) => format!("[{}]({})", title.replace(['[', ']'], ""), url),
+            None => title.to_string(),
+        };
+        digest.push_str(&format!("\n## {}\n\n", heading));
+
+        if let Some(published_at) = item.published_at {
+            digest.push_str(&format!("*Published {}*\n\n", published_at.format("%Y-%m-%d")));
+        }
+        match (&item.summary_markdown, &item.error) {
+            (Some(summary), _) => digest.push_str(&demote_headings(summary, 2)),
+            (None, Some(error)) => digest.push_str(&format!("*No summary: {}*", error)),
+            (None, None) => {},
+        }
+        digest.push('\n');
+    }
+
+    digest
+}
+
+/// Moves Markdown headings `levels` deeper so they nest under a digest section.
+/// Lines inside fenced code blocks are left alone.
+fn demote_headings(markdown: &str, levels: usize) -> String {
+    let mut in_code = false;
+    markdown.lines()
+        .map(|line| {
+            if line.trim_start().starts_with("```") {
+                in_code = !in_code;
+            }
+            let hashes = line.chars().take_while(|c| *c == '#').count();
+            let is_heading = (1..=6).contains(&hashes) && line[hashes..].starts_with(' ');
+            if !in_code && is_heading {
+                format!("{}{}", "#".repeat((hashes + levels).min(6) - hashes), line)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- generated -->
+<rss version="2.0"><channel>
+  <title>Example &amp; Co</title>
+  <link>https://example.com/</link>
+  <description>News from Example</description>
+  <language>en-gb</language>
+  <item><title>Old post</title><link>/posts/old</link><pubDate>Mon, 01 Jan 2024 10:00:00 GMT</pubDate></item>
+  <item><title>Undated post</title><link>posts/undated</link><description>No date here</description></item>
+  <item><title>New post</title><link>https://example.com/posts/new</link><pubDate>Wed, 03 Jan 2024 10:00:00 GMT</pubDate></item>
+  <item><title>Another undated post</title><link>javascript:alert(1)</link></item>
+</channel></rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title type="html">&lt;b&gt;Atom&lt;/b&gt; news</title>
+  <id>urn:example:feed</id>
+  <updated>2024-01-05T00:00:00Z</updated>
+  <entry>
+    <title>First</title><id>urn:example:1</id>
+    <link rel="related" href="https://elsewhere.example/"/>
+    <link href="entries/1"/>
+    <updated>2024-01-04T00:00:00Z</updated>
+    <content type="html">&lt;p&gt;Entry body&lt;/p&gt;</content>
+  </entry>
+</feed>"#;
+
+    fn item(title: Option<&str>, url: Option<&str>, summary: Option<&str>, error: Option<&str>) -> DigestItem {
+        DigestItem {
+            title: title.map(str::to_string),
+            url: url.map(str::to_string),
+            published_at: DateTime::parse_from_rfc3339("2024-01-03T10:00:00Z").ok().map(|date| date.to_utc()),
+            summary_markdown: summary.map(str::to_string),
+            word_count: 0,
+            usage: None,
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn feeds_are_told_apart_from_html() {
+        assert!(is_feed(None, RSS.as_bytes()));
+        assert!(is_feed(Some("text/xml"), ATOM.as_bytes()));
+        assert!(is_feed(None, b"\xef\xbb\xbf<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">"));
+        assert!(is_feed(Some("application/rss+xml; charset=utf-8"), b""));
+        assert!(!is_feed(Some("text/html"), b"<!DOCTYPE html><html><body>rss</body></html>"));
+        assert!(!is_feed(Some("application/xml"), b"<?xml version=\"1.0\"?><sitemap/>"));
+    }
+
+    #[test]
+    fn root_element_skips_the_prolog() {
+        assert_eq!(root_element("<?xml version=\"1.0\"?>\n<!-- a -->\n<!DOCTYPE rss>\n<rss version=\"2.0\">"), Some("rss"));
+        assert_eq!(root_element("<feed xmlns=\"http://www.w3.org/2005/Atom\">"), Some("feed"));
+        assert_eq!(root_element("<html>"), Some("html"));
+        assert_eq!(root_element("<!-- never closed"), None);
+        assert_eq!(root_element("plain text"), None);
+    }
+
+    #[test]
+    fn entries_are_newest_first_with_undated_entries_last_in_feed_order() {
+        let feed = parse_feed(RSS.as_bytes(), "https://example.com/blog/feed.xml").unwrap();
+        let titles: Vec<_> = feed.entries.iter().map(|entry| entry.title.as_deref().unwrap()).collect();
+        assert_eq!(titles, ["New post", "Old post", "Undated post", "Another undated post"]);
+        assert_eq!(feed.title.as_deref(), Some("Example & Co"));
+        assert_eq!(feed.lang.as_deref(), Some("en-gb"));
+        assert_eq!(feed.entries[2].content.as_deref(), Some("No date here"));
+    }
+
+    #[test]
+    fn relative_links_are_resolved_against_the_feed_url() {
+        let feed = parse_feed(RSS.as_bytes(), "https://example.com/blog/feed.xml").unwrap();
+        let urls: Vec<_> = feed.entries.iter().map(|entry| entry.url.as_deref()).collect();
+        assert_eq!(urls, [
+            Some("https://example.com/posts/new"),
+            Some("https://example.com/posts/old"),
+            Some("https://example.com/blog/posts/undated"),
+            None,
+        ]);
+
+        let feed = parse_feed(ATOM.as_bytes(), "https://example.com/atom.xml").unwrap();
+        assert_eq!(feed.title.as_deref(), Some("Atom news"));
+        assert_eq!(feed.entries[0].url.as_deref(), Some("https://example.com/entries/1"));
+        assert_eq!(feed.entries[0].content.as_deref(), Some("<p>Entry body</p>"));
+    }
+
+    #[test]
+    fn invalid_feeds_are_parse_errors() {
+        assert!(matches!(parse_feed(b"<html><body>Not a feed</body></html>", "https://example.com/"), Err(AppError::ParseError(_))));
+    }
+
+    #[test]
+    fn digest_has_a_section_per_item() {
+        let feed = parse_feed(RSS.as_bytes(), "https://example.com/feed.xml").unwrap();
+        let items = [
+            item(Some("New [post]"), Some("https://example.com/posts/new"), Some("# Key points\n\nIt is new."), None),
+            item(None, None, None, Some("The page timed out")),
+        ];
+        assert_eq!(render_digest(&feed, &items), "# Example & Co\n\
+            \n## [New post](https://example.com/posts/new)\n\n*Published 2024-01-03*\n\n### Key points\n\nIt is new.\n\
+            \n## Untitled\n\n*Published 2024-01-03*\n\n*No summary: The page timed out*\n");
+    }
+
+    #[test]
+    fn headings_are_demoted_outside_code_fences() {
+        let markdown = "# Title\n\n##### Deep\n\n####### Not a heading\n#hashtag\n\n```sh\n# a shell comment\n```\n## After";
+        assert_eq!(
+            demote_headings(markdown, 2),
+            "### Title\n\n###### Deep\n\n####### Not a heading\n#hashtag\n\n```sh\n# a shell comment\n```\n#### After",
+        );
+    }
+}
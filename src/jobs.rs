@@ -0,0 +1,340 @@
+use serde::Serialize;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::AbortHandle;
+use uuid::Uuid;
+
+use crate::api::models::{ScrapeRequest, ScrapeResponse};
+use crate::auth::ApiClient;
+use crate::config::JobConfig;
+use crate::error::{AppError, ErrorBody};
+use crate::limits::Billing;
+use crate::pipeline::{process_scrape_request, PipelineEvent};
+use crate::AppState;
+
+/// Lifecycle of an asynchronous scrape job
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Fetching,
+    Extracting,
+    Summarizing,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Done | JobState::Failed | JobState::Cancelled)
+    }
+
+    /// Rough completion fraction reported to clients for each state
+    fn progress(self) -> f32 {
+        match self {
+            JobState::Queued => 0.0,
+            JobState::Fetching => 0.1,
+            JobState::Extracting => 0.3,
+            JobState::Summarizing => 0.5,
+            JobState::Done | JobState::Failed | JobState::Cancelled => 1.0,
+        }
+    }
+}
+
+/// Snapshot of a job returned by the jobs API
+#[derive(Serialize, Clone)]
+pub struct JobStatus {
+    pub id: Uuid,
+    pub url: String,
+    pub state: JobState,
+    pub progress: f32,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub result: Option<ScrapeResponse>,
+    pub error: Option<ErrorBody>,
+}
+
+struct JobEntry {
+    status: JobStatus,
+    /// Name of the API key that submitted the job; only it may see or cancel the job
+    owner: String,
+    handle: Option<AbortHandle>,
+}
+
+/// Why a job could not be cancelled
+pub enum CancelError {
+    NotFound,
+    AlreadyFinished(JobState),
+}
+
+/// Tracks asynchronous scrape jobs and runs them on a bounded worker pool
+pub struct JobManager {
+    jobs: Mutex<HashMap<Uuid, JobEntry>>,
+    workers: Arc<Semaphore>,
+    config: JobConfig,
+}
+
+impl JobManager {
+    pub fn new(config: &JobConfig) -> Self {
+        JobManager {
+            jobs: Mutex::new(HashMap::new()),
+            workers: Arc::new(Semaphore::new(config.workers)),
+            config: config.clone(),
+        }
+    }
+
+    /// Queues a job and returns its initial status without waiting for it to run.
+    /// The tokens the job spends are charged to `client` as they are used.
+    pub fn submit(&self, state: AppState, client: ApiClient, req: ScrapeRequest) -> JobStatus {
+        self.prune_finished();
+
+        let now = Utc::now();
+        let id = Uuid::new_v4();
+        let status = JobStatus {
+            id,
+            url: req.response_url().to_string(),
+            state: JobState::Queued,
+            progress: 0.0,
+            created_at: now,
+            updated_at: now,
+            result: None,
+            error: None,
+        };
+
+        // Insert before spawning so the worker always finds its entry
+        let owner = client.name.clone();
+        self.jobs.lock().unwrap().insert(id, JobEntry { status: status.clone(), owner, handle: None });
+
+        let workers = self.workers.clone();
+        let timeout = self.config.timeout;
+        let handle = tokio::spawn(async move {
+            let Ok(_permit) = workers.acquire_owned().await else {
+                return;
+            };
+            run_job(state, id, client, req, timeout).await;
+        });
+
+        if let Some(entry) = self.jobs.lock().unwrap().get_mut(&id) {
+            entry.handle = Some(handle.abort_handle());
+        }
+
+        println!("Queued job {} for URL: {}", id, status.url);
+        status
+    }
+
+    /// The status of a job submitted by `client`; other clients' jobs are not found
+    pub fn get(&self, id: &Uuid, client: &ApiClient) -> Option<JobStatus> {
+        self.jobs.lock().unwrap().get(id)
+            .filter(|entry| entry.owner == client.name)
+            .map(|entry| entry.status.clone())
+    }
+
+    /// Aborts a queued or running job. The computation behind it stops too,
+    /// unless another request is waiting on the same result.
+    pub fn cancel(&self, id: &Uuid, client: &ApiClient) -> Result<JobStatus, CancelError> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get_mut(id)
+            .filter(|entry| entry.owner == client.name)
+            .ok_or(CancelError::NotFound)?;
+
+        if entry.status.state.is_finished() {
+            return Err(CancelError::AlreadyFinished(entry.status.state));
+        }
+
+        if let Some(handle) = entry.handle.take() {
+            handle.abort();
+        }
+        set_state(&mut entry.status, JobState::Cancelled);
+
+        println!("Cancelled job {}", id);
+        Ok(entry.status.clone())
+    }
+
+    fn update(&self, id: &Uuid, apply: impl FnOnce(&mut JobStatus)) {
+        let mut jobs = self.jobs.lock().unwrap();
+        // Cancelled jobs keep their state even if the worker reports late progress
+        if let Some(entry) = jobs.get_mut(id)
+            && !entry.status.state.is_finished()
+        {
+            apply(&mut entry.status);
+            if entry.status.state.is_finished() {
+                entry.handle = None;
+            }
+        }
+    }
+
+    /// Forgets finished jobs older than the retention period
+    fn prune_finished(&self) {
+        let Ok(retention) = chrono::Duration::from_std(self.config.retention) else {
+            return;
+        };
+        let cutoff = Utc::now() - retention;
+        self.jobs.lock().unwrap().retain(|_, entry| {
+            !entry.status.state.is_finished() || entry.status.updated_at > cutoff
+        });
+    }
+}
+
+async fn run_job(state: AppState, id: Uuid, client: ApiClient, req: ScrapeRequest, timeout: std::time::Duration) {
+    let jobs = state.jobs.clone();
+    println!("Starting job {} for {}", id, req.label());
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let billing = Billing::new(state.limits.clone(), client);
+    let work = tokio::time::timeout(timeout, process_scrape_request(&state, &req, &billing, Some(&tx)));
+    tokio::pin!(work);
+
+    // Apply progress events while the pipeline runs
+    let result = loop {
+        tokio::select! {
+            result = &mut work => break result,
+            Some(event) = rx.recv() => apply_event(&jobs, &id, event),
+        }
+    };
+    while let Ok(event) = rx.try_recv() {
+        apply_event(&jobs, &id, event);
+    }
+
+    jobs.update(&id, |status| match result {
+        Ok(Ok(response)) => {
+            status.result = Some(response);
+            set_state(status, JobState::Done);
+        },
+        Ok(Err(err)) => {
+            println!("Job {} failed: {}", id, err);
+            status.error = Some(err.body());
+            set_state(status, JobState::Failed);
+        },
+        Err(_) => {
+            println!("Job {} timed out after {:?}", id, timeout);
+            status.error = Some(AppError::Timeout(format!("Job timed out after {} seconds", timeout.as_secs())).body());
+            set_state(status, JobState::Failed);
+        },
+    });
+}
+
+fn apply_event(jobs: &JobManager, id: &Uuid, event: PipelineEvent) {
+    let next = match event {
+        PipelineEvent::FetchStarted { .. } => JobState::Fetching,
+        PipelineEvent::FetchDone { .. } => JobState::Extracting,
+        PipelineEvent::ExtractionDone { .. } => JobState::Summarizing,
+        _ => return,
+    };
+    jobs.update(id, |status| set_state(status, next));
+}
+
+fn set_state(status: &mut JobStatus, state: JobState) {
+    status.state = state;
+    status.progress = state.progress();
+    status.updated_at = Utc::now();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::testing::{self, FakeLlm};
+
+    fn text_request(text: &str) -> ScrapeRequest {
+        ScrapeRequest { text: Some(text.to_string()), ..ScrapeRequest::default() }
+    }
+
+    /// Polls a job until it reaches `state`
+    async fn wait_for(state: &AppState, id: &Uuid, client: &ApiClient, expected: JobState) -> JobStatus {
+        for _ in 0..200 {
+            let status = state.jobs.get(id, client).expect("job exists");
+            if status.state == expected {
+                return status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("job {} never reached {:?}", id, expected);
+    }
+
+    #[tokio::test]
+    async fn job_moves_from_queued_to_done() {
+        let state = testing::state(Arc::new(FakeLlm::replying("A summary").with_delay(Duration::from_millis(100))));
+        let client = testing::client("ci");
+
+        let queued = state.jobs.submit(state.clone(), client.clone(), text_request("Some text"));
+        assert_eq!(queued.state, JobState::Queued);
+        assert_eq!(queued.progress, 0.0);
+
+        wait_for(&state, &queued.id, &client, JobState::Summarizing).await;
+        let done = wait_for(&state, &queued.id, &client, JobState::Done).await;
+        assert_eq!(done.progress, 1.0);
+        assert!(done.result.unwrap().summary.contains("A summary"));
+        assert!(done.error.is_none());
+    }
+
+    #[tokio::test]
+    async fn failing_job_reports_its_error() {
+        let state = testing::state(Arc::new(FakeLlm::failing()));
+        let client = testing::client("ci");
+
+        let id = state.jobs.submit(state.clone(), client.clone(), text_request("Some text")).id;
+        let failed = wait_for(&state, &id, &client, JobState::Failed).await;
+        assert!(failed.result.is_none());
+        assert_eq!(failed.error.unwrap().code, "LLM_ERROR");
+    }
+
+    #[tokio::test]
+    async fn finished_job_cannot_be_cancelled() {
+        let state = testing::state(Arc::new(FakeLlm::replying("A summary")));
+        let client = testing::client("ci");
+
+        let id = state.jobs.submit(state.clone(), client.clone(), text_request("Some text")).id;
+        wait_for(&state, &id, &client, JobState::Done).await;
+        assert!(matches!(state.jobs.cancel(&id, &client), Err(CancelError::AlreadyFinished(JobState::Done))));
+    }
+
+    #[tokio::test]
+    async fn jobs_are_only_visible_to_their_owner() {
+        let state = testing::state(Arc::new(FakeLlm::replying("A summary").with_delay(Duration::from_secs(5))));
+        let (owner, other) = (testing::client("owner"), testing::client("other"));
+
+        let id = state.jobs.submit(state.clone(), owner.clone(), text_request("Some text")).id;
+        assert!(state.jobs.get(&id, &other).is_none());
+        assert!(matches!(state.jobs.cancel(&id, &other), Err(CancelError::NotFound)));
+
+        let Ok(cancelled) = state.jobs.cancel(&id, &owner) else { panic!("the owner could not cancel its job") };
+        assert_eq!(cancelled.state, JobState::Cancelled);
+        assert_eq!(state.jobs.get(&id, &owner).unwrap().state, JobState::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn jobs_wait_for_a_free_worker() {
+        let mut config = testing::config();
+        config.jobs.workers = 1;
+        let state = testing::state_with(config, Arc::new(FakeLlm::replying("A summary").with_delay(Duration::from_millis(200))));
+        let client = testing::client("ci");
+
+        let first = state.jobs.submit(state.clone(), client.clone(), text_request("First text")).id;
+        let second = state.jobs.submit(state.clone(), client.clone(), text_request("Second text")).id;
+
+        wait_for(&state, &first, &client, JobState::Summarizing).await;
+        assert_eq!(state.jobs.get(&second, &client).unwrap().state, JobState::Queued);
+
+        wait_for(&state, &first, &client, JobState::Done).await;
+        wait_for(&state, &second, &client, JobState::Done).await;
+    }
+
+    #[tokio::test]
+    async fn finished_jobs_are_pruned_after_the_retention_period() {
+        let mut config = testing::config();
+        config.jobs.retention = Duration::ZERO;
+        let state = testing::state_with(config, Arc::new(FakeLlm::replying("A summary").with_delay(Duration::from_millis(50))));
+        let client = testing::client("ci");
+
+        let finished = state.jobs.submit(state.clone(), client.clone(), text_request("First text")).id;
+        wait_for(&state, &finished, &client, JobState::Done).await;
+
+        // Pruning happens on submit and keeps jobs that have not finished
+        let running = state.jobs.submit(state.clone(), client.clone(), text_request("Second text")).id;
+        assert!(state.jobs.get(&finished, &client).is_none());
+        assert!(state.jobs.get(&running, &client).is_some());
+    }
+}
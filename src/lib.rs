@@ -1,27 +1,56 @@
 pub mod api;
+pub mod auth;
+pub mod cache;
+pub mod canonical;
+pub mod chunker;
 pub mod config;
+pub mod encoding;
 pub mod error;
+pub mod feed;
+pub mod jobs;
+pub mod limits;
 pub mod llm;
+pub mod metadata;
+pub mod pdf;
+pub mod pipeline;
+pub mod readability;
 pub mod scraper;
+pub mod singleflight;
+pub mod ssrf;
+pub mod structured;
+pub mod summarizer;
+pub mod text;
+pub mod usage;
+
+#[cfg(test)]
+mod testing;
+
+pub use cache::CachedResponse;
 
 use std::sync::Arc;
+use auth::KeyStore;
+use cache::CacheStore;
 use config::Config;
-use std::collections::HashMap;
-use std::sync::Mutex;
-use chrono::DateTime;
-use chrono::Utc;
+use jobs::JobManager;
+use limits::ClientLimiter;
+use llm::LlmProvider;
+use scraper::{Extractor, Fetcher};
+use singleflight::SingleFlight;
+use api::models::ScrapeResponse;
 
 /// Application state that will be shared across handlers
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<Config>,
-    pub cache: Arc<Mutex<HashMap<String, CachedResponse>>>,
+    pub llm: Arc<dyn LlmProvider>,
+    pub fetcher: Arc<Fetcher>,
+    pub extractor: Arc<Extractor>,
+    pub cache: Arc<dyn CacheStore>,
+    pub jobs: Arc<JobManager>,
+    /// API keys accepted by the HTTP API
+    pub keys: Arc<KeyStore>,
+    /// Request rates and LLM token usage of every client
+    pub limits: Arc<ClientLimiter>,
+    /// Scrapes currently in progress, keyed by canonical URL
+    pub inflight: Arc<SingleFlight<ScrapeResponse>>,
 }
-
-/// Structure to store cached responses
-#[derive(Clone)]
-pub struct CachedResponse {
-    pub summary: String,
-    pub word_count: usize,
-    pub timestamp: DateTime<Utc>,
-} 
\ No newline at end of file
@@ -0,0 +1,458 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{Datelike, Months, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use crate::auth::ApiClient;
+use crate::config::PricingConfig;
+use crate::error::{AppError, ErrorDetails, Result};
+use crate::llm::TokenUsage;
+use crate::usage::SummaryUsage;
+
+const LIMIT_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const REMAINING_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const DAILY_REMAINING_HEADER: HeaderName = HeaderName::from_static("x-quota-daily-remaining");
+const MONTHLY_REMAINING_HEADER: HeaderName = HeaderName::from_static("x-quota-monthly-remaining");
+
+/// What a client has used of its limits
+struct ClientUsage {
+    /// Requests that may be sent right away, refilled at the client's rate up to its burst
+    requests: f64,
+    refilled_at: Instant,
+    /// UTC day the token counts belong to
+    day: NaiveDate,
+    daily_tokens: u64,
+    monthly_tokens: u64,
+    daily_cost_usd: f64,
+    monthly_cost_usd: f64,
+}
+
+/// What a client spent on the LLM today and this month
+#[derive(Serialize)]
+pub struct ClientSpend {
+    pub client: String,
+    /// The current UTC day
+    pub day: NaiveDate,
+    pub daily_tokens: u64,
+    pub daily_cost_usd: f64,
+    pub monthly_tokens: u64,
+    pub monthly_cost_usd: f64,
+}
+
+/// LLM tokens and cost of one client on one UTC day
+pub struct DailyUsage {
+    pub client: String,
+    pub day: NaiveDate,
+    pub tokens: u64,
+    pub cost_usd: f64,
+}
+
+/// Durable storage for the token counts behind the quotas and spend report.
+///
+/// Calls block, so the limiter makes them from tokio's blocking thread pool.
+pub trait UsageStore: Send + Sync {
+    /// Adds to what the client used on the day
+    fn add(&self, usage: &DailyUsage) -> Result<()>;
+
+    /// What every client used on each day from `since` on
+    fn load(&self, since: NaiveDate) -> Result<Vec<DailyUsage>>;
+}
+
+impl ClientUsage {
+    fn new(client: &ApiClient) -> Self {
+        ClientUsage {
+            requests: client.limits.burst as f64,
+            refilled_at: Instant::now(),
+            day: Utc::now().date_naive(),
+            daily_tokens: 0,
+            monthly_tokens: 0,
+            daily_cost_usd: 0.0,
+            monthly_cost_usd: 0.0,
+        }
+    }
+
+    /// Counts of a client read back from the store. The bucket starts full;
+    /// the first refill caps it at the client's burst.
+    fn restored(today: NaiveDate) -> Self {
+        ClientUsage {
+            requests: f64::INFINITY,
+            refilled_at: Instant::now(),
+            day: today,
+            daily_tokens: 0,
+            monthly_tokens: 0,
+            daily_cost_usd: 0.0,
+            monthly_cost_usd: 0.0,
+        }
+    }
+
+    fn refill(&mut self, client: &ApiClient) {
+        let now = Instant::now();
+        let per_second = client.limits.requests_per_minute as f64 / 60.0;
+        let refilled = self.requests + now.duration_since(self.refilled_at).as_secs_f64() * per_second;
+        self.requests = refilled.min(client.limits.burst as f64);
+        self.refilled_at = now;
+    }
+
+    /// Starts new token counts when the day or month has changed
+    fn roll_over(&mut self) {
+        let today = Utc::now().date_naive();
+        if (today.year(), today.month()) != (self.day.year(), self.day.month()) {
+            self.monthly_tokens = 0;
+            self.monthly_cost_usd = 0.0;
+        }
+        if today != self.day {
+            self.daily_tokens = 0;
+            self.daily_cost_usd = 0.0;
+            self.day = today;
+        }
+    }
+}
+
+/// Per-client request rate limits, LLM token quotas and spend.
+///
+/// Requests are limited by a token bucket per API key. Token counts and costs
+/// are kept per UTC day and month. Without a store they are only kept in
+/// memory and start over when the server restarts.
+pub struct ClientLimiter {
+    clients: Mutex<HashMap<String, ClientUsage>>,
+    pricing: PricingConfig,
+    store: Option<Arc<dyn UsageStore>>,
+}
+
+impl ClientLimiter {
+    pub fn new(pricing: PricingConfig) -> Self {
+        ClientLimiter { clients: Mutex::new(HashMap::new()), pricing, store: None }
+    }
+
+    /// A limiter that saves token counts to `store` and starts from what it
+    /// holds for the current month
+    pub fn with_store(pricing: PricingConfig, store: Arc<dyn UsageStore>) -> Result<Self> {
+        let today = Utc::now().date_naive();
+        let month_start = today.with_day(1).expect("every month has a first day");
+        let mut clients: HashMap<String, ClientUsage> = HashMap::new();
+        for saved in store.load(month_start)? {
+            let usage = clients.entry(saved.client).or_insert_with(|| ClientUsage::restored(today));
+            usage.monthly_tokens += saved.tokens;
+            usage.monthly_cost_usd += saved.cost_usd;
+            if saved.day == today {
+                usage.daily_tokens += saved.tokens;
+                usage.daily_cost_usd += saved.cost_usd;
+            }
+        }
+        println!("Restored LLM token counts of {} keys", clients.len());
+        Ok(ClientLimiter { clients: Mutex::new(clients), pricing, store: Some(store) })
+    }
+
+    fn with_usage<T>(&self, client: &ApiClient, apply: impl FnOnce(&mut ClientUsage) -> T) -> T {
+        let mut clients = self.clients.lock().unwrap();
+        let usage = clients.entry(client.name.clone()).or_insert_with(|| ClientUsage::new(client));
+        usage.refill(client);
+        usage.roll_over();
+        apply(usage)
+    }
+
+    /// Takes one request from the client's bucket, or fails when it is empty
+    pub fn acquire(&self, client: &ApiClient) -> Result<()> {
+        let per_minute = client.limits.requests_per_minute;
+        if per_minute == 0 {
+            return Ok(());
+        }
+
+        self.with_usage(client, |usage| {
+            if usage.requests >= 1.0 {
+                usage.requests -= 1.0;
+                return Ok(());
+            }
+            let wait = (1.0 - usage.requests) * 60.0 / per_minute as f64;
+            Err(AppError::RateLimited(
+                format!("Rate limit of {} requests per minute exceeded", per_minute),
+                retry_after(wait.ceil() as u64),
+            ))
+        })
+    }
+
+    /// Fails when the client has used up its daily or monthly LLM tokens
+    pub fn check_quota(&self, client: &ApiClient) -> Result<()> {
+        let limits = client.limits;
+        self.with_usage(client, |usage| {
+            let today = usage.day;
+            if limits.monthly_tokens > 0 && usage.monthly_tokens >= limits.monthly_tokens {
+                let next_month = today.with_day(1).and_then(|first| first.checked_add_months(Months::new(1)));
+                return Err(AppError::QuotaExceeded(
+                    format!("Monthly quota of {} LLM tokens used up", limits.monthly_tokens),
+                    retry_after(next_month.map_or(0, seconds_until)),
+                ));
+            }
+            if limits.daily_tokens > 0 && usage.daily_tokens >= limits.daily_tokens {
+                return Err(AppError::QuotaExceeded(
+                    format!("Daily quota of {} LLM tokens used up", limits.daily_tokens),
+                    retry_after(today.succ_opt().map_or(0, seconds_until)),
+                ));
+            }
+            Ok(())
+        })
+    }
+
+    /// Counts the tokens of one LLM call of the client against its quotas,
+    /// saves them to the store and returns them priced. Tokens of models without
+    /// a configured price add no cost. A failed save is logged; the counts in
+    /// memory still apply.
+    async fn record(&self, client: &ApiClient, model: &str, tokens: TokenUsage) -> SummaryUsage {
+        let spent = SummaryUsage::new(model, tokens, &self.pricing);
+        let tokens = spent.tokens.total_tokens;
+        let cost = spent.cost_usd.unwrap_or(0.0);
+        let day = self.with_usage(client, |usage| {
+            usage.daily_tokens += tokens;
+            usage.monthly_tokens += tokens;
+            usage.daily_cost_usd += cost;
+            usage.monthly_cost_usd += cost;
+            println!("Key '{}' used {} LLM tokens ({} today, {} this month; ${:.6} this month)",
+                     client.name, tokens, usage.daily_tokens, usage.monthly_tokens, usage.monthly_cost_usd);
+            usage.day
+        });
+
+        let Some(store) = self.store.clone() else { return spent };
+        let daily = DailyUsage { client: client.name.clone(), day, tokens, cost_usd: cost };
+        let saved = tokio::task::spawn_blocking(move || store.add(&daily))
+            .await
+            .unwrap_or_else(|e| Err(AppError::InternalError(e.to_string())));
+        if let Err(e) = saved {
+            println!("Failed to save LLM token counts of key '{}': {}", client.name, e);
+        }
+        spent
+    }
+
+    /// The LLM spend this month of every client, including what the store
+    /// held when the server started
+    pub fn spend(&self) -> Vec<ClientSpend> {
+        let mut clients = self.clients.lock().unwrap();
+        let mut spend: Vec<ClientSpend> = clients.iter_mut()
+            .map(|(name, usage)| {
+                usage.roll_over();
+                ClientSpend {
+                    client: name.clone(),
+                    day: usage.day,
+                    daily_tokens: usage.daily_tokens,
+                    daily_cost_usd: usage.daily_cost_usd,
+                    monthly_tokens: usage.monthly_tokens,
+                    monthly_cost_usd: usage.monthly_cost_usd,
+                }
+            })
+            .collect();
+        spend.sort_by(|a, b| a.client.cmp(&b.client));
+        spend
+    }
+
+    /// Reports what is left of the client's limits; limits that are off are left out
+    fn set_headers(&self, client: &ApiClient, headers: &mut HeaderMap) {
+        let limits = client.limits;
+        self.with_usage(client, |usage| {
+            if limits.requests_per_minute > 0 {
+                headers.insert(LIMIT_HEADER, HeaderValue::from(limits.requests_per_minute));
+                headers.insert(REMAINING_HEADER, HeaderValue::from(usage.requests.floor() as u64));
+            }
+            if limits.daily_tokens > 0 {
+                headers.insert(DAILY_REMAINING_HEADER, HeaderValue::from(limits.daily_tokens.saturating_sub(usage.daily_tokens)));
+            }
+            if limits.monthly_tokens > 0 {
+                headers.insert(MONTHLY_REMAINING_HEADER, HeaderValue::from(limits.monthly_tokens.saturating_sub(usage.monthly_tokens)));
+            }
+        });
+    }
+}
+
+/// Charges the LLM calls of a computation to the client that started it.
+///
+/// The quota is checked before every call and the tokens are recorded as soon
+/// as the call returns, so work that later fails, times out or is abandoned is
+/// still paid for.
+#[derive(Clone)]
+pub struct Billing {
+    limiter: Arc<ClientLimiter>,
+    client: ApiClient,
+}
+
+impl Billing {
+    pub fn new(limiter: Arc<ClientLimiter>, client: ApiClient) -> Self {
+        Billing { limiter, client }
+    }
+
+    /// Fails when the client has used up its daily or monthly LLM tokens
+    pub fn check_quota(&self) -> Result<()> {
+        self.limiter.check_quota(&self.client)
+    }
+
+    /// Records the tokens one LLM call of `model` took and returns what they cost
+    pub async fn charge(&self, model: &str, tokens: TokenUsage) -> SummaryUsage {
+        self.limiter.record(&self.client, model, tokens).await
+    }
+}
+
+fn retry_after(seconds: u64) -> ErrorDetails {
+    ErrorDetails { retry_after: Some(seconds.max(1)), ..ErrorDetails::default() }
+}
+
+/// Seconds from now until the start of `day` in UTC
+fn seconds_until(day: NaiveDate) -> u64 {
+    let start = day.and_hms_opt(0, 0, 0).expect("midnight is a valid time").and_utc();
+    (start - Utc::now()).num_seconds().max(0) as u64
+}
+
+/// Applies the request rate limit of the client that `require_scope` attached
+/// to the request, and reports what is left of its limits in the response headers
+pub async fn rate_limit(State(limiter): State<Arc<ClientLimiter>>, request: Request, next: Next) -> Response {
+    let Some(client) = request.extensions().get::<ApiClient>().cloned() else {
+        return next.run(request).await;
+    };
+
+    let mut response = match limiter.acquire(&client) {
+        Ok(()) => next.run(request).await,
+        Err(err) => {
+            println!("Rejected {} {} for key '{}': {}", request.method(), request.uri().path(), client.name, err);
+            err.into_response()
+        },
+    };
+    limiter.set_headers(&client, response.headers_mut());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::cache::SqliteCache;
+    use crate::config::{ApiScope, ClientLimits, ModelPrice};
+
+    fn client(limits: ClientLimits) -> ApiClient {
+        ApiClient { name: "ci".to_string(), scopes: vec![ApiScope::Scrape], limits }
+    }
+
+    fn limits(requests_per_minute: u32, burst: u32, daily_tokens: u64, monthly_tokens: u64) -> ClientLimits {
+        ClientLimits { requests_per_minute, burst, daily_tokens, monthly_tokens }
+    }
+
+    fn tokens(prompt_tokens: u64, completion_tokens: u64) -> TokenUsage {
+        TokenUsage { prompt_tokens, completion_tokens, total_tokens: prompt_tokens + completion_tokens }
+    }
+
+    #[test]
+    fn rejects_requests_once_the_burst_is_used() {
+        let limiter = ClientLimiter::new(PricingConfig::default());
+        let client = client(limits(60, 2, 0, 0));
+        assert!(limiter.acquire(&client).is_ok());
+        assert!(limiter.acquire(&client).is_ok());
+        match limiter.acquire(&client) {
+            Err(AppError::RateLimited(_, details)) => assert_eq!(details.retry_after, Some(1)),
+            other => panic!("expected a rate limit error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn bucket_refills_at_the_rate_up_to_the_burst() {
+        let limiter = ClientLimiter::new(PricingConfig::default());
+        // Ten requests a second
+        let client = client(limits(600, 2, 0, 0));
+        assert!(limiter.acquire(&client).is_ok());
+        assert!(limiter.acquire(&client).is_ok());
+        assert!(limiter.acquire(&client).is_err());
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(limiter.acquire(&client).is_ok());
+        assert!(limiter.acquire(&client).is_err());
+
+        // A long wait fills the bucket no further than the burst
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(limiter.acquire(&client).is_ok());
+        assert!(limiter.acquire(&client).is_ok());
+        assert!(limiter.acquire(&client).is_err());
+    }
+
+    #[test]
+    fn zero_rate_turns_the_limit_off() {
+        let limiter = ClientLimiter::new(PricingConfig::default());
+        let client = client(limits(0, 0, 0, 0));
+        for _ in 0..100 {
+            assert!(limiter.acquire(&client).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn charged_tokens_use_up_the_quotas() {
+        let limiter = Arc::new(ClientLimiter::new(PricingConfig::default()));
+        let daily = Billing::new(limiter.clone(), client(limits(0, 0, 100, 0)));
+        daily.charge("gpt-4o", tokens(60, 30)).await;
+        assert!(daily.check_quota().is_ok());
+        daily.charge("gpt-4o", tokens(5, 5)).await;
+        assert!(matches!(daily.check_quota(), Err(AppError::QuotaExceeded(..))));
+
+        let limiter = Arc::new(ClientLimiter::new(PricingConfig::default()));
+        let monthly = Billing::new(limiter, client(limits(0, 0, 0, 50)));
+        monthly.charge("gpt-4o", tokens(50, 0)).await;
+        match monthly.check_quota() {
+            Err(AppError::QuotaExceeded(_, details)) => assert!(details.retry_after.unwrap() <= 31 * 24 * 3600),
+            other => panic!("expected a quota error, got {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn daily_quota_resets_at_the_next_utc_day() {
+        let limiter = Arc::new(ClientLimiter::new(PricingConfig::default()));
+        let billing = Billing::new(limiter, client(limits(0, 0, 10, 0)));
+        billing.charge("gpt-4o", tokens(10, 0)).await;
+        match billing.check_quota() {
+            Err(AppError::QuotaExceeded(_, details)) => {
+                let tomorrow = Utc::now().date_naive().succ_opt().unwrap();
+                let expected = seconds_until(tomorrow).max(1);
+                assert!(details.retry_after.unwrap().abs_diff(expected) <= 1);
+            },
+            other => panic!("expected a quota error, got {:?}", other.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn token_counts_survive_a_restart_with_a_store() {
+        let path = std::env::temp_dir().join(format!("usage-{}.db", uuid::Uuid::new_v4()));
+        let quota = client(limits(60, 1, 100, 0));
+        let store = Arc::new(SqliteCache::open(&path, Duration::from_secs(60)).unwrap());
+        let limiter = Arc::new(ClientLimiter::with_store(PricingConfig::default(), store).unwrap());
+        Billing::new(limiter.clone(), quota.clone()).charge("gpt-4o", tokens(60, 0)).await;
+        Billing::new(limiter, quota.clone()).charge("gpt-4o", tokens(40, 0)).await;
+
+        let store = Arc::new(SqliteCache::open(&path, Duration::from_secs(60)).unwrap());
+        // A row from last month no longer counts
+        let last_month = Utc::now().date_naive().with_day(1).unwrap().pred_opt().unwrap();
+        store.add(&DailyUsage { client: "ci".to_string(), day: last_month, tokens: 1000, cost_usd: 1.0 }).unwrap();
+        let restarted = ClientLimiter::with_store(PricingConfig::default(), store).unwrap();
+        assert!(matches!(restarted.check_quota(&quota), Err(AppError::QuotaExceeded(..))));
+        let spend = restarted.spend();
+        assert_eq!((spend[0].daily_tokens, spend[0].monthly_tokens), (100, 100));
+        // The bucket of a restored client starts at its burst
+        assert!(restarted.acquire(&quota).is_ok());
+        assert!(restarted.acquire(&quota).is_err());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn reports_the_spend_of_every_client() {
+        let pricing = PricingConfig {
+            prices: [("gpt-4o".to_string(), ModelPrice { prompt: 2.0, completion: 10.0 })].into(),
+        };
+        let limiter = Arc::new(ClientLimiter::new(pricing));
+        let billing = Billing::new(limiter.clone(), client(limits(0, 0, 0, 0)));
+        billing.charge("gpt-4o", tokens(1_000_000, 100_000)).await;
+        billing.charge("unpriced-model", tokens(500, 500)).await;
+
+        let spend = limiter.spend();
+        assert_eq!(spend.len(), 1);
+        assert_eq!(spend[0].client, "ci");
+        assert_eq!(spend[0].daily_tokens, 1_101_000);
+        assert_eq!(spend[0].monthly_tokens, 1_101_000);
+        assert!((spend[0].monthly_cost_usd - 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,200 @@
+use async_trait::async_trait;
+use reqwest::RequestBuilder;
+use serde::Serialize;
+use std::time::Duration;
+use crate::config::LlmConfig;
+use crate::error::{Result, AppError};
+use super::{Completion, CompletionRequest, DeltaCallback, LlmProvider, TokenUsage, CLIENT, require_api_key, send_with_retry, stream_with_retry};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+#[derive(Serialize, Debug)]
+struct Message<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    system: &'a str,
+    messages: Vec<Message<'a>>,
+    max_tokens: u32,
+    temperature: f32,
+    stream: bool,
+}
+
+/// Provider for the Anthropic Messages API
+pub struct AnthropicProvider {
+    base_url: String,
+    api_key: String,
+    model: String,
+    max_tokens: u32,
+    temperature: f32,
+}
+
+impl AnthropicProvider {
+    pub fn new(config: &LlmConfig) -> Result<Self> {
+        Ok(Self {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            api_key: require_api_key(config)?,
+            model: config.model.clone(),
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+        })
+    }
+
+    fn messages_request<'a>(&'a self, request: &CompletionRequest<'a>, stream: bool) -> MessagesRequest<'a> {
+        MessagesRequest {
+            model: &self.model,
+            system: request.system,
+            messages: vec![Message { role: "user", content: request.prompt }],
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
+            stream,
+        }
+    }
+
+    fn post(&self, body: &MessagesRequest<'_>, timeout: Duration) -> RequestBuilder {
+        CLIENT
+            .post(format!("{}/messages", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .timeout(timeout)
+            .json(body)
+    }
+}
+
+/// Reads a `usage` object, which names its counts `input_tokens` and `output_tokens`
+fn read_usage(usage: &serde_json::Value, into: &mut TokenUsage) {
+    if let Some(input) = usage["input_tokens"].as_u64() {
+        into.prompt_tokens = input;
+    }
+    if let Some(output) = usage["output_tokens"].as_u64() {
+        into.completion_tokens = output;
+    }
+    into.total_tokens = into.prompt_tokens + into.completion_tokens;
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    fn name(&self) -> &'static str {
+        "Anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: &CompletionRequest<'_>) -> Result<Completion> {
+        println!("Preparing LLM request");
+        println!("Prompt size: {} chars", request.prompt.len());
+
+        let body = self.messages_request(request, false);
+        let json = send_with_retry(self.name(), || self.post(&body, Duration::from_secs(60))).await?;
+
+        // The response holds a list of content blocks; concatenate the text ones
+        let content = json["content"]
+            .as_array()
+            .map(|blocks| {
+                blocks.iter()
+                    .filter(|block| block["type"] == "text")
+                    .filter_map(|block| block["text"].as_str())
+                    .collect::<Vec<_>>()
+                    .join("")
+            })
+            .filter(|text| !text.is_empty());
+
+        match content {
+            Some(content) => {
+                let mut usage = TokenUsage::default();
+                read_usage(&json["usage"], &mut usage);
+                println!("Successfully received LLM response ({} chars, {} tokens)", content.len(), usage.total_tokens);
+                let model = json["model"].as_str().map(str::to_string);
+                Ok(Completion { text: content, usage, model })
+            },
+            None => {
+                println!("Invalid response format: {:?}", json);
+                Err(AppError::llm("Invalid response format".to_string()))
+            }
+        }
+    }
+
+    async fn complete_stream(&self, request: &CompletionRequest<'_>, on_delta: DeltaCallback<'_>) -> Result<Completion> {
+        println!("Preparing streaming LLM request");
+        println!("Prompt size: {} chars", request.prompt.len());
+
+        let body = self.messages_request(request, true);
+        let mut content = String::new();
+        let mut usage = TokenUsage::default();
+        let mut model = None;
+
+        // Streams may legitimately run longer than a buffered completion
+        stream_with_retry(self.name(), || self.post(&body, Duration::from_secs(180)), |data| {
+            let json: serde_json::Value = serde_json::from_str(data)
+                .map_err(|e| AppError::llm(format!("Invalid stream event: {}", e)))?;
+
+            match json["type"].as_str() {
+                // The input tokens are counted up front, the output tokens as the message ends
+                Some("message_start") => {
+                    read_usage(&json["message"]["usage"], &mut usage);
+                    model = json["message"]["model"].as_str().map(str::to_string);
+                    Ok(true)
+                },
+                Some("message_delta") => {
+                    read_usage(&json["usage"], &mut usage);
+                    Ok(true)
+                },
+                Some("content_block_delta") => {
+                    if let Some(delta) = json["delta"]["text"].as_str() {
+                        content.push_str(delta);
+                        on_delta(delta);
+                    }
+                    Ok(true)
+                },
+                Some("message_stop") => Ok(false),
+                Some("error") => Err(AppError::llm(format!(
+                    "Stream error: {}",
+                    json["error"]["message"].as_str().unwrap_or("unknown error")
+                ))),
+                _ => Ok(true),
+            }
+        }).await?;
+
+        println!("Successfully streamed LLM response ({} chars, {} tokens)", content.len(), usage.total_tokens);
+        Ok(Completion { text: content, usage, model })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LlmProviderKind;
+    use crate::llm::tests::{collect_stream, config, serve_events};
+
+    #[tokio::test]
+    async fn maps_stream_events_to_tokens() {
+        let body = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-3-5-haiku-20241022\",\"usage\":{\"input_tokens\":10,\"output_tokens\":1}}}\n\n",
+            "event: ping\ndata: {\"type\":\"ping\"}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\" there\"}}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":3}}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+        );
+        let provider = AnthropicProvider::new(&LlmConfig {
+            base_url: serve_events(body).await,
+            ..config(LlmProviderKind::Anthropic, Some("key"))
+        }).unwrap();
+
+        let (completion, deltas) = collect_stream(&provider).await;
+        assert_eq!(deltas, vec!["Hi", " there"]);
+        assert_eq!(completion.text, "Hi there");
+        assert_eq!(completion.usage.total_tokens, 13);
+        assert_eq!(completion.model.as_deref(), Some("claude-3-5-haiku-20241022"));
+    }
+}
@@ -0,0 +1,354 @@
+pub mod anthropic;
+pub mod openai;
+mod sse;
+
+use async_trait::async_trait;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response, StatusCode};
+use std::sync::Arc;
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::ops::AddAssign;
+use crate::config::{LlmConfig, LlmProviderKind};
+use crate::error::{parse_retry_after, AppError, ErrorDetails, Result};
+
+pub use anthropic::AnthropicProvider;
+pub use openai::OpenAiCompatibleProvider;
+
+/// System prompt shared by every provider
+pub const SYSTEM_PROMPT: &str = "You are a content summarizer that extracts the main points from web content. Identify the key information, main arguments, and important details. Ignore HTML markup and focus on the actual text content. Format your summary using clear Markdown structure with headers and bullet points.";
+
+/// A single completion request, independent of the provider wire format
+pub struct CompletionRequest<'a> {
+    pub system: &'a str,
+    pub prompt: &'a str,
+    pub site_url: Option<&'a str>,
+    pub site_name: Option<&'a str>,
+}
+
+/// Tokens a completion consumed, as reported by the provider. Providers that
+/// do not report usage leave every count at zero.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+impl TokenUsage {
+    /// Reads the `usage` object of an OpenAI-style response
+    pub fn from_json(usage: &serde_json::Value) -> Option<Self> {
+        let mut usage = serde_json::from_value::<TokenUsage>(usage.clone()).ok()?;
+        if usage.total_tokens == 0 {
+            usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+        }
+        Some(usage)
+    }
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+/// The text of a completion together with the tokens it took
+#[derive(Debug, Default)]
+pub struct Completion {
+    pub text: String,
+    pub usage: TokenUsage,
+    /// Model that actually served the completion, when the provider says so;
+    /// routers like OpenRouter may pick another one than requested
+    pub model: Option<String>,
+}
+
+/// Callback receiving incremental pieces of a streamed completion
+pub type DeltaCallback<'a> = &'a (dyn Fn(&str) + Send + Sync);
+
+/// A backend able to turn a prompt into a completion
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// Short provider name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Model identifier sent to the provider
+    fn model(&self) -> &str;
+
+    /// Fails when the provider cannot serve completions at all, so callers can
+    /// bail out before doing work whose only purpose is the summary
+    fn ensure_available(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn complete(&self, request: &CompletionRequest<'_>) -> Result<Completion>;
+
+    /// Streams the completion, calling `on_delta` for every text fragment as it
+    /// arrives, and returns the full completion once the stream ends.
+    ///
+    /// Providers without streaming support deliver the whole completion as a
+    /// single delta.
+    async fn complete_stream(&self, request: &CompletionRequest<'_>, on_delta: DeltaCallback<'_>) -> Result<Completion> {
+        let completion = self.complete(request).await?;
+        on_delta(&completion.text);
+        Ok(completion)
+    }
+}
+
+// Create a static client to reuse connections with shorter timeout
+pub(crate) static CLIENT: Lazy<Client> = Lazy::new(|| {
+    ClientBuilder::new()
+        .timeout(Duration::from_secs(60))
+        .connect_timeout(Duration::from_secs(10))
+        .pool_max_idle_per_host(5)
+        .pool_idle_timeout(Duration::from_secs(30))
+        .build()
+        .expect("Failed to build HTTP client")
+});
+
+/// Builds the provider selected in the configuration.
+///
+/// A hosted provider without an API key is not an error: the server still
+/// starts so that endpoints without summaries keep working.
+pub fn build_provider(config: &LlmConfig) -> Result<Arc<dyn LlmProvider>> {
+    if config.provider.requires_api_key() && config.api_key.is_none() {
+        let reason = format!("no API key is configured for the {} provider", config.provider);
+        println!("LLM summaries disabled: {}", reason);
+        return Ok(Arc::new(UnavailableProvider { model: config.model.clone(), reason }));
+    }
+
+    let provider: Arc<dyn LlmProvider> = match config.provider {
+        LlmProviderKind::OpenRouter => Arc::new(OpenAiCompatibleProvider::openrouter(config)?),
+        LlmProviderKind::OpenAi => Arc::new(OpenAiCompatibleProvider::openai(config)?),
+        LlmProviderKind::Local => Arc::new(OpenAiCompatibleProvider::local(config)),
+        LlmProviderKind::Anthropic => Arc::new(AnthropicProvider::new(config)?),
+    };
+
+    println!("Using LLM provider: {} (model={})", provider.name(), provider.model());
+    Ok(provider)
+}
+
+/// Stands in for a provider that could not be configured; every completion fails
+pub struct UnavailableProvider {
+    model: String,
+    reason: String,
+}
+
+impl UnavailableProvider {
+    fn error(&self) -> AppError {
+        AppError::LlmUnavailable(format!("LLM summaries are disabled: {}", self.reason))
+    }
+}
+
+#[async_trait]
+impl LlmProvider for UnavailableProvider {
+    fn name(&self) -> &'static str {
+        "unavailable"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn ensure_available(&self) -> Result<()> {
+        Err(self.error())
+    }
+
+    async fn complete(&self, _request: &CompletionRequest<'_>) -> Result<Completion> {
+        Err(self.error())
+    }
+}
+
+pub(crate) fn require_api_key(config: &LlmConfig) -> Result<String> {
+    config.api_key.clone().ok_or_else(|| {
+        AppError::ConfigError(format!("An API key is required for the {} provider", config.provider))
+    })
+}
+
+/// Sends a request built by `build_request`, retrying transient failures with
+/// exponential backoff, and returns the parsed JSON body.
+pub(crate) async fn send_with_retry<F>(provider: &str, build_request: F) -> Result<serde_json::Value>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let res = send_checked_with_retry(provider, build_request).await?;
+    res.json::<serde_json::Value>().await.map_err(|e| {
+        println!("Failed to parse JSON: {}", e);
+        AppError::llm(format!("JSON parse error: {}", e))
+    })
+}
+
+/// Streams a request built by `build_request` and feeds every `data:` payload
+/// of the server-sent event stream to `on_data` until the stream ends.
+pub(crate) async fn stream_with_retry<F, D>(provider: &str, build_request: F, mut on_data: D) -> Result<()>
+where
+    F: Fn() -> RequestBuilder,
+    D: FnMut(&str) -> Result<bool> + Send,
+{
+    use futures::StreamExt;
+
+    let res = send_checked_with_retry(provider, build_request).await?;
+    let mut body = res.bytes_stream();
+    let mut parser = sse::SseParser::default();
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| AppError::llm(format!("Stream error: {}", e)))?;
+        for data in parser.feed(&chunk) {
+            // `on_data` returns false once the provider signalled the end of the stream
+            if !on_data(&data)? {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a request, retrying transient failures with exponential backoff, and
+/// returns the response once the provider answers with a success status.
+async fn send_checked_with_retry<F>(provider: &str, build_request: F) -> Result<Response>
+where
+    F: Fn() -> RequestBuilder,
+{
+    // Try up to 3 times with exponential backoff
+    let max_retries = 2;
+    let mut last_error = AppError::llm("Unknown error");
+
+    for attempt in 0..=max_retries {
+        if attempt > 0 {
+            println!("Retry attempt {} for LLM API request", attempt);
+            // Exponential backoff between retries
+            tokio::time::sleep(Duration::from_millis(500 * 2u64.pow(attempt as u32))).await;
+        }
+
+        println!("Sending request to {} (attempt {})", provider, attempt + 1);
+
+        match build_request().send().await {
+            Ok(res) => {
+                let status = res.status();
+                println!("Received response with status: {}", status);
+
+                if status.is_success() {
+                    return Ok(res);
+                } else {
+                    let details = ErrorDetails {
+                        upstream: Some(provider.to_string()),
+                        upstream_status: Some(status.as_u16()),
+                        retry_after: res.headers().get(RETRY_AFTER)
+                            .and_then(|value| value.to_str().ok())
+                            .and_then(parse_retry_after),
+                    };
+                    // Try to get error message from response
+                    let message = match res.text().await {
+                        Ok(text) => {
+                            println!("Error response: {}", text);
+                            format!("API error ({}): {}", status, text)
+                        },
+                        Err(e) => format!("HTTP error ({}): {}", status, e),
+                    };
+                    last_error = if status == StatusCode::TOO_MANY_REQUESTS {
+                        AppError::LlmRateLimited(message, details)
+                    } else {
+                        AppError::LlmError(message, details)
+                    };
+
+                    // Don't retry on certain status codes
+                    if status.as_u16() == 401 || status.as_u16() == 403 {
+                        println!("Not retrying due to authentication error");
+                        break;
+                    }
+                }
+            },
+            Err(e) => {
+                println!("Request error: {}", e);
+                last_error = AppError::llm(format!("Request error: {}", e));
+
+                if e.is_timeout() {
+                    println!("Request timed out");
+                    last_error = AppError::LlmTimeout(format!("{} did not respond in time", provider));
+                }
+            }
+        }
+    }
+
+    println!("All retry attempts failed");
+    Err(last_error)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    pub(crate) fn config(provider: LlmProviderKind, api_key: Option<&str>) -> LlmConfig {
+        LlmConfig {
+            provider,
+            base_url: "http://127.0.0.1:9/v1".to_string(),
+            api_key: api_key.map(str::to_string),
+            model: "test-model".to_string(),
+            max_tokens: 100,
+            temperature: 0.0,
+        }
+    }
+
+    /// Serves one `text/event-stream` response with `body` on a local port and returns its base URL
+    pub(crate) async fn serve_events(body: &str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(), body,
+        );
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 4096];
+            let _ = socket.read(&mut request).await;
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+        format!("http://{}/v1", addr)
+    }
+
+    /// Streams a completion and returns it with the deltas it reported
+    pub(crate) async fn collect_stream(provider: &dyn LlmProvider) -> (Completion, Vec<String>) {
+        let deltas = std::sync::Mutex::new(Vec::new());
+        let on_delta = |delta: &str| deltas.lock().unwrap().push(delta.to_string());
+        let request = CompletionRequest { system: SYSTEM_PROMPT, prompt: "hi", site_url: None, site_name: None };
+        let completion = provider.complete_stream(&request, &on_delta).await.unwrap();
+        (completion, deltas.into_inner().unwrap())
+    }
+
+    #[test]
+    fn hosted_provider_without_key_is_unavailable() {
+        let provider = build_provider(&config(LlmProviderKind::OpenRouter, None)).unwrap();
+        assert_eq!(provider.name(), "unavailable");
+        assert_eq!(provider.model(), "test-model");
+        assert!(matches!(provider.ensure_available(), Err(AppError::LlmUnavailable(_))));
+    }
+
+    #[test]
+    fn selects_configured_provider() {
+        let cases = [
+            (LlmProviderKind::OpenRouter, Some("key"), "OpenRouter"),
+            (LlmProviderKind::OpenAi, Some("key"), "OpenAI"),
+            (LlmProviderKind::Anthropic, Some("key"), "Anthropic"),
+            (LlmProviderKind::Local, None, "local"),
+        ];
+        for (kind, key, name) in cases {
+            let provider = build_provider(&config(kind, key)).unwrap();
+            assert_eq!(provider.name(), name);
+            assert!(provider.ensure_available().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn unavailable_provider_fails_completions() {
+        let provider = build_provider(&config(LlmProviderKind::Anthropic, None)).unwrap();
+        let request = CompletionRequest { system: SYSTEM_PROMPT, prompt: "hi", site_url: None, site_name: None };
+        assert!(matches!(provider.complete(&request).await, Err(AppError::LlmUnavailable(_))));
+    }
+}
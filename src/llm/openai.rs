@@ -0,0 +1,232 @@
+use async_trait::async_trait;
+use reqwest::RequestBuilder;
+use serde::Serialize;
+use std::time::Duration;
+use crate::config::LlmConfig;
+use crate::error::{Result, AppError};
+use super::{Completion, CompletionRequest, DeltaCallback, LlmProvider, TokenUsage, CLIENT, require_api_key, send_with_retry, stream_with_retry};
+
+#[derive(Serialize, Debug)]
+struct Message<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize, Debug)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<Message<'a>>,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Serialize, Debug)]
+struct StreamOptions {
+    // Asks for a final chunk carrying the token usage, which OpenAI only sends on request
+    include_usage: bool,
+}
+
+/// Provider for any endpoint speaking the OpenAI chat completions protocol.
+///
+/// This covers OpenRouter, OpenAI itself and local servers such as Ollama or
+/// llama.cpp, which expose the same API under `/v1`.
+pub struct OpenAiCompatibleProvider {
+    name: &'static str,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    max_tokens: u32,
+    temperature: f32,
+    // OpenRouter uses the HTTP-Referer and X-Title headers for attribution
+    send_attribution: bool,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn openrouter(config: &LlmConfig) -> Result<Self> {
+        Ok(Self {
+            name: "OpenRouter",
+            api_key: Some(require_api_key(config)?),
+            send_attribution: true,
+            ..Self::from_config(config)
+        })
+    }
+
+    pub fn openai(config: &LlmConfig) -> Result<Self> {
+        Ok(Self {
+            name: "OpenAI",
+            api_key: Some(require_api_key(config)?),
+            ..Self::from_config(config)
+        })
+    }
+
+    pub fn local(config: &LlmConfig) -> Self {
+        Self {
+            name: "local",
+            ..Self::from_config(config)
+        }
+    }
+
+    fn from_config(config: &LlmConfig) -> Self {
+        Self {
+            name: "OpenAI-compatible",
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            api_key: config.api_key.clone(),
+            model: config.model.clone(),
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+            send_attribution: false,
+        }
+    }
+
+    fn chat_request<'a>(&'a self, request: &CompletionRequest<'a>, stream: bool) -> ChatRequest<'a> {
+        let body = ChatRequest {
+            model: &self.model,
+            messages: vec![
+                Message { role: "system", content: request.system },
+                Message { role: "user", content: request.prompt },
+            ],
+            max_tokens: Some(self.max_tokens),
+            temperature: Some(self.temperature),
+            stream,
+            stream_options: stream.then_some(StreamOptions { include_usage: true }),
+        };
+
+        println!("Request payload: model={}, max_tokens={}, temperature={}, stream={}",
+                 body.model, body.max_tokens.unwrap_or(0), body.temperature.unwrap_or(0.0), body.stream);
+
+        body
+    }
+
+    fn post(&self, body: &ChatRequest<'_>, request: &CompletionRequest<'_>, timeout: Duration) -> RequestBuilder {
+        let mut req = CLIENT
+            .post(format!("{}/chat/completions", self.base_url))
+            .timeout(timeout)
+            .json(body);
+
+        if let Some(key) = &self.api_key {
+            req = req.bearer_auth(key);
+        }
+
+        // Add optional headers if provided
+        if self.send_attribution {
+            if let Some(url) = request.site_url {
+                req = req.header("HTTP-Referer", url);
+            }
+            if let Some(name) = request.site_name {
+                req = req.header("X-Title", name);
+            }
+        }
+
+        req
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiCompatibleProvider {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, request: &CompletionRequest<'_>) -> Result<Completion> {
+        println!("Preparing LLM request");
+        println!("Prompt size: {} chars", request.prompt.len());
+
+        let body = self.chat_request(request, false);
+        let json = send_with_retry(self.name, || self.post(&body, request, Duration::from_secs(60))).await?;
+
+        match json["choices"][0]["message"]["content"].as_str() {
+            Some(content) => {
+                let usage = TokenUsage::from_json(&json["usage"]).unwrap_or_default();
+                println!("Successfully received LLM response ({} chars, {} tokens)", content.len(), usage.total_tokens);
+                let model = json["model"].as_str().map(str::to_string);
+                Ok(Completion { text: content.to_string(), usage, model })
+            },
+            None => {
+                println!("Invalid response format: {:?}", json);
+                Err(AppError::llm("Invalid response format".to_string()))
+            }
+        }
+    }
+
+    async fn complete_stream(&self, request: &CompletionRequest<'_>, on_delta: DeltaCallback<'_>) -> Result<Completion> {
+        println!("Preparing streaming LLM request");
+        println!("Prompt size: {} chars", request.prompt.len());
+
+        let body = self.chat_request(request, true);
+        let mut content = String::new();
+        let mut usage = TokenUsage::default();
+        let mut model = None;
+
+        // Streams may legitimately run longer than a buffered completion
+        stream_with_retry(self.name, || self.post(&body, request, Duration::from_secs(180)), |data| {
+            if data == "[DONE]" {
+                return Ok(false);
+            }
+
+            let json: serde_json::Value = serde_json::from_str(data)
+                .map_err(|e| AppError::llm(format!("Invalid stream chunk: {}", e)))?;
+
+            if let Some(message) = json["error"]["message"].as_str() {
+                return Err(AppError::llm(format!("Stream error: {}", message)));
+            }
+
+            if let Some(delta) = json["choices"][0]["delta"]["content"].as_str()
+                && !delta.is_empty()
+            {
+                content.push_str(delta);
+                on_delta(delta);
+            }
+
+            if let Some(served_by) = json["model"].as_str() {
+                model = Some(served_by.to_string());
+            }
+
+            // The usage arrives with the last chunk, which has no content
+            if let Some(reported) = TokenUsage::from_json(&json["usage"]) {
+                usage = reported;
+            }
+
+            Ok(true)
+        }).await?;
+
+        println!("Successfully streamed LLM response ({} chars, {} tokens)", content.len(), usage.total_tokens);
+        Ok(Completion { text: content, usage, model })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LlmProviderKind;
+    use crate::llm::tests::{collect_stream, config, serve_events};
+
+    #[tokio::test]
+    async fn maps_stream_chunks_to_tokens() {
+        let body = concat!(
+            ": OPENROUTER PROCESSING\n\n",
+            "data: {\"model\":\"gpt-4o-2024-08-06\",\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":10,\"completion_tokens\":2,\"total_tokens\":12}}\n\n",
+            "data: [DONE]\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"after the end\"}}]}\n\n",
+        );
+        let provider = OpenAiCompatibleProvider::local(&LlmConfig {
+            base_url: serve_events(body).await,
+            ..config(LlmProviderKind::Local, None)
+        });
+
+        let (completion, deltas) = collect_stream(&provider).await;
+        assert_eq!(deltas, vec!["Hel", "lo"]);
+        assert_eq!(completion.text, "Hello");
+        assert_eq!(completion.usage.total_tokens, 12);
+        assert_eq!(completion.model.as_deref(), Some("gpt-4o-2024-08-06"));
+    }
+}
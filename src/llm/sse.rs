@@ -0,0 +1,79 @@
+/// Incremental parser for `text/event-stream` bodies.
+///
+/// Network chunks may split lines (and UTF-8 sequences) anywhere, so bytes
+/// are buffered until a full line is available. Only `data:` fields are
+/// kept; comments such as OpenRouter's `: OPENROUTER PROCESSING` keep-alives
+/// and `event:`/`id:` fields are ignored.
+#[derive(Default)]
+pub(crate) struct SseParser {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Feeds a chunk of the body and returns the data of every event it completed
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                // A blank line dispatches the event
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+            } else if let Some(value) = line.strip_prefix("data:") {
+                self.data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_events_split_across_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b"data: {\"text\":\"Hel").is_empty());
+        assert!(parser.feed(b"lo\"}\n").is_empty());
+        assert_eq!(parser.feed(b"\ndata: next\n\n"), vec!["{\"text\":\"Hello\"}", "next"]);
+    }
+
+    #[test]
+    fn joins_utf8_sequences_split_across_chunks() {
+        let bytes = "data: café ✓\n\n".as_bytes();
+        // Split inside the three bytes of the check mark
+        let split = bytes.len() - 4;
+        let mut parser = SseParser::default();
+        assert!(parser.feed(&bytes[..split]).is_empty());
+        assert_eq!(parser.feed(&bytes[split..]), vec!["café ✓"]);
+    }
+
+    #[test]
+    fn ignores_comments_and_other_fields() {
+        let mut parser = SseParser::default();
+        let events = parser.feed(b": OPENROUTER PROCESSING\n\nevent: delta\nid: 7\ndata: token\n\n");
+        assert_eq!(events, vec!["token"]);
+    }
+
+    #[test]
+    fn joins_multi_line_data_with_newlines() {
+        let mut parser = SseParser::default();
+        assert_eq!(parser.feed(b"data: first\ndata:second\n\n"), vec!["first\nsecond"]);
+    }
+
+    #[test]
+    fn accepts_crlf_line_endings() {
+        let mut parser = SseParser::default();
+        assert_eq!(parser.feed(b"data: one\r\n\r\ndata: [DONE]\r\n\r\n"), vec!["one", "[DONE]"]);
+    }
+}
@@ -1,10 +1,15 @@
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use std::collections::HashMap;
-use std::sync::Mutex;
 use rust_web_scrapper::{
     config::Config,
     api::routes::create_router,
+    auth::KeyStore,
+    llm::build_provider,
+    cache::build_store,
+    scraper::{Extractor, Fetcher},
+    jobs::JobManager,
+    limits::ClientLimiter,
+    singleflight::SingleFlight,
     AppState,
 };
 
@@ -15,10 +20,42 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let server_addr = config.server_addr;
     println!("Starting server on {}", server_addr);
     
+    // Create the LLM provider selected in the configuration
+    let llm = build_provider(&config.llm)?;
+    
+    // Create the HTTP client used for user-supplied URLs
+    let fetcher = Arc::new(Fetcher::new(&config.fetch)?);
+    
+    // Compile the noise selectors used when extracting page text
+    let extractor = Arc::new(Extractor::new(&config.extract)?);
+    
+    // Open the cache backend selected in the configuration
+    let stores = build_store(&config.cache)?;
+    
+    // Create the worker pool for asynchronous jobs
+    let jobs = Arc::new(JobManager::new(&config.jobs));
+    
+    // Load the API keys clients authenticate with
+    let keys = Arc::new(KeyStore::new(&config.auth, &config.limits));
+    let limits = Arc::new(match stores.usage {
+        Some(store) => ClientLimiter::with_store(config.pricing.clone(), store)?,
+        None => ClientLimiter::new(config.pricing.clone()),
+    });
+    
+    // Coalesced work is abandoned once no caller could still be waiting for it
+    let inflight_deadline = config.jobs.timeout.max(config.request_timeout);
+
     // Create application state
     let app_state = AppState {
         config: Arc::new(config),
-        cache: Arc::new(Mutex::new(HashMap::new())),
+        llm,
+        fetcher,
+        extractor,
+        cache: stores.cache,
+        jobs,
+        keys,
+        limits,
+        inflight: Arc::new(SingleFlight::new(inflight_deadline)),
     };
     
     // Build the router with routes
@@ -0,0 +1,203 @@
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use crate::encoding::DetectedEncoding;
+use crate::text::clean_chars;
+
+static TITLE_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("title").expect("Failed to parse title selector")
+});
+
+static META_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("meta[content]").expect("Failed to parse meta selector")
+});
+
+static HTML_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("html[lang]").expect("Failed to parse html selector")
+});
+
+static CANONICAL_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("link[rel~=canonical][href]").expect("Failed to parse canonical selector")
+});
+
+static ICON_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("link[rel~=icon][href], link[rel~=apple-touch-icon][href]").expect("Failed to parse icon selector")
+});
+
+static TIME_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("time[datetime]").expect("Failed to parse time selector")
+});
+
+// OpenGraph and Twitter Card properties whose values are URLs
+const URL_PROPERTIES: &[&str] = &[
+    "url", "image", "image:url", "image:secure_url", "image:src", "video", "video:url", "audio",
+];
+
+/// Information about a page itself, taken from its `<head>` and markup
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub author: Option<String>,
+    /// Publication date exactly as the page states it
+    pub published_at: Option<String>,
+    /// Last modification date exactly as the page states it
+    pub modified_at: Option<String>,
+    pub lang: Option<String>,
+    /// The page's declared canonical URL, made absolute
+    pub canonical_url: Option<String>,
+    pub site_name: Option<String>,
+    pub favicon: Option<String>,
+    /// OpenGraph properties without the `og:` prefix, e.g. `title`, `image`, `type`
+    pub open_graph: BTreeMap<String, String>,
+    /// Twitter Card properties without the `twitter:` prefix, e.g. `card`, `site`
+    pub twitter: BTreeMap<String, String>,
+    /// Number of pages, for PDF documents
+    pub page_count: Option<usize>,
+    /// Character encoding a fetched HTML page was decoded from
+    pub encoding: Option<DetectedEncoding>,
+}
+
+/// Extracts page metadata from a full HTML document.
+///
+/// Dedicated tags win over their OpenGraph counterparts, so `<title>` is used
+/// before `og:title`. Relative URLs are resolved against `page_url` when given.
+pub fn extract_metadata(html: &str, page_url: Option<&str>) -> PageMetadata {
+    let document = Html::parse_document(html);
+    let base = page_url.and_then(|url| Url::parse(url).ok());
+    let resolve = |href: &str| match &base {
+        Some(base) => base.join(href).map(|url| url.to_string()).unwrap_or_else(|_| href.to_string()),
+        None => href.to_string(),
+    };
+
+    // Meta tags keyed by lowercased `name`, `property`, `http-equiv` or `itemprop`; the first occurrence wins
+    let mut meta: BTreeMap<String, String> = BTreeMap::new();
+    for element in document.select(&META_SELECTOR) {
+        let Some(content) = element.value().attr("content").and_then(clean_value) else { continue };
+        for key in ["name", "property", "http-equiv", "itemprop"] {
+            if let Some(key) = element.value().attr(key) {
+                meta.entry(key.trim().to_ascii_lowercase()).or_insert_with(|| content.clone());
+            }
+        }
+    }
+    let first = |keys: &[&str]| keys.iter().find_map(|key| meta.get(*key).cloned());
+
+    let prefixed = |prefix: &str| -> BTreeMap<String, String> {
+        meta.iter()
+            .filter_map(|(key, value)| {
+                let property = key.strip_prefix(prefix)?;
+                let value = if URL_PROPERTIES.contains(&property) { resolve(value) } else { value.clone() };
+                Some((property.to_string(), value))
+            })
+            .collect()
+    };
+
+    let title = document.select(&TITLE_SELECTOR)
+        .next()
+        .and_then(|element| clean_value(&element.text().collect::<String>()))
+        .or_else(|| first(&["og:title", "twitter:title"]));
+
+    let published_at = first(&[
+        "article:published_time", "datepublished", "date", "pubdate", "publishdate", "dc.date", "dc.date.issued",
+    ]).or_else(|| first_time(&document, "datePublished"));
+
+    let modified_at = first(&["article:modified_time", "og:updated_time", "datemodified", "last-modified"])
+        .or_else(|| first_time(&document, "dateModified"));
+
+    let lang = document.select(&HTML_SELECTOR)
+        .next()
+        .and_then(|element| element.value().attr("lang"))
+        .and_then(clean_value)
+        .or_else(|| first(&["content-language", "og:locale"]));
+
+    PageMetadata {
+        title,
+        description: first(&["description", "og:description", "twitter:description"]),
+        author: first(&["author", "article:author", "dc.creator", "twitter:creator"]),
+        published_at,
+        modified_at,
+        lang,
+        canonical_url: first_href(&document, &CANONICAL_SELECTOR).map(|href| resolve(&href)),
+        site_name: first(&["og:site_name", "application-name", "apple-mobile-web-app-title"]),
+        favicon: first_href(&document, &ICON_SELECTOR).map(|href| resolve(&href)),
+        open_graph: prefixed("og:"),
+        twitter: prefixed("twitter:"),
+        page_count: None,
+        encoding: None,
+    }
+}
+
+fn first_href(document: &Html, selector: &Selector) -> Option<String> {
+    document.select(selector)
+        .filter_map(|element| element.value().attr("href"))
+        .find_map(clean_value)
+}
+
+/// The `datetime` of the first `<time itemprop=...>`, e.g. microdata `datePublished`
+fn first_time(document: &Html, itemprop: &str) -> Option<String> {
+    document.select(&TIME_SELECTOR)
+        .filter(|element| element.value().attr("itemprop") == Some(itemprop))
+        .find_map(|element| element.value().attr("datetime").and_then(clean_value))
+}
+
+fn clean_value(value: &str) -> Option<String> {
+    let value = clean_chars(value).split_whitespace().collect::<Vec<_>>().join(" ");
+    (!value.is_empty()).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_tag_wins_over_open_graph() {
+        let html = "<html><head><meta property=\"og:title\" content=\"OG title\"><title> Page\n title </title></head></html>";
+        let metadata = extract_metadata(html, None);
+        assert_eq!(metadata.title.as_deref(), Some("Page title"));
+        assert_eq!(metadata.open_graph.get("title").map(String::as_str), Some("OG title"));
+
+        let html = "<html><head><title> </title><meta property=\"og:title\" content=\"OG title\"></head></html>";
+        assert_eq!(extract_metadata(html, None).title.as_deref(), Some("OG title"));
+    }
+
+    #[test]
+    fn resolves_relative_urls_against_the_page() {
+        let html = "<html><head><link rel=\"shortcut icon\" href=\"/favicon.ico\">\
+                    <link rel=\"canonical\" href=\"../post\"><meta property=\"og:image\" content=\"img/cover.png\"></head></html>";
+        let metadata = extract_metadata(html, Some("https://example.com/blog/2024/entry"));
+        assert_eq!(metadata.favicon.as_deref(), Some("https://example.com/favicon.ico"));
+        assert_eq!(metadata.canonical_url.as_deref(), Some("https://example.com/blog/post"));
+        assert_eq!(metadata.open_graph.get("image").map(String::as_str), Some("https://example.com/blog/2024/img/cover.png"));
+    }
+
+    #[test]
+    fn published_date_falls_back_to_time_elements() {
+        let html = "<html><head><meta property=\"article:published_time\" content=\"2024-03-01T10:00:00Z\"></head>\
+                    <body><time itemprop=\"datePublished\" datetime=\"2023-01-01\">Jan 1</time></body></html>";
+        assert_eq!(extract_metadata(html, None).published_at.as_deref(), Some("2024-03-01T10:00:00Z"));
+
+        let html = "<html><body><time datetime=\"2020-05-05\">Other</time>\
+                    <time itemprop=\"datePublished\" datetime=\"2023-01-01\">Jan 1</time>\
+                    <time itemprop=\"dateModified\" datetime=\"2023-02-01\">Feb 1</time></body></html>";
+        let metadata = extract_metadata(html, None);
+        assert_eq!(metadata.published_at.as_deref(), Some("2023-01-01"));
+        assert_eq!(metadata.modified_at.as_deref(), Some("2023-02-01"));
+    }
+
+    #[test]
+    fn missing_fields_are_none() {
+        let metadata = extract_metadata("<html><body><p>Just text</p></body></html>", Some("https://example.com/"));
+        assert!(metadata.title.is_none());
+        assert!(metadata.description.is_none());
+        assert!(metadata.author.is_none());
+        assert!(metadata.published_at.is_none());
+        assert!(metadata.modified_at.is_none());
+        assert!(metadata.lang.is_none());
+        assert!(metadata.canonical_url.is_none());
+        assert!(metadata.site_name.is_none());
+        assert!(metadata.favicon.is_none());
+        assert!(metadata.open_graph.is_empty() && metadata.twitter.is_empty());
+    }
+}
@@ -0,0 +1,505 @@
+use pdf_extract::{decode_text_string, Dictionary, Document, MediaBox, Object, OutputDev, OutputError, Transform};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use crate::error::{AppError, Result};
+use crate::text::{clean_chars, normalize_text};
+
+// Every PDF starts with this header, although readers accept junk before it within the first kilobyte
+const PDF_MAGIC: &[u8] = b"%PDF-";
+const MAGIC_SEARCH_BYTES: usize = 1024;
+
+// Lines set this much larger than the body text are headings
+const HEADING_SIZE_RATIO: f64 = 1.15;
+// Longer lines are paragraphs in a large font rather than headings
+const MAX_HEADING_CHARS: usize = 120;
+// Deeper heading levels are not distinguished
+const MAX_HEADING_LEVEL: usize = 3;
+
+/// Whether `bytes` hold a PDF document, judged by its header
+pub fn is_pdf(bytes: &[u8]) -> bool {
+    bytes.windows(PDF_MAGIC.len())
+        .take(MAGIC_SEARCH_BYTES)
+        .any(|window| window == PDF_MAGIC)
+}
+
+/// Text and document information of a PDF
+pub struct PdfDocument {
+    /// Plain text, with paragraphs separated by blank lines
+    pub text: String,
+    /// The same text as Markdown, with headings and a `<!-- page N -->`
+    /// marker at the start of every page
+    pub markdown: String,
+    pub page_count: usize,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub created_at: Option<String>,
+    pub modified_at: Option<String>,
+    pub lang: Option<String>,
+}
+
+/// Extracts the text of a PDF document page by page.
+///
+/// Headings are recognized by font size: lines set noticeably larger than the
+/// body text become Markdown headings, the largest size first. This is CPU
+/// bound, so async callers should run it on a blocking thread.
+pub fn extract_pdf(bytes: &[u8]) -> Result<PdfDocument> {
+    // pdf-extract panics on some malformed files instead of returning an error
+    panic::catch_unwind(AssertUnwindSafe(|| extract(bytes)))
+        .unwrap_or_else(|_| Err(AppError::ParseError("The PDF document could not be read".to_string())))
+}
+
+fn extract(bytes: &[u8]) -> Result<PdfDocument> {
+    let mut document = Document::load_mem(bytes)
+        .map_err(|e| AppError::ParseError(format!("Invalid PDF document: {}", e)))?;
+    if document.is_encrypted() {
+        // Many PDFs are encrypted with an empty password only to restrict printing or copying
+        document.decrypt("")
+            .map_err(|_| AppError::ParseError("The PDF document is password protected".to_string()))?;
+    }
+
+    let mut collector = LineCollector::default();
+    pdf_extract::output_doc(&document, &mut collector)
+        .map_err(|e| AppError::ParseError(format!("Failed to extract PDF text: {}", e)))?;
+    let pages = collector.pages;
+
+    let body_size = body_font_size(&pages);
+    let heading_sizes = heading_sizes(&pages, body_size);
+
+    let mut text = String::new();
+    let mut markdown = String::new();
+    let mut first_heading = None;
+    for (index, lines) in pages.iter().enumerate() {
+        if pages.len() > 1 {
+            markdown.push_str(&format!("\n\n<!-- page {} -->", index + 1));
+        }
+        for block in blocks(lines, &heading_sizes, body_size) {
+            text.push_str("\n\n");
+            text.push_str(&block.text);
+            markdown.push_str("\n\n");
+            match block.level {
+                Some(level) => {
+                    first_heading.get_or_insert_with(|| block.text.clone());
+                    markdown.push_str(&format!("{} {}", "#".repeat(level), block.text));
+                },
+                None => markdown.push_str(&block.text),
+            }
+        }
+    }
+
+    let info = trailer_dictionary(&document, b"Info");
+    let catalog = trailer_dictionary(&document, b"Root");
+    let info_text = |key: &[u8]| info.and_then(|info| text_entry(&document, info, key));
+
+    Ok(PdfDocument {
+        text: normalize_text(&text),
+        markdown: clean_chars(markdown.trim()),
+        page_count: pages.len(),
+        title: info_text(b"Title").or(first_heading),
+        author: info_text(b"Author"),
+        created_at: info_text(b"CreationDate").map(|date| format_pdf_date(&date)),
+        modified_at: info_text(b"ModDate").map(|date| format_pdf_date(&date)),
+        lang: catalog.and_then(|catalog| text_entry(&document, catalog, b"Lang")),
+    })
+}
+
+/// A line of text with the largest font size used on it
+struct Line {
+    text: String,
+    size: f64,
+    /// Whether a vertical gap separates the line from the one before
+    starts_paragraph: bool,
+}
+
+/// Receives positioned characters from pdf-extract and groups them into lines,
+/// following the spacing rules of its plain text output
+struct LineCollector {
+    pages: Vec<Vec<Line>>,
+    page_height: f64,
+    line: Option<Line>,
+    starts_word: bool,
+    last_end: f64,
+    last_y: f64,
+}
+
+impl Default for LineCollector {
+    fn default() -> Self {
+        LineCollector {
+            pages: Vec::new(),
+            page_height: 0.0,
+            line: None,
+            starts_word: false,
+            last_end: f64::MAX,
+            last_y: 0.0,
+        }
+    }
+}
+
+impl LineCollector {
+    fn finish_line(&mut self) {
+        if let Some(line) = self.line.take()
+            && !line.text.trim().is_empty()
+            && let Some(page) = self.pages.last_mut()
+        {
+            page.push(Line { text: line.text.split_whitespace().collect::<Vec<_>>().join(" "), ..line });
+        }
+    }
+}
+
+impl OutputDev for LineCollector {
+    fn begin_page(&mut self, _page_num: u32, media_box: &MediaBox, _art_box: Option<(f64, f64, f64, f64)>) -> std::result::Result<(), OutputError> {
+        self.pages.push(Vec::new());
+        self.page_height = media_box.ury - media_box.lly;
+        self.last_end = f64::MAX;
+        self.last_y = 0.0;
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> std::result::Result<(), OutputError> {
+        self.finish_line();
+        Ok(())
+    }
+
+    fn output_character(&mut self, trm: &Transform, width: f64, _spacing: f64, font_size: f64, char: &str) -> std::result::Result<(), OutputError> {
+        // The effective size is the side of a square with the area of the transformed glyph box
+        let scaled_x = font_size * trm.m11 + font_size * trm.m21;
+        let scaled_y = font_size * trm.m12 + font_size * trm.m22;
+        let size = (scaled_x * scaled_y).abs().sqrt();
+        // PDF coordinates grow upwards; flip them so that y grows down the page
+        let (x, y) = (trm.m31, self.page_height - trm.m32);
+
+        if self.starts_word {
+            let gap = (y - self.last_y).abs();
+            if gap > size * 1.5 {
+                self.finish_line();
+                self.line = Some(Line { text: String::new(), size, starts_paragraph: true });
+            } else if x < self.last_end && gap > size * 0.5 {
+                self.finish_line();
+                self.line = Some(Line { text: String::new(), size, starts_paragraph: false });
+            } else if x > self.last_end + size * 0.1
+                && let Some(line) = &mut self.line
+            {
+                line.text.push(' ');
+            }
+        }
+
+        let line = self.line.get_or_insert_with(|| Line { text: String::new(), size, starts_paragraph: true });
+        line.text.push_str(char);
+        if !char.trim().is_empty() {
+            line.size = line.size.max(size);
+        }
+
+        self.starts_word = false;
+        self.last_y = y;
+        self.last_end = x + width * size;
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> std::result::Result<(), OutputError> {
+        self.starts_word = true;
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> std::result::Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> std::result::Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+/// Font sizes are compared in half points, which absorbs rounding in the transforms
+fn size_key(size: f64) -> i64 {
+    (size * 2.0).round() as i64
+}
+
+/// The font size that most of the text is set in
+fn body_font_size(pages: &[Vec<Line>]) -> f64 {
+    let mut chars_by_size: HashMap<i64, usize> = HashMap::new();
+    for line in pages.iter().flatten() {
+        *chars_by_size.entry(size_key(line.size)).or_default() += line.text.chars().count();
+    }
+    chars_by_size.into_iter()
+        .max_by_key(|(key, chars)| (*chars, -key))
+        .map(|(key, _)| key as f64 / 2.0)
+        .unwrap_or(0.0)
+}
+
+/// The sizes used for headings, largest first; the position is the heading level minus one
+fn heading_sizes(pages: &[Vec<Line>], body_size: f64) -> Vec<i64> {
+    let mut sizes: Vec<i64> = pages.iter()
+        .flatten()
+        .filter(|line| is_heading_candidate(line, body_size))
+        .map(|line| size_key(line.size))
+        .collect();
+    sizes.sort_unstable_by(|a, b| b.cmp(a));
+    sizes.dedup();
+    sizes
+}
+
+fn is_heading_candidate(line: &Line, body_size: f64) -> bool {
+    body_size > 0.0
+        && line.size >= body_size * HEADING_SIZE_RATIO
+        && line.text.chars().count() <= MAX_HEADING_CHARS
+        && line.text.chars().any(char::is_alphabetic)
+}
+
+/// A paragraph or heading of one page
+struct Block {
+    text: String,
+    level: Option<usize>,
+}
+
+/// Joins the lines of a page into paragraphs and headings. Lines set in a
+/// heading size are only headings when they could be one on their own.
+fn blocks(lines: &[Line], heading_sizes: &[i64], body_size: f64) -> Vec<Block> {
+    let level_of = |line: &Line| {
+        if !is_heading_candidate(line, body_size) {
+            return None;
+        }
+        heading_sizes.iter()
+            .position(|size| *size == size_key(line.size))
+            .map(|index| (index + 1).min(MAX_HEADING_LEVEL))
+    };
+
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut previous_size = None;
+    for line in lines {
+        let level = level_of(line);
+        // Headings wrapped over several lines keep their size; anything else starts a new block
+        let continues = !line.starts_paragraph
+            && previous_size == Some(size_key(line.size))
+            && blocks.last().is_some_and(|block| block.level == level);
+        previous_size = Some(size_key(line.size));
+
+        match blocks.last_mut() {
+            Some(block) if continues => join_line(&mut block.text, &line.text),
+            _ => blocks.push(Block { text: line.text.clone(), level }),
+        }
+    }
+    blocks
+}
+
+/// Appends a wrapped line, undoing hyphenation at the line break
+fn join_line(text: &mut String, line: &str) {
+    let hyphenated = text.ends_with('-')
+        && text.chars().rev().nth(1).is_some_and(char::is_alphabetic)
+        && line.chars().next().is_some_and(char::is_lowercase);
+    if hyphenated {
+        text.pop();
+    } else {
+        text.push(' ');
+    }
+    text.push_str(line);
+}
+
+/// A dictionary referenced from the trailer, such as `Info` or `Root`
+fn trailer_dictionary<'a>(document: &'a Document, key: &[u8]) -> Option<&'a Dictionary> {
+    let object = document.trailer.get(key).ok()?;
+    document.dereference(object).ok()?.1.as_dict().ok()
+}
+
+fn text_entry(document: &Document, dictionary: &Dictionary, key: &[u8]) -> Option<String> {
+    let object: &Object = dictionary.get(key).ok()?;
+    let object = document.dereference(object).ok()?.1;
+    let text = decode_text_string(object).ok()?;
+    let text = clean_chars(&text).split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// Converts a PDF date such as `D:20230115093000+01'00'` to ISO 8601, keeping
+/// only the parts that are present. Values that do not look like a PDF date
+/// are returned unchanged.
+fn format_pdf_date(date: &str) -> String {
+    let raw = date.trim();
+    let value = raw.strip_prefix("D:").unwrap_or(raw);
+    let digits: String = value.chars().take_while(char::is_ascii_digit).collect();
+    if digits.len() < 4 {
+        return raw.to_string();
+    }
+
+    let mut formatted = digits[..4].to_string();
+    for range in [4..6, 6..8] {
+        let Some(part) = digits.get(range) else { return formatted };
+        formatted.push('-');
+        formatted.push_str(part);
+    }
+    // A time needs at least hours and minutes to be meaningful
+    let (Some(hours), Some(minutes)) = (digits.get(8..10), digits.get(10..12)) else { return formatted };
+    let seconds = digits.get(12..14).unwrap_or("00");
+    formatted.push_str(&format!("T{}:{}:{}", hours, minutes, seconds));
+
+    let offset = &value[digits.len()..];
+    if offset.starts_with('Z') {
+        formatted.push('Z');
+    } else if let Some(sign) = offset.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let offset_digits: String = offset.chars().filter(char::is_ascii_digit).collect();
+        if let (Some(offset_hours), Some(offset_minutes)) = (offset_digits.get(0..2), offset_digits.get(2..4)) {
+            formatted.push_str(&format!("{}{}:{}", sign, offset_hours, offset_minutes));
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PDF with the given page content streams, set in Helvetica as font `F1`
+    fn fixture(pages: &[&str]) -> Vec<u8> {
+        let first_page = 5;
+        let kids: Vec<String> = (0..pages.len()).map(|i| format!("{} 0 R", first_page + 2 * i)).collect();
+        let mut objects = vec![
+            "<< /Type /Catalog /Pages 2 0 R /Lang (en-GB) >>".to_string(),
+            format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>".to_string(),
+            "<< /Title (Annual  report) /Author (Jane Doe) /CreationDate (D:20240102030405+01'00') >>".to_string(),
+        ];
+        for (i, content) in pages.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Resources << /Font << /F1 3 0 R >> >> /Contents {} 0 R >>",
+                first_page + 2 * i + 1,
+            ));
+            objects.push(format!("<< /Length {} >>\nstream\n{}\nendstream", content.len(), content));
+        }
+
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).bytes());
+        for offset in offsets {
+            pdf.extend(format!("{:010} 00000 n \n", offset).bytes());
+        }
+        pdf.extend(format!(
+            "trailer\n<< /Size {} /Root 1 0 R /Info 4 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1, xref,
+        ).bytes());
+        pdf
+    }
+
+    /// A content stream line of `text` at size `size`, `y` points from the bottom of the page
+    fn text(size: u32, y: u32, text: &str) -> String {
+        format!("BT /F1 {} Tf 72 {} Td ({}) Tj ET", size, y, text)
+    }
+
+    fn line(text: &str, size: f64, starts_paragraph: bool) -> Line {
+        Line { text: text.to_string(), size, starts_paragraph }
+    }
+
+    #[test]
+    fn is_pdf_finds_the_header_near_the_start() {
+        assert!(is_pdf(b"%PDF-1.7\n"));
+        assert!(is_pdf(b"\xef\xbb\xbfjunk before %PDF-1.4"));
+        assert!(!is_pdf(b"<html><body>%PD</body></html>"));
+        let mut late = vec![b' '; MAGIC_SEARCH_BYTES + 10];
+        late.extend(b"%PDF-1.4");
+        assert!(!is_pdf(&late));
+    }
+
+    #[test]
+    fn pdf_dates_become_iso_8601() {
+        assert_eq!(format_pdf_date("D:20240102030405+01'00'"), "2024-01-02T03:04:05+01:00");
+        assert_eq!(format_pdf_date("D:20240102030405-0530"), "2024-01-02T03:04:05-05:30");
+        assert_eq!(format_pdf_date("D:20240102030405Z"), "2024-01-02T03:04:05Z");
+        assert_eq!(format_pdf_date("D:202401020304"), "2024-01-02T03:04:00");
+        assert_eq!(format_pdf_date("20240102"), "2024-01-02");
+        assert_eq!(format_pdf_date("D:2024"), "2024");
+        // Hours without minutes say too little to keep
+        assert_eq!(format_pdf_date("D:2024010203"), "2024-01-02");
+        // An offset without minutes is dropped rather than guessed
+        assert_eq!(format_pdf_date("D:20240102030405+01"), "2024-01-02T03:04:05");
+        assert_eq!(format_pdf_date(" last Tuesday "), "last Tuesday");
+        assert_eq!(format_pdf_date("D:24"), "D:24");
+    }
+
+    #[test]
+    fn larger_short_lines_become_headings_by_size() {
+        let body = "Revenue grew in every region this year, which the board puts down to";
+        let lines = vec![
+            line("Annual report", 24.0, true),
+            line("Results", 18.0, true),
+            line(body, 12.0, true),
+            line(body, 12.0, false),
+            line("new products.", 12.0, false),
+            line("2024", 18.0, true),
+            line(&"A whole paragraph set in a large font. ".repeat(4), 18.0, true),
+            line("Outlook", 12.5, true),
+            line(body, 12.0, true),
+        ];
+        let pages = vec![lines];
+        let body_size = body_font_size(&pages);
+        assert_eq!(body_size, 12.0);
+        assert_eq!(heading_sizes(&pages, body_size), vec![48, 36]);
+
+        let blocks = blocks(&pages[0], &heading_sizes(&pages, body_size), body_size);
+        let levels: Vec<_> = blocks.iter().map(|block| (block.text.as_str(), block.level)).collect();
+        assert_eq!(levels[0], ("Annual report", Some(1)));
+        assert_eq!(levels[1], ("Results", Some(2)));
+        assert_eq!(levels[2], (format!("{} {} new products.", body, body).as_str(), None));
+        // Lines without letters, overlong lines and sizes just above the body text are not headings
+        assert_eq!(levels[3], ("2024", None));
+        assert_eq!(levels[4].1, None);
+        assert_eq!(levels[5], ("Outlook", None));
+        assert_eq!(levels.len(), 7);
+    }
+
+    #[test]
+    fn wrapped_lines_are_joined_and_dehyphenated() {
+        let mut text = "An inter-".to_string();
+        join_line(&mut text, "national deal");
+        assert_eq!(text, "An international deal");
+        let mut text = "Pages 10-".to_string();
+        join_line(&mut text, "12");
+        assert_eq!(text, "Pages 10- 12");
+        let mut text = "Self-".to_string();
+        join_line(&mut text, "Service");
+        assert_eq!(text, "Self- Service");
+    }
+
+    #[test]
+    fn pages_are_marked_and_headings_kept() {
+        let body = "Revenue grew in every region this year, which the board puts down to new products.";
+        let pages = [
+            [text(24, 720, "Annual report"), text(12, 690, body)].join("\n"),
+            [text(18, 720, "Outlook"), text(12, 690, body)].join("\n"),
+        ];
+        let document = extract_pdf(&fixture(&[&pages[0], &pages[1]])).unwrap();
+
+        assert_eq!(document.page_count, 2);
+        assert_eq!(
+            document.markdown,
+            format!("<!-- page 1 -->\n\n# Annual report\n\n{}\n\n<!-- page 2 -->\n\n## Outlook\n\n{}", body, body),
+        );
+        assert!(document.text.starts_with("Annual report\n\n"));
+        assert!(!document.text.contains("<!--"));
+        assert_eq!(document.title.as_deref(), Some("Annual report"));
+        assert_eq!(document.author.as_deref(), Some("Jane Doe"));
+        assert_eq!(document.created_at.as_deref(), Some("2024-01-02T03:04:05+01:00"));
+        assert_eq!(document.modified_at, None);
+        assert_eq!(document.lang.as_deref(), Some("en-GB"));
+    }
+
+    #[test]
+    fn single_pages_have_no_marker() {
+        let document = extract_pdf(&fixture(&[&text(12, 720, "Just one line")])).unwrap();
+        assert_eq!(document.markdown, "Just one line");
+    }
+
+    #[test]
+    fn malformed_documents_are_errors() {
+        assert!(matches!(extract_pdf(b"%PDF-1.4\nnot really a PDF"), Err(AppError::ParseError(_))));
+        assert!(matches!(extract_pdf(b""), Err(AppError::ParseError(_))));
+    }
+
+    #[test]
+    fn panics_inside_pdf_extract_become_errors() {
+        // Showing text in a font the page does not define panics in pdf-extract
+        let document = fixture(&["BT /F9 12 Tf 72 720 Td (Missing font) Tj ET"]);
+        assert!(panic::catch_unwind(|| extract(&document)).is_err());
+        assert!(matches!(extract_pdf(&document), Err(AppError::ParseError(_))));
+    }
+}
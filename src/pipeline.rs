@@ -0,0 +1,670 @@
+use bytes::Bytes;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use chrono::Utc;
+use futures::future::{join_all, BoxFuture};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::error::{AppError, ErrorBody, Result};
+use crate::api::models::{
+    DigestItem, ExtractFormat, ExtractRequest, ExtractResponse, ScrapeInput, ScrapeRequest, ScrapeResponse,
+};
+use crate::canonical::{canonicalize_url, resolve_declared_canonical};
+use crate::feed::{parse_feed, render_digest, FeedEntry};
+use crate::pdf::extract_pdf;
+use crate::metadata::{extract_metadata, PageMetadata};
+use crate::structured::{extract_structured_data, StructuredItem};
+use crate::scraper::{extract_body, extract_canonical_link, ExtractedText, ExtractionStrategy, FetchedPage, PageBody};
+use crate::summarizer::summarize;
+use crate::limits::Billing;
+use crate::text::{clean_chars, normalize_text};
+use crate::llm::DeltaCallback;
+use crate::usage::SummaryUsage;
+use crate::{AppState, CachedResponse};
+
+/// Progress notifications emitted while a request moves through the pipeline
+#[derive(Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum PipelineEvent {
+    FetchStarted { url: String },
+    FetchDone { bytes: usize },
+    ExtractionDone { word_count: usize, chars: usize, strategy: ExtractionStrategy },
+    Token { delta: String },
+    Done { response: Box<ScrapeResponse> },
+    Error {
+        status_code: u16,
+        #[serde(flatten)]
+        error: ErrorBody,
+    },
+}
+
+impl PipelineEvent {
+    /// Name used as the SSE `event:` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            PipelineEvent::FetchStarted { .. } => "fetch_started",
+            PipelineEvent::FetchDone { .. } => "fetch_done",
+            PipelineEvent::ExtractionDone { .. } => "extraction_done",
+            PipelineEvent::Token { .. } => "token",
+            PipelineEvent::Done { .. } => "done",
+            PipelineEvent::Error { .. } => "error",
+        }
+    }
+}
+
+pub type EventSender = UnboundedSender<PipelineEvent>;
+
+fn emit(events: Option<&EventSender>, event: PipelineEvent) {
+    if let Some(events) = events {
+        // The receiver is gone when the client disconnected; keep working so the result still gets cached
+        let _ = events.send(event);
+    }
+}
+
+/// Runs the full fetch → extract → summarize pipeline for one request.
+///
+/// When `events` is given, progress is reported on it as each stage completes,
+/// including the summary tokens as the model streams them. LLM calls are
+/// charged to `billing`.
+pub async fn process_scrape_request(
+    state: &AppState,
+    req: &ScrapeRequest,
+    billing: &Billing,
+    events: Option<&EventSender>,
+) -> Result<ScrapeResponse> {
+    scrape(state, req, billing, events, true).await
+}
+
+/// Runs the pipeline for one request. Feeds are only digested when
+/// `digest_feeds` is set, so the entries of a digest cannot start another one.
+async fn scrape(
+    state: &AppState,
+    req: &ScrapeRequest,
+    billing: &Billing,
+    events: Option<&EventSender>,
+    digest_feeds: bool,
+) -> Result<ScrapeResponse> {
+    // Fetched pages are cached by URL, supplied content by its hash
+    let input = req.input()?;
+    let cache_key = match input {
+        ScrapeInput::Url(url) | ScrapeInput::Feed(url) => canonicalize_url(url)?,
+        ScrapeInput::Html(html) => content_cache_key("html", &req.url, html),
+        ScrapeInput::Text(text) => content_cache_key("text", "", text),
+        ScrapeInput::Markdown(markdown) => content_cache_key("markdown", "", markdown),
+    };
+
+    // Check cache first; the store only returns entries younger than the configured TTL.
+    // Digests are never cached, only their entries, so an explicit feed skips the lookup.
+    if !matches!(input, ScrapeInput::Feed(_))
+        && let Some(cached) = state.cache.get(&cache_key).await
+    {
+        println!("Cache hit for {} ({})", req.label(), cache_key);
+        return Ok(cached_response(req, &cache_key, cached));
+    }
+
+    // Without a working LLM, or quota left to use it, there is nothing to serve beyond the cache
+    state.llm.ensure_available()?;
+    billing.check_quota()?;
+
+    // Concurrent requests for the same page share one fetch and LLM call. A digest
+    // never shares with a plain scrape of the same URL, which one of its entries may be.
+    let flight_key = if digest_feeds { format!("digest {}", cache_key) } else { cache_key.clone() };
+    // The computation is charged to the request that starts it, even when that request goes away
+    let (task_state, task_req, task_key) = (state.clone(), req.clone(), cache_key.clone());
+    let (task_billing, task_events) = (billing.clone(), events.cloned());
+    let (result, joined) = state.inflight.run(&flight_key, async move {
+        scrape_uncached(&task_state, &task_req, &task_billing, task_key, task_events.as_ref(), digest_feeds).await
+    }).await;
+
+    if joined {
+        println!("Joined in-flight request for {} ({})", req.label(), cache_key);
+    }
+
+    // A joined computation may have been started for a different spelling of the URL
+    result.map(|response| ScrapeResponse { url: req.response_url().to_string(), ..response })
+}
+
+/// Fetches a page and extracts its content without summarizing it.
+///
+/// Needs no LLM, and its results are not cached: they are cheap to recompute.
+pub async fn process_extract_request(state: &AppState, req: &ExtractRequest) -> Result<ExtractResponse> {
+    let canonical_url = canonicalize_url(&req.url)?;
+    let page = fetch_page(state, &req.url, None).await?;
+    let canonical_url = declared_canonical(&page).unwrap_or(canonical_url);
+
+    let PageContent { metadata, structured_data, extracted, word_count } = extract_fetched(state, &page, None).await?;
+    let format = req.format.unwrap_or_default();
+    let content = match format {
+        ExtractFormat::Markdown => extracted.markdown,
+        ExtractFormat::Text => extracted.text,
+    };
+
+    println!("Extraction completed for URL: {}", req.url);
+    Ok(ExtractResponse {
+        url: req.url.clone(),
+        canonical_url,
+        final_url: page.final_url,
+        upstream_status: page.status,
+        format,
+        content,
+        word_count,
+        extraction_strategy: extracted.strategy,
+        metadata,
+        structured_data,
+        links: extracted.links,
+        extracted_at: Utc::now(),
+    })
+}
+
+/// Fetches, extracts and summarizes a page that is not in the cache
+async fn scrape_uncached(
+    state: &AppState,
+    req: &ScrapeRequest,
+    billing: &Billing,
+    cache_key: String,
+    events: Option<&EventSender>,
+    digest_feeds: bool,
+) -> Result<ScrapeResponse> {
+    let source_url = (!req.url.trim().is_empty()).then_some(req.url.as_str());
+
+    let (page, canonical_url, content) = match req.input()? {
+        ScrapeInput::Url(url) => {
+            let page = fetch_page(state, url, events).await?;
+            if let PageBody::Feed(bytes) = &page.body {
+                if !digest_feeds {
+                    return Err(AppError::ParseError(format!("{} is a feed, not a page", page.final_url)));
+                }
+                return digest_feed(state, req, billing, &cache_key, &page, bytes).await;
+            }
+
+            // Honor the canonical URL declared by the page; another URL may already have cached it
+            let declared_canonical = declared_canonical(&page)
+                .filter(|canonical| *canonical != cache_key);
+            if let Some(canonical) = &declared_canonical
+                && let Some(cached) = state.cache.get(canonical).await
+            {
+                println!("Cache hit for declared canonical URL: {}", canonical);
+                state.cache.put(&cache_key, cached.clone()).await;
+                return Ok(ScrapeResponse {
+                    upstream_status: Some(page.status),
+                    final_url: Some(page.final_url.clone()),
+                    ..cached_response(req, canonical, cached)
+                });
+            }
+            let canonical_url = declared_canonical.unwrap_or_else(|| cache_key.clone());
+
+            let content = extract_fetched(state, &page, events).await?;
+            (Some(page), canonical_url, content)
+        },
+        ScrapeInput::Feed(feed_url) => {
+            let page = fetch_page(state, feed_url, events).await?;
+            let PageBody::Feed(bytes) = &page.body else {
+                return Err(AppError::ParseError(format!("{} is not an RSS or Atom feed", page.final_url)));
+            };
+            return digest_feed(state, req, billing, &cache_key, &page, bytes).await;
+        },
+        ScrapeInput::Html(html) => {
+            println!("Using supplied HTML ({} bytes)", html.len());
+            (None, cache_key.clone(), extract_page(state, html, source_url, events)?)
+        },
+        ScrapeInput::Text(text) => {
+            println!("Using supplied text ({} bytes)", text.len());
+            let text = normalize_text(text);
+            (None, cache_key.clone(), verbatim_content(text.clone(), text, events))
+        },
+        ScrapeInput::Markdown(markdown) => {
+            println!("Using supplied Markdown ({} bytes)", markdown.len());
+            let markdown = clean_chars(markdown.trim());
+            (None, cache_key.clone(), verbatim_content(normalize_text(&markdown), markdown, events))
+        },
+    };
+    let PageContent { metadata, structured_data, extracted, word_count } = content;
+    let formatted = extracted.markdown;
+
+    println!("Calling LLM API...");
+    let llm_start = std::time::Instant::now();
+    
+    // Forward summary tokens to the event stream as they arrive
+    let on_delta = |delta: &str| emit(events, PipelineEvent::Token { delta: delta.to_string() });
+    let summary_result = summarize(
+        state.llm.as_ref(),
+        billing,
+        &formatted,
+        &state.config.summarizer,
+        source_url,
+        events.map(|_| &on_delta as DeltaCallback),
+    ).await;
+    
+    let summary = match summary_result {
+        Ok(summary) => {
+            println!("LLM API call successful in {:?}", llm_start.elapsed());
+            summary
+        },
+        Err(e) => {
+            println!("LLM API error: {}", e);
+            return Err(e);
+        }
+    };
+
+    let usage = summary.usage;
+    println!("Summary used {} tokens of {} (cost: {})", usage.tokens.total_tokens, usage.model,
+             usage.cost_usd.map_or("unknown".to_string(), |cost| format!("${:.6}", cost)));
+
+    println!("Formatting summary...");
+    // Ensure proper Markdown formatting
+    let formatted_summary = ensure_markdown_formatting(&summary.text);
+    
+    // No truncation happening now, so use the summary directly
+    let final_summary = formatted_summary;
+
+    println!("Storing result in cache");
+    // Any page may declare any same-site URL canonical, so the summary is only stored
+    // under the declared URL when the fetch itself ended up there
+    let fetched_canonical = page.as_ref()
+        .is_some_and(|page| canonicalize_url(&page.final_url).is_ok_and(|final_key| final_key == canonical_url));
+    let cached = CachedResponse {
+        canonical_url: canonical_url.clone(),
+        summary: final_summary.clone(),
+        content_markdown: Some(formatted.clone()),
+        word_count,
+        upstream_status: page.as_ref().map(|page| page.status),
+        final_url: page.as_ref().map(|page| page.final_url.clone()),
+        extraction_strategy: Some(extracted.strategy),
+        metadata: Some(metadata.clone()),
+        structured_data: Some(structured_data.clone()),
+        usage: Some(usage.clone()),
+        timestamp: Utc::now(),
+    };
+    if canonical_url != cache_key && fetched_canonical {
+        state.cache.put(&canonical_url, cached.clone()).await;
+    }
+    state.cache.put(&cache_key, cached).await;
+
+    println!("Request completed successfully for {}", req.label());
+    Ok(ScrapeResponse {
+        url: req.url.clone(),
+        canonical_url,
+        summary: final_summary,
+        content_markdown: Some(formatted),
+        scraped_at: Utc::now(),
+        word_count,
+        upstream_status: page.as_ref().map(|page| page.status),
+        final_url: page.map(|page| page.final_url),
+        extraction_strategy: Some(extracted.strategy),
+        metadata: Some(metadata),
+        structured_data: Some(structured_data),
+        feed_items: None,
+        usage: Some(usage),
+        status: "success".to_string(),
+    })
+}
+
+/// Summarizes the latest entries of a feed and combines them into one digest.
+///
+/// Every entry goes through the regular cached pipeline under its own URL, so
+/// running the digest again only summarizes entries that are new. The digest
+/// itself is not cached.
+async fn digest_feed(
+    state: &AppState,
+    req: &ScrapeRequest,
+    billing: &Billing,
+    cache_key: &str,
+    page: &FetchedPage,
+    bytes: &Bytes,
+) -> Result<ScrapeResponse> {
+    let feed = parse_feed(bytes, &page.final_url)?;
+    let limits = &state.config.feed;
+    let count = req.max_items.unwrap_or(limits.default_items).clamp(1, limits.max_items);
+    println!("Digesting {} of {} feed entries from {}", count.min(feed.entries.len()), feed.entries.len(), page.final_url);
+
+    // An entry linking back to the feed would only summarize the feed itself
+    let final_key = canonicalize_url(&page.final_url).ok();
+    let links_to_feed = |entry: &&FeedEntry| {
+        let key = entry.url.as_deref().and_then(|url| canonicalize_url(url).ok());
+        key.is_some_and(|key| key == cache_key || Some(&key) == final_key.as_ref())
+    };
+
+    let permits = Semaphore::new(limits.concurrency);
+    let items = join_all(feed.entries.iter().filter(|entry| !links_to_feed(entry)).take(count).map(|entry| {
+        let permits = &permits;
+        async move {
+            let _permit = permits.acquire().await.expect("digest semaphore is never closed");
+            let result = summarize_entry(state, billing, entry).await;
+            if let Err(err) = &result {
+                println!("Feed entry failed for {}: {}", entry.url.as_deref().unwrap_or("<no link>"), err);
+            }
+            DigestItem {
+                title: entry.title.clone(),
+                url: entry.url.clone(),
+                published_at: entry.published_at,
+                word_count: result.as_ref().map_or(0, |response| response.word_count),
+                summary_markdown: result.as_ref().ok().map(|response| response.summary.clone()),
+                usage: result.as_ref().ok().and_then(|response| response.usage.clone()),
+                error: result.as_ref().err().map(|err| err.message().to_string()),
+            }
+        }
+    })).await;
+
+    // Fail like any other scrape when the LLM is gone or the quota is used up,
+    // rather than returning a digest of errors
+    if !items.is_empty() && items.iter().all(|item| item.summary_markdown.is_none()) {
+        state.llm.ensure_available()?;
+        billing.check_quota()?;
+    }
+
+    Ok(ScrapeResponse {
+        url: req.response_url().to_string(),
+        canonical_url: cache_key.to_string(),
+        summary: render_digest(&feed, &items),
+        content_markdown: None,
+        scraped_at: Utc::now(),
+        word_count: items.iter().map(|item| item.word_count).sum(),
+        upstream_status: Some(page.status),
+        final_url: Some(page.final_url.clone()),
+        extraction_strategy: Some(ExtractionStrategy::Feed),
+        metadata: Some(feed.metadata()),
+        structured_data: None,
+        usage: SummaryUsage::sum(items.iter().filter_map(|item| item.usage.as_ref())),
+        feed_items: Some(items),
+        status: "success".to_string(),
+    })
+}
+
+/// Scrapes one digest entry. Boxed because the pipeline recurses into itself here.
+fn scrape_entry<'a>(
+    state: &'a AppState,
+    req: &'a ScrapeRequest,
+    billing: &'a Billing,
+) -> BoxFuture<'a, Result<ScrapeResponse>> {
+    Box::pin(scrape(state, req, billing, None, false))
+}
+
+/// Summarizes the page an entry links to, falling back to the content the
+/// feed carries for entries without a link or whose page cannot be fetched
+async fn summarize_entry(state: &AppState, billing: &Billing, entry: &FeedEntry) -> Result<ScrapeResponse> {
+    let from_content = |url: &str| entry.content.as_ref().map(|content| ScrapeRequest {
+        url: url.to_string(),
+        html: Some(content.clone()),
+        ..ScrapeRequest::default()
+    });
+
+    let Some(url) = &entry.url else {
+        let req = from_content("")
+            .ok_or_else(|| AppError::ParseError("The entry has neither a link nor content".to_string()))?;
+        return scrape_entry(state, &req, billing).await;
+    };
+
+    let req = ScrapeRequest { url: url.clone(), ..ScrapeRequest::default() };
+    match scrape_entry(state, &req, billing).await {
+        Err(err) if !err.is_llm_failure() && !matches!(err, AppError::QuotaExceeded(..)) && entry.content.is_some() => {
+            println!("Using feed content for {}: {}", url, err);
+            let req = from_content(url).expect("entry content was checked");
+            scrape_entry(state, &req, billing).await
+        },
+        result => result,
+    }
+}
+
+/// Fetches a page with the pipeline's fetch timeout, reporting progress on `events`
+async fn fetch_page(state: &AppState, url: &str, events: Option<&EventSender>) -> Result<FetchedPage> {
+    println!("Fetching HTML for URL: {}", url);
+    emit(events, PipelineEvent::FetchStarted { url: url.to_string() });
+    let fetch_start = std::time::Instant::now();
+    
+    // Fetch with even shorter timeout - 5 seconds
+    let html_result = tokio::time::timeout(
+        Duration::from_secs(5), 
+        state.fetcher.fetch_html(url)
+    ).await;
+    
+    let page = match html_result {
+        Ok(result) => {
+            match result {
+                Ok(page) => {
+                    println!("HTML fetch successful in {:?} (HTTP {})", fetch_start.elapsed(), page.status);
+                    page
+                },
+                Err(AppError::FetchError(msg)) => {
+                    println!("HTML fetch error: {}", msg);
+                    return Err(AppError::FetchError(format!("Failed to fetch HTML: {}", msg)));
+                },
+                Err(e) => {
+                    println!("HTML fetch error: {}", e);
+                    return Err(e);
+                }
+            }
+        },
+        Err(_) => {
+            println!("HTML fetch timed out after 5 seconds");
+            return Err(AppError::FetchTimeout("HTML fetch timed out after 5 seconds".to_string()));
+        }
+    };
+    
+    emit(events, PipelineEvent::FetchDone { bytes: page.body.len() });
+    Ok(page)
+}
+
+/// The canonical URL a fetched HTML page declares, made absolute
+fn declared_canonical(page: &FetchedPage) -> Option<String> {
+    let PageBody::Html(html) = &page.body else { return None };
+    extract_canonical_link(html).and_then(|declared| resolve_declared_canonical(&page.final_url, &declared))
+}
+
+/// Everything extracted from a fetched page
+pub struct PageContent {
+    pub metadata: PageMetadata,
+    pub structured_data: Vec<StructuredItem>,
+    pub extracted: ExtractedText,
+    pub word_count: usize,
+}
+
+/// Extracts metadata, structured data and the main content of a page.
+/// Relative URLs are resolved against `page_url` when given.
+fn extract_page(
+    state: &AppState,
+    html: &str,
+    page_url: Option<&str>,
+    events: Option<&EventSender>,
+) -> Result<PageContent> {
+    println!("🔍 Extracting and formatting HTML content");
+    let metadata = extract_metadata(html, page_url);
+    let structured_data = extract_structured_data(html, page_url);
+    let raw_body = extract_body(html)
+        .ok_or_else(|| {
+            println!("No <body> tag found in HTML");
+            AppError::ParseError("No <body> tag found in the HTML".to_string())
+        })?;
+    
+    let extracted = state.extractor.extract(&raw_body, page_url, &structured_data);
+    Ok(finish_extraction(metadata, structured_data, extracted, events))
+}
+
+/// Extracts the content of a fetched HTML page or PDF document
+async fn extract_fetched(state: &AppState, page: &FetchedPage, events: Option<&EventSender>) -> Result<PageContent> {
+    match &page.body {
+        PageBody::Html(html) => {
+            let mut content = extract_page(state, html, Some(&page.final_url), events)?;
+            content.metadata.encoding = page.encoding.clone();
+            Ok(content)
+        },
+        PageBody::Pdf(bytes) => extract_pdf_document(bytes.clone(), events).await,
+        PageBody::Feed(_) => Err(AppError::ParseError(format!(
+            "{} is an RSS or Atom feed; use /api/scrape for a digest of its entries", page.final_url
+        ))),
+    }
+}
+
+/// Extracts the text and document information of a PDF on a blocking thread
+async fn extract_pdf_document(bytes: Bytes, events: Option<&EventSender>) -> Result<PageContent> {
+    println!("📄 Extracting text from PDF ({} bytes)", bytes.len());
+    let document = tokio::task::spawn_blocking(move || extract_pdf(&bytes))
+        .await
+        .map_err(|e| AppError::InternalError(format!("PDF extraction task failed: {}", e)))??;
+    println!("Extracted {} PDF pages", document.page_count);
+
+    let metadata = PageMetadata {
+        title: document.title,
+        author: document.author,
+        published_at: document.created_at,
+        modified_at: document.modified_at,
+        lang: document.lang,
+        page_count: Some(document.page_count),
+        ..PageMetadata::default()
+    };
+    let extracted = ExtractedText {
+        text: document.text,
+        markdown: document.markdown,
+        strategy: ExtractionStrategy::Pdf,
+        links: Vec::new(),
+    };
+    Ok(finish_extraction(metadata, Vec::new(), extracted, events))
+}
+
+/// Wraps caller-supplied text or Markdown, which is summarized as is
+fn verbatim_content(text: String, markdown: String, events: Option<&EventSender>) -> PageContent {
+    let extracted = ExtractedText { text, markdown, strategy: ExtractionStrategy::Verbatim, links: Vec::new() };
+    finish_extraction(PageMetadata::default(), Vec::new(), extracted, events)
+}
+
+fn finish_extraction(
+    metadata: PageMetadata,
+    structured_data: Vec<StructuredItem>,
+    extracted: ExtractedText,
+    events: Option<&EventSender>,
+) -> PageContent {
+    println!("Content size: {} chars ({:?} extraction)", extracted.markdown.len(), extracted.strategy);
+
+    // Calculate word count
+    let word_count = extracted.text.split_whitespace().count();
+    println!("Word count: {}", word_count);
+    emit(events, PipelineEvent::ExtractionDone {
+        word_count,
+        chars: extracted.markdown.len(),
+        strategy: extracted.strategy,
+    });
+
+    PageContent { metadata, structured_data, extracted, word_count }
+}
+
+/// Cache key for caller-supplied content: a hash of the content together with
+/// its kind and the URL its links are resolved against
+fn content_cache_key(kind: &str, source_url: &str, content: &str) -> String {
+    let mut hasher = Sha256::new();
+    for part in [kind, source_url.trim(), content] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("content:sha256:{}", hex::encode(hasher.finalize()))
+}
+
+fn cached_response(req: &ScrapeRequest, cache_key: &str, cached: CachedResponse) -> ScrapeResponse {
+    let canonical_url = if cached.canonical_url.is_empty() {
+        cache_key.to_string()
+    } else {
+        cached.canonical_url
+    };
+
+    ScrapeResponse {
+        url: req.response_url().to_string(),
+        canonical_url,
+        summary: cached.summary,
+        content_markdown: cached.content_markdown,
+        scraped_at: Utc::now(),
+        word_count: cached.word_count,
+        upstream_status: cached.upstream_status,
+        final_url: cached.final_url,
+        extraction_strategy: cached.extraction_strategy,
+        metadata: cached.metadata,
+        structured_data: cached.structured_data,
+        feed_items: None,
+        usage: cached.usage,
+        status: "success (cached)".to_string(),
+    }
+}
+
+/// Ensures the text is properly formatted as Markdown.
+/// This function does some basic validation and formatting to improve the Markdown structure.
+fn ensure_markdown_formatting(text: &str) -> String {
+    let text = text.trim();
+    
+    // Make sure it has a title/heading
+    if !text.starts_with('#') {
+        // Try to find a good title from the first line or use "Website Summary"
+        let first_line = text.lines().next().unwrap_or("Website Summary");
+        let title = if first_line.len() > 50 {
+            "Website Summary"
+        } else {
+            first_line
+        };
+        
+        return format!("# {}\n\n{}", title, text);
+    }
+    
+    // Text already has Markdown formatting, return as is
+    text.to_string()
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::testing::{self, response, serve_pages, FakeLlm};
+
+    fn page(canonical: &str) -> String {
+        let html = format!(
+            "<html><head><title>Post</title><link rel=\"canonical\" href=\"{}\"></head>\
+             <body><article><p>Some article text that is long enough to be the main content of the page.</p></article></body></html>",
+            canonical,
+        );
+        response("200 OK", "text/html", "", &html)
+    }
+
+    async fn scrape_url(state: &AppState, url: &str) -> ScrapeResponse {
+        let billing = Billing::new(state.limits.clone(), testing::client("ci"));
+        let req = ScrapeRequest { url: url.to_string(), ..ScrapeRequest::default() };
+        process_scrape_request(state, &req, &billing, None).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn foreign_declared_canonical_is_not_written() {
+        let base = serve_pages(vec![("/~attacker/post", page("/~victim/post"))]).await;
+        let state = testing::state(Arc::new(FakeLlm::replying("Attacker summary")));
+
+        let response = scrape_url(&state, &format!("{}/~attacker/post", base)).await;
+        assert_eq!(response.canonical_url, format!("{}/~victim/post", base));
+        assert!(state.cache.get(&format!("{}/~attacker/post", base)).await.is_some());
+        assert!(state.cache.get(&format!("{}/~victim/post", base)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn declared_canonical_reached_by_redirect_is_written() {
+        let base = serve_pages(vec![
+            ("/old", response("301 Moved Permanently", "text/plain", "location: /new\r\n", "")),
+            ("/new", page("/new")),
+        ]).await;
+        let state = testing::state(Arc::new(FakeLlm::replying("A summary")));
+
+        scrape_url(&state, &format!("{}/old", base)).await;
+        assert!(state.cache.get(&format!("{}/old", base)).await.is_some());
+        assert!(state.cache.get(&format!("{}/new", base)).await.is_some());
+    }
+
+    #[test]
+    fn content_cache_key_is_stable_and_apart_from_url_keys() {
+        let key = content_cache_key("text", "", "Some text");
+        assert_eq!(key, content_cache_key("text", " ", "Some text"));
+        assert!(key.starts_with("content:sha256:"));
+        assert_eq!(key.len(), "content:sha256:".len() + 64);
+
+        assert_ne!(key, content_cache_key("markdown", "", "Some text"));
+        assert_ne!(key, content_cache_key("text", "", "Other text"));
+        assert_ne!(
+            content_cache_key("html", "https://a.example/", "<p>a</p>"),
+            content_cache_key("html", "https://b.example/", "<p>a</p>"),
+        );
+        // The separators keep the kind, URL and content from running together
+        assert_ne!(content_cache_key("text", "a", "b"), content_cache_key("text", "", "ab"));
+        assert!(canonicalize_url("https://a.example/").is_ok_and(|url_key| !url_key.starts_with("content:")));
+    }
+}
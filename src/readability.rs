@@ -0,0 +1,243 @@
+use ego_tree::{NodeId, NodeRef};
+use once_cell::sync::Lazy;
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashMap;
+
+// Elements whose text is scored as a paragraph
+static PARAGRAPH_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("p, pre, td, blockquote, div").expect("Failed to parse paragraph selector")
+});
+
+// Paragraphs shorter than this carry no signal
+const MIN_PARAGRAPH_CHARS: usize = 25;
+
+// The best candidate must reach both limits, otherwise the scores are inconclusive
+const MIN_TOP_SCORE: f64 = 20.0;
+const MIN_CONTENT_CHARS: usize = 250;
+
+// Class and id fragments that suggest main content or boilerplate
+const POSITIVE_HINTS: &[&str] = &[
+    "article", "body", "content", "entry", "hentry", "main", "page", "post", "text", "blog", "story",
+];
+const NEGATIVE_HINTS: &[&str] = &[
+    "ad-", "-ad", "banner", "combx", "comment", "com-", "contact", "foot", "hidden", "masthead",
+    "media", "meta", "outbrain", "promo", "related", "scroll", "share", "shoutbox", "sidebar",
+    "skyscraper", "sponsor", "shopping", "tags", "tool", "widget",
+];
+
+// A <div> with any of these children is a container rather than a paragraph
+const DIV_BLOCK_CHILDREN: &[&str] = &["blockquote", "dl", "div", "img", "ol", "p", "pre", "table", "ul"];
+
+/// The main content found by scoring: the best candidate together with the
+/// siblings that belong to it, in document order
+pub struct MainContent<'a> {
+    pub elements: Vec<ElementRef<'a>>,
+    pub score: f64,
+}
+
+struct Candidate<'a> {
+    element: ElementRef<'a>,
+    score: f64,
+}
+
+/// Finds the subtree most likely to hold the page's main content.
+///
+/// Follows Mozilla Readability: paragraphs score by length and commas and pass
+/// that score on to their parent and grandparent, class and id names push
+/// candidates up or down, and the totals are scaled down by link density.
+/// Returns `None` when the best candidate is too weak or too short to trust.
+pub fn find_main_content<'a>(document: &'a Html, noise: &Selector) -> Option<MainContent<'a>> {
+    let mut candidates: HashMap<NodeId, Candidate<'a>> = HashMap::new();
+
+    for paragraph in document.select(&PARAGRAPH_SELECTOR) {
+        if paragraph.value().name() == "div" && has_block_children(paragraph) {
+            continue;
+        }
+        if in_noise(&paragraph, noise) {
+            continue;
+        }
+
+        let (text, _) = visible_text(paragraph, noise);
+        let len = text.trim().chars().count();
+        if len < MIN_PARAGRAPH_CHARS {
+            continue;
+        }
+
+        let score = 1.0 + text.matches(',').count() as f64 + (len as f64 / 100.0).min(3.0);
+
+        let ancestors = paragraph.ancestors()
+            .filter_map(ElementRef::wrap)
+            .filter(|ancestor| ancestor.value().name() != "html")
+            .take(3);
+        for (level, ancestor) in ancestors.enumerate() {
+            let divider = match level {
+                0 => 1.0,
+                1 => 2.0,
+                level => level as f64 * 3.0,
+            };
+            candidates.entry(ancestor.id())
+                .or_insert_with(|| Candidate { element: ancestor, score: initial_score(ancestor) })
+                .score += score / divider;
+        }
+    }
+
+    // Content that is mostly links is navigation, whatever its size
+    for candidate in candidates.values_mut() {
+        candidate.score *= 1.0 - link_density(candidate.element, noise);
+    }
+
+    let top = candidates.values().max_by(|a, b| a.score.total_cmp(&b.score))?;
+    let (top_text, _) = visible_text(top.element, noise);
+    let top_len = top_text.split_whitespace().collect::<Vec<_>>().join(" ").chars().count();
+    if top.score < MIN_TOP_SCORE || top_len < MIN_CONTENT_CHARS {
+        println!("Content scores inconclusive (best {:.1} over {} chars)", top.score, top_len);
+        return None;
+    }
+
+    // Siblings that score well or read like prose are part of the same content
+    let threshold = (top.score * 0.2).max(10.0);
+    let elements = match top.element.parent().and_then(ElementRef::wrap) {
+        Some(parent) => parent.children()
+            .filter_map(ElementRef::wrap)
+            .filter(|sibling| {
+                sibling.id() == top.element.id()
+                    || (!noise.matches(sibling) && belongs_with_top(sibling, &candidates, threshold, noise))
+            })
+            .collect(),
+        None => vec![top.element],
+    };
+
+    Some(MainContent { elements, score: top.score })
+}
+
+fn belongs_with_top(
+    sibling: &ElementRef,
+    candidates: &HashMap<NodeId, Candidate>,
+    threshold: f64,
+    noise: &Selector,
+) -> bool {
+    if candidates.get(&sibling.id()).is_some_and(|candidate| candidate.score >= threshold) {
+        return true;
+    }
+    if sibling.value().name() != "p" {
+        return false;
+    }
+
+    let (text, _) = visible_text(*sibling, noise);
+    let text = text.trim();
+    let len = text.chars().count();
+    let density = link_density(*sibling, noise);
+    (len > 80 && density < 0.25) || (len > 0 && density == 0.0 && (text.ends_with('.') || text.contains(". ")))
+}
+
+/// Starting score of a candidate from its tag and its class and id names
+fn initial_score(element: ElementRef) -> f64 {
+    let tag_score = match element.value().name() {
+        "div" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    tag_score + class_weight(element)
+}
+
+fn class_weight(element: ElementRef) -> f64 {
+    let mut weight = 0.0;
+    for name in [element.value().attr("class"), element.value().attr("id")].into_iter().flatten() {
+        let name = name.to_ascii_lowercase();
+        if NEGATIVE_HINTS.iter().any(|hint| name.contains(hint)) {
+            weight -= 25.0;
+        }
+        if POSITIVE_HINTS.iter().any(|hint| name.contains(hint)) {
+            weight += 25.0;
+        }
+    }
+    weight
+}
+
+fn has_block_children(element: ElementRef) -> bool {
+    element.children()
+        .filter_map(ElementRef::wrap)
+        .any(|child| DIV_BLOCK_CHILDREN.contains(&child.value().name()))
+}
+
+/// Whether the element or one of its ancestors matches the noise selector
+pub(crate) fn in_noise(element: &ElementRef, noise: &Selector) -> bool {
+    noise.matches(element)
+        || element.ancestors().filter_map(ElementRef::wrap).any(|ancestor| noise.matches(&ancestor))
+}
+
+/// Fraction of the visible text that sits inside links
+fn link_density(element: ElementRef, noise: &Selector) -> f64 {
+    let (text, link_chars) = visible_text(element, noise);
+    let total = text.chars().filter(|c| !c.is_whitespace()).count();
+    if total == 0 {
+        return 0.0;
+    }
+    link_chars as f64 / total as f64
+}
+
+/// Text below an element, skipping noise, and how many of its non-whitespace
+/// characters are link text. Walks an explicit stack so that deeply nested
+/// markup cannot overflow the call stack.
+fn visible_text(element: ElementRef, noise: &Selector) -> (String, usize) {
+    let mut text = String::new();
+    let mut link_chars = 0;
+    let mut stack: Vec<(NodeRef<Node>, bool)> = element.children().rev().map(|child| (child, false)).collect();
+
+    while let Some((node, in_link)) = stack.pop() {
+        match node.value() {
+            Node::Text(fragment) => {
+                text.push_str(fragment);
+                if in_link {
+                    link_chars += fragment.chars().filter(|c| !c.is_whitespace()).count();
+                }
+            },
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(node)
+                    && !noise.matches(&child)
+                {
+                    let in_link = in_link || child.value().name() == "a";
+                    stack.extend(node.children().rev().map(|child| (child, in_link)));
+                }
+            },
+            _ => {},
+        }
+    }
+    (text, link_chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise() -> Selector {
+        Selector::parse("nav, script").unwrap()
+    }
+
+    #[test]
+    fn visible_text_counts_link_characters_and_skips_noise() {
+        let document = Html::parse_fragment("<div>Read <a href=\"/x\">the <b>docs</b></a> now<nav>Menu</nav><script>x()</script></div>");
+        let div = document.select(&Selector::parse("div").unwrap()).next().unwrap();
+        let (text, link_chars) = visible_text(div, &noise());
+        assert_eq!(text, "Read the docs now");
+        assert_eq!(link_chars, "thedocs".len());
+    }
+
+    #[test]
+    fn scores_deeply_nested_markup() {
+        let depth = 100_000;
+        let html = format!(
+            "<html><body><div class=\"article\">{}{}{}</div></body></html>",
+            "<span>".repeat(depth),
+            "<p>A long enough paragraph, with commas, to be scored as the content of the page.</p>".repeat(10),
+            "</span>".repeat(depth),
+        );
+        let document = Html::parse_document(&html);
+        let (text, link_chars) = visible_text(document.root_element(), &noise());
+        assert!(text.contains("scored as the content"));
+        assert_eq!(link_chars, 0);
+        assert!(find_main_content(&document, &noise()).is_some());
+    }
+}
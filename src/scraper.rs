@@ -1,24 +1,54 @@
-use reqwest::{Client, ClientBuilder};
-use scraper::{Html, Selector};
+use bytes::Bytes;
+use ego_tree::NodeRef;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::{redirect, Client, ClientBuilder, StatusCode, Url};
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Duration;
 use once_cell::sync::Lazy;
-use crate::error::Result;
-
-// Create a static client to reuse connections
-static CLIENT: Lazy<Client> = Lazy::new(|| {
-    ClientBuilder::new()
-        .timeout(Duration::from_secs(10))
-        .connect_timeout(Duration::from_secs(5))
-        .pool_max_idle_per_host(10)
-        .build()
-        .expect("Failed to build HTTP client")
-});
+use serde::{Deserialize, Serialize};
+use crate::config::{ExtractConfig, FetchConfig};
+use crate::encoding::{decode_html, DetectedEncoding};
+use crate::error::{parse_retry_after, AppError, ErrorDetails, Result};
+use crate::feed::is_feed;
+use crate::pdf::is_pdf;
+use crate::readability::{find_main_content, in_noise};
+use crate::ssrf::{find_blocked, GuardedResolver, UrlPolicy};
+use crate::structured::StructuredItem;
+use crate::text::{clean_chars, normalize_text};
+
+const MAX_REDIRECTS: usize = 10;
+
+// Fragments that only appear on bot-protection interstitials (Cloudflare, DataDome, Imperva, PerimeterX)
+const CHALLENGE_MARKERS: &[&str] = &[
+    "<title>Just a moment...</title>",
+    "<title>Attention Required! | Cloudflare</title>",
+    "cf-browser-verification",
+    "cf_chl_opt",
+    "/cdn-cgi/challenge-platform/",
+    "captcha-delivery.com",
+    "_Incapsula_Resource",
+    "px-captcha",
+];
 
 // Create static selectors to avoid recompiling them each time
 static BODY_SELECTOR: Lazy<Selector> = Lazy::new(|| {
     Selector::parse("body").expect("Failed to parse body selector")
 });
 
+static TABLE_ROW_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("tr").expect("Failed to parse table row selector")
+});
+
+static LINK_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("a[href]").expect("Failed to parse link selector")
+});
+
+static CANONICAL_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("link[rel~=canonical][href]").expect("Failed to parse canonical selector")
+});
+
 // Content selectors - most websites use these elements for main content
 static CONTENT_SELECTORS: Lazy<Vec<Selector>> = Lazy::new(|| {
     vec![
@@ -35,15 +65,222 @@ static CONTENT_SELECTORS: Lazy<Vec<Selector>> = Lazy::new(|| {
     ]
 });
 
-// Selector for elements to remove
-static NOISE_SELECTORS: Lazy<Selector> = Lazy::new(|| {
-    Selector::parse("script, style, nav, header, footer, iframe, noscript, svg, .ads, .advertisement, .banner, .cookie-banner, .cookie-notice, .popup").expect("Failed to parse noise selector")
-});
+// Elements whose text never belongs in the extracted content
+const NOISE_SELECTORS: &str = "script, style, template, nav, header, footer, iframe, noscript, svg, \
+    [hidden], [aria-hidden=true], [role=navigation], [role=banner], [role=contentinfo], \
+    .ads, .advertisement, .banner, .cookie-banner, .cookie-notice, .popup";
+
+/// HTTP client for user-supplied URLs, guarded against SSRF.
+///
+/// Every redirect hop is checked against the `UrlPolicy`, and host names are
+/// resolved through a `GuardedResolver` so that connections are only made to
+/// allowed addresses.
+pub struct Fetcher {
+    client: Client,
+    policy: Arc<UrlPolicy>,
+    max_body_bytes: usize,
+}
+
+impl Fetcher {
+    pub fn new(config: &FetchConfig) -> Result<Self> {
+        let policy = Arc::new(UrlPolicy::new(&config.allowlist)?);
+
+        let redirect_policy = policy.clone();
+        let client = ClientBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .connect_timeout(Duration::from_secs(5))
+            .pool_max_idle_per_host(10)
+            // A proxy would resolve the host itself and bypass the guarded resolver
+            .no_proxy()
+            .dns_resolver(Arc::new(GuardedResolver::new(policy.clone())))
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    return attempt.error("too many redirects");
+                }
+                match redirect_policy.check_url(attempt.url()) {
+                    Ok(()) => attempt.follow(),
+                    Err(blocked) => attempt.error(blocked),
+                }
+            }))
+            .build()
+            .map_err(|e| AppError::ConfigError(format!("Failed to build HTTP client: {}", e)))?;
+
+        Ok(Fetcher { client, policy, max_body_bytes: config.max_body_bytes })
+    }
+
+    /// Fetches a page and fails with a distinct error for each kind of
+    /// non-successful response, so error pages are never summarized.
+    ///
+    /// PDF documents and RSS or Atom feeds are recognized by their content
+    /// and returned as bytes; everything else is decoded as HTML, in the
+    /// encoding found by `decode_html`.
+    pub async fn fetch_html(&self, url: &str) -> Result<FetchedPage> {
+        let parsed = Url::parse(url)
+            .map_err(|e| AppError::InvalidUrl(format!("Invalid URL '{}': {}", url, e)))?;
+        self.policy.check_url(&parsed)
+            .map_err(|blocked| AppError::BlockedUrl(blocked.to_string()))?;
+
+        let mut response = self.client.get(parsed).send().await.map_err(fetch_error)?;
+        let status = response.status();
+        let final_url = response.url().to_string();
+        let headers = response.headers().clone();
+        let content_type = headers.get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let bytes = self.read_body(&mut response).await?;
+
+        // Trust the content over the header: servers label PDFs as octet streams and error pages as PDFs
+        let mut encoding = None;
+        let body = if is_pdf(&bytes) {
+            PageBody::Pdf(bytes)
+        } else if is_feed(content_type.as_deref(), &bytes) {
+            PageBody::Feed(bytes)
+        } else {
+            let (html, detected) = decode_html(&bytes, content_type.as_deref(), Some(&final_url));
+            encoding = Some(detected);
+            PageBody::Html(html)
+        };
+
+        let text = match &body {
+            PageBody::Html(html) => html.as_str(),
+            PageBody::Pdf(_) | PageBody::Feed(_) => "",
+        };
+        let class = classify_response(status, &headers, text);
+        if class != ResponseClass::Success {
+            println!("Upstream responded with {} ({:?}) for {}", status, class, final_url);
+        }
+
+        let describe = || format!("{} returned HTTP {}", final_url, status);
+        let retry_after = headers.get(RETRY_AFTER).and_then(|value| value.to_str().ok());
+        let details = || ErrorDetails {
+            upstream: Some(final_url.clone()),
+            upstream_status: Some(status.as_u16()),
+            retry_after: retry_after.and_then(parse_retry_after),
+        };
+        match class {
+            ResponseClass::Success if is_pdf_type(content_type.as_deref()) && matches!(body, PageBody::Html(_)) => {
+                Err(AppError::ParseError(format!("{} is served as a PDF but is not a PDF document", final_url)))
+            },
+            ResponseClass::Success => Ok(FetchedPage { body, status: status.as_u16(), final_url, content_type, encoding }),
+            ResponseClass::ClientError => Err(AppError::UpstreamClientError(describe(), details())),
+            ResponseClass::ServerError => Err(AppError::UpstreamServerError(describe(), details())),
+            ResponseClass::RateLimited => Err(AppError::UpstreamRateLimited(
+                match retry_after {
+                    Some(retry_after) => format!("{} (Retry-After: {})", describe(), retry_after),
+                    None => describe(),
+                },
+                details(),
+            )),
+            ResponseClass::Blocked => Err(AppError::UpstreamBlocked(
+                format!("{} with a bot-protection challenge", describe()),
+                details(),
+            )),
+        }
+    }
+
+    /// Reads the response body, failing as soon as it exceeds the size limit.
+    /// The declared `Content-Length` is checked first, but the streamed read
+    /// is capped too since servers can omit or misstate it.
+    async fn read_body(&self, response: &mut reqwest::Response) -> Result<Bytes> {
+        let url = response.url().to_string();
+        let too_large = || AppError::ContentTooLarge(format!(
+            "Response from {} exceeds the limit of {} bytes", url, self.max_body_bytes
+        ));
 
-pub async fn fetch_html(url: &str) -> Result<String> {
-    let response = CLIENT.get(url).send().await?;
-    let html = response.text().await?;
-    Ok(html)
+        if response.content_length().is_some_and(|length| length > self.max_body_bytes as u64) {
+            return Err(too_large());
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(fetch_error)? {
+            if body.len() + chunk.len() > self.max_body_bytes {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Bytes::from(body))
+    }
+}
+
+/// A successfully fetched page
+pub struct FetchedPage {
+    pub body: PageBody,
+    /// HTTP status of the final response
+    pub status: u16,
+    /// URL the page was served from after following redirects
+    pub final_url: String,
+    /// The `Content-Type` header of the response
+    pub content_type: Option<String>,
+    /// The character encoding an HTML body was decoded from
+    pub encoding: Option<DetectedEncoding>,
+}
+
+/// The content of a fetched page, by document type
+pub enum PageBody {
+    Html(String),
+    Pdf(Bytes),
+    /// An RSS, Atom or JSON feed
+    Feed(Bytes),
+}
+
+impl PageBody {
+    pub fn len(&self) -> usize {
+        match self {
+            PageBody::Html(html) => html.len(),
+            PageBody::Pdf(bytes) | PageBody::Feed(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn is_pdf_type(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|mime| {
+            let mime = mime.trim();
+            mime.eq_ignore_ascii_case("application/pdf") || mime.eq_ignore_ascii_case("application/x-pdf")
+        })
+}
+
+/// How an upstream response is treated by the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResponseClass {
+    Success,
+    ClientError,
+    ServerError,
+    RateLimited,
+    /// A bot-protection or challenge page instead of the real content
+    Blocked,
+}
+
+fn classify_response(status: StatusCode, headers: &HeaderMap, body: &str) -> ResponseClass {
+    // Challenge pages are served with 403, 429, 503 and sometimes even 200
+    let challenged = headers.get("cf-mitigated").is_some_and(|value| value == "challenge")
+        || CHALLENGE_MARKERS.iter().any(|marker| body.contains(marker));
+
+    if challenged {
+        ResponseClass::Blocked
+    } else if status.is_success() {
+        ResponseClass::Success
+    } else if status == StatusCode::TOO_MANY_REQUESTS {
+        ResponseClass::RateLimited
+    } else if status.is_client_error() {
+        ResponseClass::ClientError
+    } else {
+        // 5xx, and anything else left over after following redirects
+        ResponseClass::ServerError
+    }
+}
+
+/// Maps a request error to `BlockedUrl` when the SSRF guard rejected it
+fn fetch_error(err: reqwest::Error) -> AppError {
+    match find_blocked(&err) {
+        Some(blocked) => AppError::BlockedUrl(blocked.to_string()),
+        None => AppError::from(err),
+    }
 }
 
 pub fn extract_body(html: &str) -> Option<String> {
@@ -54,98 +291,571 @@ pub fn extract_body(html: &str) -> Option<String> {
         .map(|element| element.inner_html())
 }
 
-pub fn format_html(html: &str) -> String {
+/// Returns the `href` of the page's `<link rel="canonical">`, if it declares one
+pub fn extract_canonical_link(html: &str) -> Option<String> {
     let document = Html::parse_document(html);
-    
-    // First try to find main content using content selectors
-    let mut content = String::new();
-    for selector in CONTENT_SELECTORS.iter() {
-        if let Some(element) = document.select(selector).next() {
-            // Create a new document from this content element
-            let mut content_doc = element.inner_html();
-            
-            // Remove noise elements from this content
-            let content_fragment = Html::parse_fragment(&content_doc);
-            for noise in content_fragment.select(&NOISE_SELECTORS) {
-                if let Some(_) = noise.parent() {
-                    // This is a placeholder since we can't modify the DOM directly in scraper
-                    content_doc = content_doc.replace(&noise.html(), "");
-                }
-            }
-            
-            content = content_doc;
+
+    document.select(&CANONICAL_SELECTOR)
+        .next()
+        .and_then(|element| element.value().attr("href"))
+        .map(|href| href.trim().to_string())
+        .filter(|href| !href.is_empty())
+}
+
+// Shorter articleBody values are teasers rather than the article
+const MIN_ARTICLE_BODY_CHARS: usize = 500;
+
+// Nesting depth up to which elements are converted to Markdown; deeper content becomes plain text
+const MAX_MARKDOWN_DEPTH: usize = 256;
+
+// Elements that flow within a line; every other element boundary separates words
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "del", "dfn", "em", "i", "ins", "kbd",
+    "mark", "q", "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var",
+];
+
+/// Extracts the readable text of a page, leaving out noise such as scripts,
+/// navigation and cookie banners
+pub struct Extractor {
+    noise: Selector,
+}
+
+impl Extractor {
+    pub fn new(config: &ExtractConfig) -> Result<Self> {
+        let selectors = match &config.extra_noise_selectors {
+            Some(extra) => format!("{}, {}", NOISE_SELECTORS, extra),
+            None => NOISE_SELECTORS.to_string(),
+        };
+        let noise = Selector::parse(&selectors).map_err(|e| {
+            AppError::ConfigError(format!("Invalid EXTRA_NOISE_SELECTORS: {:?}", e))
+        })?;
+
+        Ok(Extractor { noise })
+    }
+
+    /// Finds the main content of a page and returns its text.
+    ///
+    /// Content scoring is tried first; the content selectors are only used when
+    /// the scores are inconclusive, and the whole body as a last resort. An
+    /// `articleBody` from the page's structured data is preferred over all of
+    /// them when it is substantial. Relative links and image sources are
+    /// resolved against `base_url` when given.
+    pub fn extract(&self, html: &str, base_url: Option<&str>, structured: &[StructuredItem]) -> ExtractedText {
+        let document = Html::parse_document(html);
+        let base = base_url.and_then(|url| Url::parse(url).ok());
+
+        let (elements, strategy) = if let Some(main) = find_main_content(&document, &self.noise) {
+            println!("Found content by scoring (score {:.1}, {} elements)", main.score, main.elements.len());
+            (main.elements, ExtractionStrategy::Readability)
+        } else if let Some((selector, element)) = CONTENT_SELECTORS.iter().find_map(|selector| {
+            document.select(selector)
+                .find(|element| !in_noise(element, &self.noise))
+                .map(|element| (selector, element))
+        }) {
             println!("Found content using selector: {:?}", selector);
-            break;
+            (vec![element], ExtractionStrategy::Selector)
+        } else {
+            println!("Using cleaned body content");
+            (document.select(&BODY_SELECTOR).collect(), ExtractionStrategy::Body)
+        };
+
+        let (text, markdown) = self.render(&elements, base.as_ref());
+        let links = self.collect_links(&elements, base.as_ref());
+        if let Some(article) = self.article_body(structured, base.as_ref(), text.len()) {
+            return ExtractedText { links, ..article };
         }
+
+        ExtractedText { text, markdown, strategy, links }
     }
-    
-    // If no content was found, use the entire body but clean it
-    if content.is_empty() {
-        if let Some(body) = document.select(&BODY_SELECTOR).next() {
-            let mut body_content = body.inner_html();
-            
-            // Remove noise elements from body
-            let body_fragment = Html::parse_fragment(&body_content);
-            for noise in body_fragment.select(&NOISE_SELECTORS) {
-                if let Some(_) = noise.parent() {
-                    body_content = body_content.replace(&noise.html(), "");
+
+    /// The distinct absolute http(s) links inside the content, in document order
+    fn collect_links(&self, elements: &[ElementRef], base: Option<&Url>) -> Vec<PageLink> {
+        let mut seen = HashSet::new();
+        elements.iter()
+            .flat_map(|element| element.select(&LINK_SELECTOR))
+            .filter(|link| !in_noise(link, &self.noise))
+            .filter_map(|link| {
+                let href = link.value().attr("href")?.trim();
+                let mut url = match base {
+                    Some(base) => base.join(href).ok()?,
+                    None => Url::parse(href).ok()?,
+                };
+                if url.scheme() != "http" && url.scheme() != "https" {
+                    return None;
                 }
+                // Links to anchors on the same page are not worth listing separately
+                url.set_fragment(None);
+                let url = url.to_string();
+                if !seen.insert(url.clone()) {
+                    return None;
+                }
+                let text = clean_chars(&link.text().collect::<String>()).split_whitespace().collect::<Vec<_>>().join(" ");
+                Some(PageLink { url, text })
+            })
+            .collect()
+    }
+
+    /// Renders elements as normalized plain text and as Markdown
+    fn render(&self, elements: &[ElementRef], base: Option<&Url>) -> (String, String) {
+        let mut text = String::new();
+        for element in elements {
+            text.push_str("\n\n");
+            self.collect_text(*element, &mut text);
+        }
+
+        let mut markdown = MarkdownWriter::new(&self.noise, base);
+        for element in elements {
+            markdown.block_break();
+            markdown.element(*element);
+        }
+
+        (normalize_text(&text), clean_chars(&markdown.finish()))
+    }
+
+    /// Builds the content from a schema.org `articleBody`. Pages sometimes
+    /// only publish a teaser there, so it must not be much shorter than what
+    /// the DOM extraction found.
+    fn article_body(&self, structured: &[StructuredItem], base: Option<&Url>, dom_len: usize) -> Option<ExtractedText> {
+        let (item, body) = structured.iter()
+            .filter(|item| item.is_article())
+            .filter_map(|item| Some((item, item.text_property("articleBody")?)))
+            .max_by_key(|(_, body)| body.len())?;
+        if body.len() < MIN_ARTICLE_BODY_CHARS || body.len() * 2 < dom_len {
+            return None;
+        }
+        println!("Using articleBody from structured data ({} chars)", body.len());
+
+        // Some publishers put HTML in articleBody, others plain text with one paragraph per line
+        let (text, markdown) = if body.contains("</p>") || body.contains("<br") {
+            let fragment = Html::parse_fragment(body);
+            self.render(&[fragment.root_element()], base)
+        } else {
+            let paragraphs = body.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join("\n\n");
+            (normalize_text(&paragraphs), clean_chars(&paragraphs))
+        };
+
+        let markdown = match item.text_property("headline").or_else(|| item.text_property("name")) {
+            Some(headline) => format!("# {}\n\n{}", clean_chars(headline.trim()), markdown),
+            None => markdown,
+        };
+
+        Some(ExtractedText { text, markdown, strategy: ExtractionStrategy::StructuredData, links: Vec::new() })
+    }
+
+    /// Appends the text below `element`, skipping noise subtrees entirely.
+    /// Whitespace inside text collapses as in a browser; block elements start
+    /// new paragraphs.
+    ///
+    /// The tree is walked with an explicit stack rather than recursion, so
+    /// arbitrarily deep markup cannot overflow the call stack.
+    fn collect_text(&self, element: ElementRef, out: &mut String) {
+        let mut stack: Vec<TextStep> = element.children().rev().map(TextStep::Node).collect();
+        while let Some(step) = stack.pop() {
+            let node = match step {
+                TextStep::Node(node) => node,
+                TextStep::Close(separator) => {
+                    out.push_str(separator);
+                    continue;
+                },
+            };
+            match node.value() {
+                Node::Text(text) => {
+                    out.extend(text.chars().map(|c| if c.is_ascii_whitespace() { ' ' } else { c }));
+                },
+                Node::Element(element) => {
+                    let Some(child) = ElementRef::wrap(node) else { continue };
+                    if self.noise.matches(&child) {
+                        continue;
+                    }
+                    let (open, close) = match element.name() {
+                        "br" | "tr" => ("\n", ""),
+                        "td" | "th" => (" ", " "),
+                        // Preformatted text keeps its line breaks
+                        "pre" => {
+                            out.push_str("\n\n");
+                            out.extend(child.text());
+                            out.push_str("\n\n");
+                            continue;
+                        },
+                        name if INLINE_ELEMENTS.contains(&name) => ("", ""),
+                        _ => ("\n\n", "\n\n"),
+                    };
+                    out.push_str(open);
+                    stack.push(TextStep::Close(close));
+                    stack.extend(node.children().rev().map(TextStep::Node));
+                },
+                _ => {},
             }
-            
-            content = body_content;
-            println!("Using cleaned body content");
         }
     }
-    
-    // Clean up HTML tags and normalize whitespace
-    clean_html_content(&content)
 }
 
-fn clean_html_content(html: &str) -> String {
-    // Simple HTML tag removal
-    let mut result = String::with_capacity(html.len());
-    let mut in_tag = false;
-    let mut in_entity = false;
-    
-    for c in html.chars() {
-        match c {
-            '<' => in_tag = true,
-            '>' => {
-                in_tag = false;
-                result.push(' '); // Replace tags with space
-                continue;
+/// Pending work of `Extractor::collect_text`
+enum TextStep<'a> {
+    /// A node still to be visited
+    Node(NodeRef<'a, Node>),
+    /// The separator written after an element's children
+    Close(&'static str),
+}
+
+/// How the main content of a page was located
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtractionStrategy {
+    /// Content scoring found a clear main-content subtree
+    Readability,
+    /// The first matching content selector (`main`, `article`, ...)
+    Selector,
+    /// The whole body, minus noise
+    Body,
+    /// The `articleBody` of the page's schema.org data
+    StructuredData,
+    /// Text or Markdown supplied by the caller, used unchanged
+    Verbatim,
+    /// The text of a PDF document
+    Pdf,
+    /// Summaries of the latest entries of a feed
+    Feed,
+}
+
+/// Text extracted from a page
+pub struct ExtractedText {
+    /// Plain text, with paragraphs separated by blank lines
+    pub text: String,
+    /// The same content as Markdown, keeping headings, lists, tables and code
+    pub markdown: String,
+    pub strategy: ExtractionStrategy,
+    /// Links found in the content, even when the text came from structured data
+    pub links: Vec<PageLink>,
+}
+
+/// A link found in the extracted content
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PageLink {
+    /// Absolute URL without its fragment
+    pub url: String,
+    pub text: String,
+}
+
+/// Converts an HTML subtree to Markdown.
+///
+/// Block elements are separated by blank lines; headings, ordered and
+/// unordered lists, links, emphasis, blockquotes, fenced code (with the
+/// language taken from `language-*` classes) and GFM tables are preserved.
+/// Noise subtrees are skipped. Elements nested deeper than
+/// `MAX_MARKDOWN_DEPTH` are written as plain text, which keeps the recursion
+/// bounded however deep the markup is.
+struct MarkdownWriter<'a> {
+    noise: &'a Selector,
+    base: Option<&'a Url>,
+    out: String,
+    /// Elements currently being written, including those of enclosing writers
+    depth: usize,
+}
+
+impl<'a> MarkdownWriter<'a> {
+    fn new(noise: &'a Selector, base: Option<&'a Url>) -> Self {
+        MarkdownWriter { noise, base, out: String::new(), depth: 0 }
+    }
+
+    /// A writer for content nested in the current element
+    fn nested(&self) -> Self {
+        MarkdownWriter { depth: self.depth, ..MarkdownWriter::new(self.noise, self.base) }
+    }
+
+    fn finish(self) -> String {
+        self.out.trim().to_string()
+    }
+
+    /// Renders the children of `element` on their own, for nesting in lists, quotes and tables
+    fn render(&self, element: ElementRef) -> String {
+        let mut writer = self.nested();
+        writer.children(element);
+        writer.finish()
+    }
+
+    /// Renders the children of `element` as a single line
+    fn render_inline(&self, element: ElementRef) -> String {
+        self.render(element).split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.text(text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child)
+                        && !self.noise.matches(&child)
+                    {
+                        self.element(child);
+                    }
+                },
+                _ => {},
             }
-            '&' => in_entity = true,
-            ';' => if in_entity {
-                in_entity = false;
-                result.push(' '); // Replace entities with space
-                continue;
-            },
-            _ => {}
         }
-        
-        if !in_tag && !in_entity {
-            result.push(c);
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        if self.depth >= MAX_MARKDOWN_DEPTH {
+            self.plain_text(element);
+            return;
         }
+        self.depth += 1;
+        self.write_element(element);
+        self.depth -= 1;
     }
-    
-    // Normalize whitespace
-    let mut normalized = String::with_capacity(result.len());
-    let mut last_was_whitespace = true;
-    
-    for c in result.chars() {
-        if c.is_whitespace() {
-            if !last_was_whitespace {
-                normalized.push(' ');
-                last_was_whitespace = true;
+
+    /// Appends the text below `element` without formatting, skipping noise.
+    /// Walks an explicit stack, so it is safe at any depth.
+    fn plain_text(&mut self, element: ElementRef) {
+        let mut stack: Vec<NodeRef<Node>> = vec![*element];
+        while let Some(node) = stack.pop() {
+            match node.value() {
+                Node::Text(text) => self.text(text),
+                Node::Element(_) if ElementRef::wrap(node).is_some_and(|element| !self.noise.matches(&element)) => {
+                    stack.extend(node.children().rev());
+                },
+                _ => {},
             }
-        } else {
-            normalized.push(c);
-            last_was_whitespace = false;
         }
     }
-    
-    normalized
+
+    fn write_element(&mut self, element: ElementRef) {
+        let name = element.value().name();
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse::<usize>().unwrap_or(1);
+                let heading = self.render_inline(element);
+                self.block(&format!("{} {}", "#".repeat(level), heading), !heading.is_empty());
+            },
+            "br" => self.line_break(),
+            "hr" => self.block("---", true),
+            "strong" | "b" => self.wrap_inline(element, "**"),
+            "em" | "i" => self.wrap_inline(element, "*"),
+            "del" | "s" => self.wrap_inline(element, "~~"),
+            "code" | "kbd" | "samp" => self.inline_code(element),
+            "a" => self.link(element),
+            "img" => self.image(element),
+            "pre" => self.code_block(element),
+            "ul" => self.list(element, false),
+            "ol" => self.list(element, true),
+            "blockquote" => {
+                let quote = self.render(element);
+                let quoted = quote.lines()
+                    .map(|line| if line.is_empty() { ">".to_string() } else { format!("> {}", line) })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.block(&quoted, !quote.is_empty());
+            },
+            "table" => self.table(element),
+            _ if INLINE_ELEMENTS.contains(&name) => self.children(element),
+            _ => {
+                self.block_break();
+                self.children(element);
+                self.block_break();
+            },
+        }
+    }
+
+    /// Appends text, collapsing whitespace as a browser would
+    fn text(&mut self, text: &str) {
+        for c in text.chars() {
+            if c.is_whitespace() {
+                if !self.out.is_empty() && !self.out.ends_with([' ', '\n']) {
+                    self.out.push(' ');
+                }
+            } else {
+                self.out.push(c);
+            }
+        }
+    }
+
+    fn trim_trailing_spaces(&mut self) {
+        let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(trimmed);
+    }
+
+    fn line_break(&mut self) {
+        self.trim_trailing_spaces();
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// Ends the current block with a blank line
+    fn block_break(&mut self) {
+        self.trim_trailing_spaces();
+        if self.out.is_empty() {
+            return;
+        }
+        while !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn block(&mut self, markdown: &str, keep: bool) {
+        if keep {
+            self.block_break();
+            self.out.push_str(markdown);
+            self.block_break();
+        }
+    }
+
+    /// Wraps inline content in `marker`, keeping surrounding spaces outside the markers
+    fn wrap_inline(&mut self, element: ElementRef, marker: &str) {
+        let mut inner = self.nested();
+        inner.children(element);
+        let content = inner.out.replace('\n', " ");
+        let trimmed = content.trim();
+        if trimmed.is_empty() {
+            self.text(&content);
+            return;
+        }
+        if content.starts_with(char::is_whitespace) {
+            self.text(" ");
+        }
+        self.out.push_str(&format!("{marker}{trimmed}{marker}"));
+        if content.ends_with(char::is_whitespace) {
+            self.text(" ");
+        }
+    }
+
+    fn inline_code(&mut self, element: ElementRef) {
+        let code = element.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ");
+        if code.is_empty() {
+            return;
+        }
+        let fence = "`".repeat(longest_backtick_run(&code) + 1);
+        // Code starting or ending with a backtick needs padding inside the fence
+        let pad = if code.starts_with('`') || code.ends_with('`') { " " } else { "" };
+        self.out.push_str(&format!("{fence}{pad}{code}{pad}{fence}"));
+    }
+
+    fn link(&mut self, element: ElementRef) {
+        let text = self.render_inline(element);
+        let href = element.value().attr("href").map(str::trim).unwrap_or_default();
+        if text.is_empty() {
+            return;
+        }
+        if href.is_empty() || href.starts_with('#') || href.to_ascii_lowercase().starts_with("javascript:") {
+            self.text(&text);
+            return;
+        }
+        let href = self.resolve(href);
+        self.out.push_str(&format!("[{}]({})", text, href));
+    }
+
+    fn image(&mut self, element: ElementRef) {
+        let Some(src) = element.value().attr("src").map(str::trim) else { return };
+        // Inline images would flood the output with base64
+        if src.is_empty() || src.starts_with("data:") {
+            return;
+        }
+        let alt = element.value().attr("alt").unwrap_or_default().split_whitespace().collect::<Vec<_>>().join(" ");
+        let src = self.resolve(src);
+        self.out.push_str(&format!("![{}]({})", alt, src));
+    }
+
+    fn code_block(&mut self, pre: ElementRef) {
+        let code = pre.text().collect::<String>();
+        let code = code.strip_prefix('\n').unwrap_or(&code).trim_end();
+        if code.trim().is_empty() {
+            return;
+        }
+
+        let language = std::iter::once(pre)
+            .chain(pre.children().filter_map(ElementRef::wrap).filter(|child| child.value().name() == "code"))
+            .flat_map(|element| element.value().classes())
+            .find_map(|class| class.strip_prefix("language-").or_else(|| class.strip_prefix("lang-")))
+            .unwrap_or_default();
+
+        let fence = "`".repeat(longest_backtick_run(code).max(2) + 1);
+        self.block(&format!("{fence}{language}\n{code}\n{fence}"), true);
+    }
+
+    fn list(&mut self, list: ElementRef, ordered: bool) {
+        let start = list.value().attr("start").and_then(|start| start.trim().parse::<usize>().ok()).unwrap_or(1);
+
+        let items: Vec<String> = list.children()
+            .filter_map(ElementRef::wrap)
+            .filter(|item| !self.noise.matches(item))
+            .map(|item| self.render(item))
+            .filter(|item| !item.is_empty())
+            .collect();
+        if items.is_empty() {
+            return;
+        }
+
+        let rendered = items.iter().enumerate()
+            .map(|(index, item)| {
+                let marker = if ordered { format!("{}. ", start + index) } else { "- ".to_string() };
+                let indent = " ".repeat(marker.len());
+                item.lines().enumerate()
+                    .map(|(line_no, line)| match (line_no, line.is_empty()) {
+                        (0, _) => format!("{}{}", marker, line),
+                        (_, true) => String::new(),
+                        (_, false) => format!("{}{}", indent, line),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.block(&rendered, true);
+    }
+
+    fn table(&mut self, table: ElementRef) {
+        // Rows of nested tables belong to those tables
+        let rows: Vec<Vec<String>> = table.select(&TABLE_ROW_SELECTOR)
+            .filter(|row| {
+                row.ancestors()
+                    .filter_map(ElementRef::wrap)
+                    .find(|ancestor| ancestor.value().name() == "table")
+                    .is_some_and(|owner| owner.id() == table.id())
+            })
+            .map(|row| {
+                let mut cells = Vec::new();
+                for cell in row.children().filter_map(ElementRef::wrap) {
+                    if !matches!(cell.value().name(), "td" | "th") || self.noise.matches(&cell) {
+                        continue;
+                    }
+                    cells.push(self.render_inline(cell).replace('|', "\\|"));
+                    let span = cell.value().attr("colspan").and_then(|span| span.parse::<usize>().ok()).unwrap_or(1);
+                    cells.extend(std::iter::repeat_n(String::new(), span.clamp(1, 100) - 1));
+                }
+                cells
+            })
+            .filter(|cells| cells.iter().any(|cell| !cell.is_empty()))
+            .collect();
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns < 2 {
+            // Single-column tables are layout, not data
+            self.block_break();
+            self.children(table);
+            self.block_break();
+            return;
+        }
+
+        let format_row = |cells: &[String]| {
+            let padded = (0..columns).map(|i| cells.get(i).map(String::as_str).unwrap_or(""));
+            format!("| {} |", padded.collect::<Vec<_>>().join(" | "))
+        };
+        let mut lines = vec![format_row(&rows[0]), format!("|{}", " --- |".repeat(columns))];
+        lines.extend(rows[1..].iter().map(|row| format_row(row)));
+        self.block(&lines.join("\n"), true);
+    }
+
+    fn resolve(&self, href: &str) -> String {
+        self.base
+            .and_then(|base| base.join(href).ok())
+            .map(|url| url.to_string())
+            .unwrap_or_else(|| href.to_string())
+    }
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
 }
 
 pub fn build_prompt(content: &str) -> String {
@@ -154,4 +864,222 @@ pub fn build_prompt(content: &str) -> String {
     result.push_str("The following is the content of a webpage. Please provide a concise summary formatted in Markdown. Focus on the key points, main ideas, and important details. Use headers, bullet points, and other Markdown formatting to make the summary structured and readable:\n\n");
     result.push_str(content);
     result
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Serves one response on a local port and returns its URL
+    async fn serve_once(response: String) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+        format!("http://{}/", addr)
+    }
+
+    fn local_fetcher(max_body_bytes: usize) -> Fetcher {
+        Fetcher::new(&FetchConfig { allowlist: vec!["127.0.0.1".to_string()], max_body_bytes }).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_declared_oversized_body() {
+        let url = serve_once(format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: 5000\r\n\r\n{}", "a".repeat(5000)
+        )).await;
+        let result = local_fetcher(1000).fetch_html(&url).await;
+        assert!(matches!(result, Err(AppError::ContentTooLarge(_))));
+    }
+
+    #[tokio::test]
+    async fn caps_streamed_body_without_content_length() {
+        let chunk = "a".repeat(600);
+        let url = serve_once(format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ntransfer-encoding: chunked\r\n\r\n258\r\n{chunk}\r\n258\r\n{chunk}\r\n0\r\n\r\n"
+        )).await;
+        let result = local_fetcher(1000).fetch_html(&url).await;
+        assert!(matches!(result, Err(AppError::ContentTooLarge(_))));
+    }
+
+    #[tokio::test]
+    async fn reads_body_within_the_limit() {
+        let html = "<html><body><p>Hello</p></body></html>";
+        let url = serve_once(format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/html\r\ncontent-length: {}\r\n\r\n{}", html.len(), html
+        )).await;
+        let page = local_fetcher(1000).fetch_html(&url).await.unwrap();
+        assert_eq!(page.status, 200);
+    }
+
+    #[test]
+    fn classifies_responses_by_status() {
+        let headers = HeaderMap::new();
+        let classify = |status: u16| classify_response(StatusCode::from_u16(status).unwrap(), &headers, "<p>Page</p>");
+        assert_eq!(classify(200), ResponseClass::Success);
+        assert_eq!(classify(404), ResponseClass::ClientError);
+        assert_eq!(classify(410), ResponseClass::ClientError);
+        assert_eq!(classify(429), ResponseClass::RateLimited);
+        assert_eq!(classify(500), ResponseClass::ServerError);
+        assert_eq!(classify(503), ResponseClass::ServerError);
+    }
+
+    #[test]
+    fn classifies_bot_walls_as_blocked() {
+        let challenge = "<html><head><title>Just a moment...</title></head><body></body></html>";
+        for status in [StatusCode::FORBIDDEN, StatusCode::SERVICE_UNAVAILABLE, StatusCode::OK] {
+            assert_eq!(classify_response(status, &HeaderMap::new(), challenge), ResponseClass::Blocked);
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-mitigated", "challenge".parse().unwrap());
+        assert_eq!(classify_response(StatusCode::FORBIDDEN, &headers, ""), ResponseClass::Blocked);
+        // A plain 403 without a challenge is an ordinary client error
+        assert_eq!(classify_response(StatusCode::FORBIDDEN, &HeaderMap::new(), "Forbidden"), ResponseClass::ClientError);
+    }
+
+    #[tokio::test]
+    async fn maps_upstream_failures_to_errors() {
+        let respond = |status: &str, headers: &str, body: &str| format!(
+            "HTTP/1.1 {}\r\ncontent-type: text/html\r\ncontent-length: {}\r\n{}\r\n{}", status, body.len(), headers, body
+        );
+
+        let url = serve_once(respond("404 Not Found", "", "missing")).await;
+        let err = local_fetcher(1000).fetch_html(&url).await.err().unwrap();
+        assert!(matches!(err, AppError::UpstreamClientError(..)));
+        assert_eq!(err.code(), "UPSTREAM_404");
+
+        let url = serve_once(respond("429 Too Many Requests", "retry-after: 30\r\n", "slow down")).await;
+        let err = local_fetcher(1000).fetch_html(&url).await.err().unwrap();
+        assert!(matches!(&err, AppError::UpstreamRateLimited(_, details) if details.retry_after == Some(30)));
+
+        let url = serve_once(respond("502 Bad Gateway", "", "oops")).await;
+        let err = local_fetcher(1000).fetch_html(&url).await.err().unwrap();
+        assert!(matches!(err, AppError::UpstreamServerError(..)));
+
+        let url = serve_once(respond("503 Service Unavailable", "", "<script>var cf_chl_opt = {};</script>")).await;
+        let err = local_fetcher(1000).fetch_html(&url).await.err().unwrap();
+        assert!(matches!(err, AppError::UpstreamBlocked(..)));
+    }
+
+    /// Markup nested this deep would overflow the stack of a recursive walk
+    fn deeply_nested(depth: usize) -> String {
+        format!("<html><body>{}deep text{}</body></html>", "<span>".repeat(depth), "</span>".repeat(depth))
+    }
+
+    #[test]
+    fn collects_text_of_deeply_nested_markup() {
+        let extractor = Extractor::new(&ExtractConfig { extra_noise_selectors: None, max_input_bytes: 0 }).unwrap();
+        let document = Html::parse_document(&deeply_nested(100_000));
+        let mut text = String::new();
+        extractor.collect_text(document.root_element(), &mut text);
+        assert_eq!(text.trim(), "deep text");
+    }
+
+    #[test]
+    fn collects_text_with_block_and_cell_separators() {
+        let extractor = Extractor::new(&ExtractConfig { extra_noise_selectors: None, max_input_bytes: 0 }).unwrap();
+        let document = Html::parse_document(
+            "<p>One <b>bold</b> word</p><nav>Menu</nav><table><tr><td>a</td><td>b</td></tr></table><pre>x\n  y</pre>"
+        );
+        let mut text = String::new();
+        extractor.collect_text(document.root_element(), &mut text);
+        assert_eq!(normalize_text(&text), normalize_text("One bold word\n\na b\n\nx\n  y"));
+    }
+
+    #[test]
+    fn extracts_deeply_nested_markup() {
+        let extractor = Extractor::new(&ExtractConfig { extra_noise_selectors: None, max_input_bytes: 0 }).unwrap();
+        let extracted = extractor.extract(&deeply_nested(100_000), Some("https://example.com/"), &[]);
+        assert_eq!(extracted.text, "deep text");
+        assert_eq!(extracted.markdown, "deep text");
+    }
+
+    /// Converts the body of `html` to Markdown, resolving URLs against `base`
+    fn markdown(html: &str, base: Option<&str>) -> String {
+        let document = Html::parse_document(html);
+        let noise = Selector::parse(NOISE_SELECTORS).unwrap();
+        let base = base.map(|base| Url::parse(base).unwrap());
+        let body = document.select(&BODY_SELECTOR).next().unwrap();
+        let mut writer = MarkdownWriter::new(&noise, base.as_ref());
+        writer.element(body);
+        writer.finish()
+    }
+
+    #[test]
+    fn writes_heading_levels() {
+        assert_eq!(
+            markdown("<h1>Title</h1><h3> Sub  <em>part</em> </h3><h6>Small</h6><h2></h2>", None),
+            "# Title\n\n### Sub *part*\n\n###### Small"
+        );
+    }
+
+    #[test]
+    fn writes_nested_lists_with_start_numbers() {
+        let html = "<ol start=\"3\"><li>Third<ul><li>Point</li><li>Other point</li></ul></li><li>Fourth</li></ol>";
+        assert_eq!(markdown(html, None), "3. Third\n\n   - Point\n   - Other point\n4. Fourth");
+    }
+
+    #[test]
+    fn resolves_links_and_images_against_the_base_url() {
+        let html = "<p><a href=\"../about\">About <b>us</b></a> <a href=\"#top\">Top</a> \
+                    <img src=\"/logo.png\" alt=\"The  logo\"> <img src=\"data:image/png;base64,AAAA\"></p>";
+        assert_eq!(
+            markdown(html, Some("https://example.com/blog/post")),
+            "[About **us**](https://example.com/about) Top ![The logo](https://example.com/logo.png)"
+        );
+    }
+
+    #[test]
+    fn fences_code_with_its_language() {
+        let html = "<pre><code class=\"hljs language-rust\">fn main() {\n    println!(\"```\");\n}\n</code></pre>";
+        assert_eq!(markdown(html, None), "````rust\nfn main() {\n    println!(\"```\");\n}\n````");
+    }
+
+    #[test]
+    fn writes_tables_with_colspans_and_escaped_pipes() {
+        let html = "<table><tr><th>Name</th><th>Value</th><th>Note</th></tr>\
+                    <tr><td>a|b</td><td colspan=\"2\">wide</td></tr><tr><td>c</td><td>1</td><td>ok</td></tr></table>";
+        assert_eq!(
+            markdown(html, None),
+            "| Name | Value | Note |\n| --- | --- | --- |\n| a\\|b | wide |  |\n| c | 1 | ok |"
+        );
+    }
+
+    #[test]
+    fn quotes_blockquotes_line_by_line() {
+        let html = "<blockquote><p>First paragraph</p><p>Second <i>one</i></p></blockquote>";
+        assert_eq!(markdown(html, None), "> First paragraph\n>\n> Second *one*");
+    }
+
+    #[test]
+    fn flattens_markdown_nested_beyond_the_depth_limit() {
+        // Every level recurses through quotes, lists and blocks; run
+        // on a stack no larger than a runtime worker's
+        let levels = 1000;
+        let html = format!(
+            "<html><body>{}<a href=\"/x\"><b>deep text</b></a>{}</body></html>",
+            "<blockquote><ul><li><div><span>".repeat(levels),
+            "</span></div></li></ul></blockquote>".repeat(levels),
+        );
+        let markdown = std::thread::Builder::new()
+            .stack_size(2 * 1024 * 1024)
+            .spawn(move || {
+                let document = Html::parse_document(&html);
+                let noise = Selector::parse(NOISE_SELECTORS).unwrap();
+                let mut writer = MarkdownWriter::new(&noise, None);
+                writer.element(document.root_element());
+                writer.finish()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert!(markdown.starts_with("> - "));
+        assert!(markdown.contains("deep text"));
+    }
+}
@@ -0,0 +1,222 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use crate::error::{AppError, Result};
+
+type InFlight<T> = Shared<BoxFuture<'static, Result<T>>>;
+
+struct Entry<T> {
+    shared: InFlight<T>,
+    /// Callers currently awaiting the computation
+    waiters: usize,
+    abort: AbortHandle,
+    /// Tells this computation apart from a later one for the same key
+    generation: u64,
+}
+
+struct Inner<T> {
+    entries: HashMap<String, Entry<T>>,
+    next_generation: u64,
+}
+
+/// Deduplicates concurrent computations that share a key.
+///
+/// The first caller for a key starts the computation on its own task; callers
+/// arriving while it is still running wait on the same task and receive a
+/// clone of its result. The computation is aborted once every waiter has gone
+/// away, so abandoned requests stop fetching and calling the LLM, and fails
+/// once it runs past the deadline, so a computation that waits on itself
+/// cannot hold its key forever.
+pub struct SingleFlight<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+    deadline: Duration,
+}
+
+impl<T: Clone + Send + Sync + 'static> SingleFlight<T> {
+    pub fn new(deadline: Duration) -> Self {
+        SingleFlight {
+            inner: Arc::new(Mutex::new(Inner { entries: HashMap::new(), next_generation: 0 })),
+            deadline,
+        }
+    }
+
+    /// Runs `work` unless a computation for `key` is already in flight, in
+    /// which case its result is awaited instead. The returned flag is true
+    /// when this call joined an existing computation.
+    pub async fn run<F>(&self, key: &str, work: F) -> (Result<T>, bool)
+    where
+        F: Future<Output = Result<T>> + Send + 'static,
+    {
+        let (shared, joined, generation) = {
+            let mut inner = self.inner.lock().unwrap();
+            match inner.entries.get_mut(key) {
+                Some(entry) => {
+                    entry.waiters += 1;
+                    (entry.shared.clone(), true, entry.generation)
+                },
+                None => {
+                    let generation = inner.next_generation;
+                    inner.next_generation += 1;
+
+                    let task_inner = self.inner.clone();
+                    let task_key = key.to_string();
+                    let deadline = self.deadline;
+                    let handle = tokio::spawn(async move {
+                        // Forget the key when the work ends, even if it panics or is aborted
+                        let _guard = Forget { inner: task_inner, key: task_key, generation };
+                        tokio::time::timeout(deadline, work).await.unwrap_or_else(|_| {
+                            Err(AppError::Timeout(format!("Request processing did not finish within {:?}", deadline)))
+                        })
+                    });
+                    let abort = handle.abort_handle();
+
+                    let shared = async move {
+                        handle.await.unwrap_or_else(|e| {
+                            let message = if e.is_cancelled() {
+                                "Request processing was cancelled".to_string()
+                            } else {
+                                format!("Request processing failed: {}", e)
+                            };
+                            Err(AppError::InternalError(message))
+                        })
+                    }
+                    .boxed()
+                    .shared();
+
+                    inner.entries.insert(key.to_string(), Entry { shared: shared.clone(), waiters: 1, abort, generation });
+                    (shared, false, generation)
+                }
+            }
+        };
+
+        // Dropped when this caller stops waiting, whether or not the result arrived
+        let _waiter = Waiter { inner: self.inner.clone(), key: key.to_string(), generation };
+        (shared.await, joined)
+    }
+}
+
+/// Removes the entry of a finished computation
+struct Forget<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+    key: String,
+    generation: u64,
+}
+
+impl<T> Drop for Forget<T> {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.inner.lock()
+            && inner.entries.get(&self.key).is_some_and(|entry| entry.generation == self.generation)
+        {
+            inner.entries.remove(&self.key);
+        }
+    }
+}
+
+/// Counts a caller out, aborting the computation when it was the last one
+struct Waiter<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+    key: String,
+    generation: u64,
+}
+
+impl<T> Drop for Waiter<T> {
+    fn drop(&mut self) {
+        let Ok(mut inner) = self.inner.lock() else { return };
+        let Some(entry) = inner.entries.get_mut(&self.key).filter(|entry| entry.generation == self.generation) else {
+            return;
+        };
+
+        entry.waiters -= 1;
+        if entry.waiters == 0 {
+            println!("Aborting abandoned computation for {}", self.key);
+            entry.abort.abort();
+            inner.entries.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn is_idle<T>(flight: &SingleFlight<T>) -> bool {
+        flight.inner.lock().unwrap().entries.is_empty()
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_computation() {
+        let flight = SingleFlight::<usize>::new(Duration::from_secs(5));
+        let runs = Arc::new(AtomicUsize::new(0));
+        let work = |runs: Arc<AtomicUsize>| async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(runs.fetch_add(1, Ordering::SeqCst) + 1)
+        };
+
+        let (first, second) = tokio::join!(
+            flight.run("key", work(runs.clone())),
+            flight.run("key", work(runs.clone())),
+        );
+        assert_eq!(first.0.unwrap(), 1);
+        assert_eq!(second.0.unwrap(), 1);
+        assert!(!first.1 && second.1);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(is_idle(&flight));
+    }
+
+    #[tokio::test]
+    async fn abandoned_computation_is_aborted() {
+        let flight = SingleFlight::<()>::new(Duration::from_secs(5));
+        let finished = Arc::new(AtomicUsize::new(0));
+        let task_finished = finished.clone();
+
+        let call = flight.run("key", async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            task_finished.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(10), call).await.is_err());
+        assert!(is_idle(&flight));
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn computation_continues_while_a_waiter_remains() {
+        let flight = Arc::new(SingleFlight::<u32>::new(Duration::from_secs(5)));
+        let work = || async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(7)
+        };
+
+        let leader = flight.run("key", work());
+        let follower = {
+            let flight = flight.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                flight.run("key", work()).await
+            })
+        };
+        // The leader gives up, but the follower still receives the result
+        assert!(tokio::time::timeout(Duration::from_millis(20), leader).await.is_err());
+        let (result, joined) = follower.await.unwrap();
+        assert_eq!(result.unwrap(), 7);
+        assert!(joined);
+    }
+
+    #[tokio::test]
+    async fn computation_fails_after_the_deadline() {
+        let flight = SingleFlight::<()>::new(Duration::from_millis(20));
+        let (result, _) = flight.run("key", async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        }).await;
+        assert!(matches!(result, Err(AppError::Timeout(_))));
+        assert!(is_idle(&flight));
+    }
+}
@@ -0,0 +1,349 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::Url;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use crate::error::{AppError, Result};
+
+/// Error raised when a URL or resolved address is not allowed to be fetched
+#[derive(Debug)]
+pub struct BlockedUrlError(pub String);
+
+impl fmt::Display for BlockedUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for BlockedUrlError {}
+
+/// An allowlist entry that exempts a destination from the SSRF checks
+#[derive(Debug, Clone)]
+enum AllowRule {
+    /// An address range such as `10.0.0.0/8` or a single address
+    Network { addr: IpAddr, prefix: u8 },
+    /// An exact host name such as `wiki.internal`
+    Host(String),
+    /// Every subdomain of a domain, written as `*.corp.example`
+    DomainSuffix(String),
+}
+
+impl AllowRule {
+    fn parse(entry: &str) -> Result<Self> {
+        let entry = entry.trim().to_ascii_lowercase();
+        let invalid = |reason: &str| AppError::ConfigError(format!("Invalid SSRF_ALLOWLIST entry '{}': {}", entry, reason));
+
+        if let Some(domain) = entry.strip_prefix("*.") {
+            return Ok(AllowRule::DomainSuffix(domain.to_string()));
+        }
+
+        let (addr, prefix) = match entry.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (entry.as_str(), None),
+        };
+
+        match addr.parse::<IpAddr>() {
+            Ok(addr) => {
+                let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid("bad prefix length"))?,
+                    None => max_prefix,
+                };
+                if prefix > max_prefix {
+                    return Err(invalid("prefix length out of range"));
+                }
+                Ok(AllowRule::Network { addr, prefix })
+            },
+            Err(_) if prefix.is_none() => Ok(AllowRule::Host(entry)),
+            Err(_) => Err(invalid("not an IP network")),
+        }
+    }
+
+    fn allows_ip(&self, ip: IpAddr) -> bool {
+        match (self, ip) {
+            (AllowRule::Network { addr: IpAddr::V4(net), prefix }, IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*net) & mask == u32::from(ip) & mask
+            },
+            (AllowRule::Network { addr: IpAddr::V6(net), prefix }, IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(*net) & mask == u128::from(ip) & mask
+            },
+            _ => false,
+        }
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        match self {
+            AllowRule::Host(allowed) => host == allowed,
+            AllowRule::DomainSuffix(domain) => {
+                host.strip_suffix(domain.as_str()).is_some_and(|rest| rest.ends_with('.'))
+            },
+            AllowRule::Network { .. } => false,
+        }
+    }
+}
+
+/// Decides which URLs and addresses user-supplied URLs may point at.
+///
+/// Only `http` and `https` are allowed, and loopback, private, link-local,
+/// multicast and other non-public addresses are rejected unless the
+/// destination is covered by the configured allowlist.
+#[derive(Debug, Clone, Default)]
+pub struct UrlPolicy {
+    allowlist: Vec<AllowRule>,
+}
+
+impl UrlPolicy {
+    pub fn new(allowlist: &[String]) -> Result<Self> {
+        let allowlist = allowlist.iter()
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| AllowRule::parse(entry))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(UrlPolicy { allowlist })
+    }
+
+    /// Checks the parts of a URL that can be validated without DNS: the
+    /// scheme and, for IP literals, the address itself
+    pub fn check_url(&self, url: &Url) -> std::result::Result<(), BlockedUrlError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(BlockedUrlError(format!("URL scheme '{}' is not allowed", url.scheme())));
+        }
+
+        let host = url.host_str()
+            .ok_or_else(|| BlockedUrlError("URL has no host".to_string()))?;
+
+        // IPv6 literals keep their brackets in `host_str`
+        match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(ip) => self.check_ip(ip),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn check_ip(&self, ip: IpAddr) -> std::result::Result<(), BlockedUrlError> {
+        if is_public(ip) || self.allowlist.iter().any(|rule| rule.allows_ip(ip)) {
+            Ok(())
+        } else {
+            Err(BlockedUrlError(format!("Address {} is not publicly routable", ip)))
+        }
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.allowlist.iter().any(|rule| rule.allows_host(&host))
+    }
+}
+
+/// DNS resolver that drops addresses the policy does not allow.
+///
+/// Checking at resolution time covers every redirect hop and also defeats DNS
+/// rebinding, because the connection is made to exactly the addresses that
+/// were checked.
+pub struct GuardedResolver {
+    policy: Arc<UrlPolicy>,
+}
+
+impl GuardedResolver {
+    pub fn new(policy: Arc<UrlPolicy>) -> Self {
+        GuardedResolver { policy }
+    }
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.policy.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+
+            if policy.allows_host(&host) {
+                return Ok(Box::new(addrs.into_iter()) as Addrs);
+            }
+
+            let allowed: Vec<SocketAddr> = addrs.iter()
+                .copied()
+                .filter(|addr| policy.check_ip(addr.ip()).is_ok())
+                .collect();
+
+            if allowed.is_empty() {
+                let blocked = addrs.first().map(|addr| addr.ip().to_string()).unwrap_or_default();
+                println!("Blocked request to {} (resolves to {})", host, blocked);
+                return Err(Box::new(BlockedUrlError(format!(
+                    "Host {} resolves to a non-public address ({})", host, blocked
+                ))) as Box<dyn std::error::Error + Send + Sync>);
+            }
+
+            Ok(Box::new(allowed.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Finds a `BlockedUrlError` anywhere in an error's source chain
+pub fn find_blocked<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a BlockedUrlError> {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(blocked) = err.downcast_ref::<BlockedUrlError>() {
+            return Some(blocked);
+        }
+        current = err.source();
+    }
+    None
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || octets[0] == 0                                  // "this network"
+        || (octets[0] == 100 && (octets[1] & 0xc0) == 64)  // carrier-grade NAT, 100.64.0.0/10
+        || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0) // IETF protocol assignments
+        || (octets[0] == 198 && (octets[1] & 0xfe) == 18)  // benchmarking, 198.18.0.0/15
+        || octets[0] >= 240)                               // reserved
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    // IPv4-mapped and NAT64 addresses reach the embedded IPv4 address
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(v4);
+    }
+    let segments = ip.segments();
+    if segments[0] == 0x64 && segments[1] == 0xff9b && segments[2..6] == [0, 0, 0, 0] {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+        return is_public_v4(Ipv4Addr::new(a, b, c, d));
+    }
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || (segments[0] & 0xfe00) == 0xfc00  // unique local, fc00::/7
+        || (segments[0] & 0xffc0) == 0xfe80  // link-local, fe80::/10
+        || (segments[0] & 0xffc0) == 0xfec0  // deprecated site-local, fec0::/10
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8)) // documentation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4(addr: &str) -> bool {
+        is_public_v4(addr.parse().unwrap())
+    }
+
+    fn v6(addr: &str) -> bool {
+        is_public_v6(addr.parse().unwrap())
+    }
+
+    fn policy(allowlist: &[&str]) -> UrlPolicy {
+        UrlPolicy::new(&allowlist.iter().map(|entry| entry.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    fn check(policy: &UrlPolicy, url: &str) -> bool {
+        policy.check_url(&Url::parse(url).unwrap()).is_ok()
+    }
+
+    #[test]
+    fn public_ipv4_addresses_are_allowed() {
+        assert!(v4("93.184.216.34"));
+        assert!(v4("8.8.8.8"));
+        assert!(v4("100.128.0.1"));
+    }
+
+    #[test]
+    fn non_public_ipv4_addresses_are_blocked() {
+        for addr in [
+            "127.0.0.1", "127.255.255.254", "0.0.0.0", "0.1.2.3", "10.0.0.1", "172.16.0.1", "192.168.1.1",
+            "169.254.169.254", "169.254.0.1", "100.64.0.1", "192.0.0.8", "198.18.0.1", "192.0.2.1",
+            "224.0.0.1", "255.255.255.255", "240.0.0.1",
+        ] {
+            assert!(!v4(addr), "{} should be blocked", addr);
+        }
+    }
+
+    #[test]
+    fn public_ipv6_addresses_are_allowed() {
+        assert!(v6("2606:4700:4700::1111"));
+        assert!(v6("::ffff:93.184.216.34"));
+        assert!(v6("64:ff9b::808:808"));
+    }
+
+    #[test]
+    fn non_public_ipv6_addresses_are_blocked() {
+        for addr in [
+            "::1", "::", "fe80::1", "fc00::1", "fd12:3456::1", "fec0::1", "ff02::1", "2001:db8::1",
+            // IPv4-mapped and NAT64 forms of loopback, private and metadata addresses
+            "::ffff:127.0.0.1", "::ffff:10.0.0.1", "::ffff:169.254.169.254",
+            "64:ff9b::7f00:1", "64:ff9b::a9fe:a9fe", "64:ff9b::c0a8:101",
+        ] {
+            assert!(!v6(addr), "{} should be blocked", addr);
+        }
+    }
+
+    #[test]
+    fn parses_allowlist_entries() {
+        assert!(matches!(AllowRule::parse("10.0.0.0/8").unwrap(), AllowRule::Network { prefix: 8, .. }));
+        assert!(matches!(AllowRule::parse("192.168.1.5").unwrap(), AllowRule::Network { prefix: 32, .. }));
+        assert!(matches!(AllowRule::parse("fd00::/8").unwrap(), AllowRule::Network { prefix: 8, .. }));
+        assert!(matches!(AllowRule::parse(" Wiki.Internal ").unwrap(), AllowRule::Host(host) if host == "wiki.internal"));
+        assert!(matches!(AllowRule::parse("*.corp.example").unwrap(), AllowRule::DomainSuffix(domain) if domain == "corp.example"));
+    }
+
+    #[test]
+    fn rejects_invalid_allowlist_entries() {
+        assert!(AllowRule::parse("10.0.0.0/33").is_err());
+        assert!(AllowRule::parse("fd00::/129").is_err());
+        assert!(AllowRule::parse("10.0.0.0/abc").is_err());
+        assert!(AllowRule::parse("intranet/8").is_err());
+        assert!(UrlPolicy::new(&["10.0.0.0/8".to_string(), "bad/prefix".to_string()]).is_err());
+        assert!(UrlPolicy::new(&[" ".to_string()]).is_ok());
+    }
+
+    #[test]
+    fn allowlist_exempts_networks_and_hosts() {
+        let policy = policy(&["10.0.0.0/8", "fd00::/8", "wiki.internal", "*.corp.example"]);
+        assert!(policy.check_ip("10.1.2.3".parse().unwrap()).is_ok());
+        assert!(policy.check_ip("fd00::1".parse().unwrap()).is_ok());
+        assert!(policy.check_ip("192.168.1.1".parse().unwrap()).is_err());
+        assert!(policy.check_ip("169.254.169.254".parse().unwrap()).is_err());
+
+        assert!(policy.allows_host("wiki.internal"));
+        assert!(policy.allows_host("WIKI.internal."));
+        assert!(policy.allows_host("docs.corp.example"));
+        assert!(!policy.allows_host("corp.example"));
+        assert!(!policy.allows_host("evilcorp.example"));
+        assert!(!policy.allows_host("wiki.internal.evil.com"));
+    }
+
+    #[test]
+    fn checks_urls_and_redirect_targets() {
+        let strict = UrlPolicy::default();
+        assert!(check(&strict, "https://example.com/page"));
+        assert!(check(&strict, "http://93.184.216.34/"));
+
+        // Redirect hops are checked the same way, before they are followed
+        assert!(!check(&strict, "file:///etc/passwd"));
+        assert!(!check(&strict, "gopher://example.com/"));
+        assert!(!check(&strict, "http://127.0.0.1:8080/admin"));
+        assert!(!check(&strict, "http://169.254.169.254/latest/meta-data/"));
+        assert!(!check(&strict, "http://[::1]/"));
+        assert!(!check(&strict, "http://[::ffff:169.254.169.254]/"));
+        assert!(!check(&strict, "http://[fd00::1]/"));
+        assert!(!check(&strict, "http://0x7f000001/"));
+
+        let allowed = policy(&["127.0.0.0/8"]);
+        assert!(check(&allowed, "http://127.0.0.1:8080/"));
+    }
+}
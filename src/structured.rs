@@ -0,0 +1,323 @@
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::text::decode_entities;
+
+static JSON_LD_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("script[type]").expect("Failed to parse JSON-LD selector")
+});
+
+// Items nested in another item are reached through their parent's properties
+static MICRODATA_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("[itemscope]:not([itemprop])").expect("Failed to parse microdata selector")
+});
+
+static RDFA_SELECTOR: Lazy<Selector> = Lazy::new(|| {
+    Selector::parse("[typeof]:not([property])").expect("Failed to parse RDFa selector")
+});
+
+// Items nested deeper than this in another item are kept as their text
+const MAX_ITEM_DEPTH: usize = 32;
+
+const SCHEMA_PREFIXES: &[&str] = &["https://schema.org/", "http://schema.org/", "schema:"];
+
+/// Where a structured data item was found
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StructuredFormat {
+    JsonLd,
+    Microdata,
+    Rdfa,
+}
+
+/// A schema.org item embedded in a page, in the same shape whatever its syntax.
+///
+/// Types and `@type` values of nested items are shortened from
+/// `https://schema.org/Article` to `Article`; nested items are objects with
+/// an `@type` next to their properties; repeated properties become arrays.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StructuredItem {
+    pub format: StructuredFormat,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    pub properties: Map<String, Value>,
+}
+
+impl StructuredItem {
+    /// Whether the item is an article or blog post, whose `articleBody` holds the page text
+    pub fn is_article(&self) -> bool {
+        self.types.iter().any(|kind| kind.ends_with("Article") || kind.ends_with("Posting") || kind == "Report")
+    }
+
+    pub fn text_property(&self, name: &str) -> Option<&str> {
+        match self.properties.get(name)? {
+            Value::String(text) => Some(text),
+            Value::Array(values) => values.iter().find_map(Value::as_str),
+            _ => None,
+        }
+    }
+}
+
+/// Extracts JSON-LD, microdata and RDFa Lite items from a full HTML document.
+/// URLs taken from `src` and `href` attributes are resolved against `page_url`.
+pub fn extract_structured_data(html: &str, page_url: Option<&str>) -> Vec<StructuredItem> {
+    let document = Html::parse_document(html);
+    let base = page_url.and_then(|url| Url::parse(url).ok());
+
+    let mut items = Vec::new();
+    for script in document.select(&JSON_LD_SELECTOR) {
+        let is_json_ld = script.value().attr("type")
+            .is_some_and(|kind| kind.trim().to_ascii_lowercase().starts_with("application/ld+json"));
+        if is_json_ld {
+            items.extend(parse_json_ld(&script.text().collect::<String>()));
+        }
+    }
+
+    for element in document.select(&MICRODATA_SELECTOR) {
+        let types = element.value().attr("itemtype").map(split_types).unwrap_or_default();
+        let properties = collect_properties(element, &MICRODATA, base.as_ref());
+        if !types.is_empty() || !properties.is_empty() {
+            items.push(StructuredItem { format: StructuredFormat::Microdata, types, properties });
+        }
+    }
+
+    for element in document.select(&RDFA_SELECTOR) {
+        let types = element.value().attr("typeof").map(split_types).unwrap_or_default();
+        let properties = collect_properties(element, &RDFA, base.as_ref());
+        if !properties.is_empty() {
+            items.push(StructuredItem { format: StructuredFormat::Rdfa, types, properties });
+        }
+    }
+
+    items
+}
+
+fn parse_json_ld(source: &str) -> Vec<StructuredItem> {
+    // Some sites wrap the JSON in comments or CDATA, or leave raw line breaks inside strings
+    let source = source.trim()
+        .trim_start_matches("<!--").trim_end_matches("-->")
+        .trim()
+        .trim_start_matches("//<![CDATA[").trim_end_matches("//]]>")
+        .trim()
+        .trim_end_matches(';');
+    let parsed = serde_json::from_str::<Value>(source)
+        .or_else(|_| serde_json::from_str::<Value>(&source.replace(['\n', '\r', '\t'], " ")));
+    let value = match parsed {
+        Ok(value) => value,
+        Err(e) => {
+            println!("Skipping invalid JSON-LD block: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut nodes = Vec::new();
+    flatten_graph(value, &mut nodes);
+
+    nodes.into_iter()
+        .filter_map(|node| {
+            let Value::Object(mut properties) = normalize_json(node) else { return None };
+            properties.remove("@context");
+            let types = match properties.remove("@type")? {
+                Value::String(kind) => vec![kind],
+                Value::Array(kinds) => kinds.into_iter().filter_map(|kind| kind.as_str().map(str::to_string)).collect(),
+                _ => return None,
+            };
+            Some(StructuredItem { format: StructuredFormat::JsonLd, types, properties })
+        })
+        .collect()
+}
+
+/// Collects the top-level nodes of a JSON-LD document, unwrapping arrays and `@graph`
+fn flatten_graph(value: Value, nodes: &mut Vec<Value>) {
+    match value {
+        Value::Array(values) => values.into_iter().for_each(|value| flatten_graph(value, nodes)),
+        Value::Object(mut object) => {
+            if let Some(graph) = object.remove("@graph") {
+                flatten_graph(graph, nodes);
+            }
+            if object.contains_key("@type") {
+                nodes.push(Value::Object(object));
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Decodes HTML entities in strings and shortens schema.org type names, recursively
+fn normalize_json(value: Value) -> Value {
+    match value {
+        Value::String(text) => Value::String(decode_entities(&text)),
+        Value::Array(values) => Value::Array(values.into_iter().map(normalize_json).collect()),
+        Value::Object(object) => Value::Object(
+            object.into_iter()
+                .map(|(key, value)| {
+                    let value = match (key.as_str(), value) {
+                        ("@type", Value::String(kind)) => Value::String(strip_schema_prefix(&kind)),
+                        (_, value) => normalize_json(value),
+                    };
+                    (strip_schema_prefix(&key), value)
+                })
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// The attributes that mark items and properties in one attribute-based syntax
+struct Vocabulary {
+    /// Present on every element that starts an item
+    item: &'static str,
+    types: &'static str,
+    property: &'static str,
+}
+
+const MICRODATA: Vocabulary = Vocabulary { item: "itemscope", types: "itemtype", property: "itemprop" };
+const RDFA: Vocabulary = Vocabulary { item: "typeof", types: "typeof", property: "property" };
+
+/// Collects the properties of the item started by `element`. Properties of
+/// nested items belong to those items and become nested objects.
+fn collect_properties(element: ElementRef, vocabulary: &Vocabulary, base: Option<&Url>) -> Map<String, Value> {
+    item_properties(element, vocabulary, base, 0)
+}
+
+/// Collects the properties of an item nested `depth` items deep. Descendants
+/// are walked with an explicit stack and only nested items recurse, so deeply
+/// nested markup cannot overflow the call stack.
+fn item_properties(element: ElementRef, vocabulary: &Vocabulary, base: Option<&Url>, depth: usize) -> Map<String, Value> {
+    let mut properties = Map::new();
+    let mut stack: Vec<ElementRef> = element.children().filter_map(ElementRef::wrap).rev().collect();
+
+    while let Some(child) = stack.pop() {
+        let starts_item = child.value().attr(vocabulary.item).is_some();
+
+        if let Some(names) = child.value().attr(vocabulary.property) {
+            let value = if starts_item && depth < MAX_ITEM_DEPTH {
+                let mut nested = item_properties(child, vocabulary, base, depth + 1);
+                if let Some(types) = child.value().attr(vocabulary.types).map(split_types)
+                    && !types.is_empty()
+                {
+                    nested.insert("@type".to_string(), Value::String(types.join(" ")));
+                }
+                Value::Object(nested)
+            } else {
+                Value::String(property_value(child, base))
+            };
+
+            for name in names.split_whitespace() {
+                insert_repeated(&mut properties, strip_schema_prefix(name), value.clone());
+            }
+        }
+
+        if !starts_item {
+            stack.extend(child.children().filter_map(ElementRef::wrap).rev());
+        }
+    }
+    properties
+}
+
+/// The value of a microdata or RDFa property element, following the microdata rules
+fn property_value(element: ElementRef, base: Option<&Url>) -> String {
+    let value = element.value();
+    let url_attr = match value.name() {
+        "audio" | "embed" | "iframe" | "img" | "source" | "track" | "video" => Some("src"),
+        "a" | "area" | "link" => Some("href"),
+        "object" => Some("data"),
+        _ => None,
+    };
+
+    let text = if let Some(content) = value.attr("content") {
+        content.to_string()
+    } else if let Some(url) = url_attr.and_then(|attr| value.attr(attr)) {
+        let url = url.trim();
+        base.and_then(|base| base.join(url).ok())
+            .map(|resolved| resolved.to_string())
+            .unwrap_or_else(|| url.to_string())
+    } else if let Some(attr) = match value.name() {
+        "data" | "meter" => value.attr("value"),
+        "time" => value.attr("datetime"),
+        _ => None,
+    } {
+        attr.to_string()
+    } else {
+        element.text().collect()
+    };
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn insert_repeated(properties: &mut Map<String, Value>, name: String, value: Value) {
+    match properties.get_mut(&name) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        },
+        None => {
+            properties.insert(name, value);
+        },
+    }
+}
+
+fn split_types(types: &str) -> Vec<String> {
+    types.split_whitespace().map(strip_schema_prefix).collect()
+}
+
+fn strip_schema_prefix(name: &str) -> String {
+    SCHEMA_PREFIXES.iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_nested_microdata_items() {
+        let html = r#"<div itemscope itemtype="https://schema.org/Article">
+            <h1 itemprop="headline">Title</h1>
+            <div><span itemprop="author" itemscope itemtype="https://schema.org/Person"><span itemprop="name">Ada</span></span></div>
+            <a itemprop="url" href="/post">link</a>
+        </div>"#;
+        let items = extract_structured_data(html, Some("https://example.com/blog/"));
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].types, ["Article"]);
+        assert_eq!(items[0].text_property("headline"), Some("Title"));
+        assert_eq!(items[0].text_property("url"), Some("https://example.com/post"));
+        assert_eq!(items[0].properties["author"]["name"], "Ada");
+        assert_eq!(items[0].properties["author"]["@type"], "Person");
+    }
+
+    #[test]
+    fn handles_deeply_nested_markup() {
+        let depth = 100_000;
+        let html = format!(
+            "<div itemscope itemtype=\"https://schema.org/Thing\">{}<span itemprop=\"name\">Deep</span>{}</div>",
+            "<span>".repeat(depth),
+            "</span>".repeat(depth),
+        );
+        let items = extract_structured_data(&html, None);
+        assert_eq!(items[0].text_property("name"), Some("Deep"));
+    }
+
+    #[test]
+    fn caps_the_depth_of_nested_items() {
+        let depth = 100_000;
+        let html = format!(
+            "<div itemscope>{}leaf{}</div>",
+            "<span itemprop=\"part\" itemscope>".repeat(depth),
+            "</span>".repeat(depth),
+        );
+        let items = extract_structured_data(&html, None);
+        let mut value = &Value::Object(items[0].properties.clone());
+        let mut levels = 0;
+        while let Some(part) = value.get("part") {
+            value = part;
+            levels += 1;
+        }
+        assert_eq!(levels, MAX_ITEM_DEPTH + 1);
+        assert_eq!(value, "leaf");
+    }
+}
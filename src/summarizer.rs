@@ -0,0 +1,223 @@
+use futures::future::try_join_all;
+use tokio::sync::Semaphore;
+use crate::chunker::{char_len, chunk_text};
+use crate::config::SummarizerConfig;
+use crate::error::{Result, AppError};
+use crate::limits::Billing;
+use crate::llm::{CompletionRequest, DeltaCallback, LlmProvider, SYSTEM_PROMPT};
+use crate::scraper::build_prompt;
+use crate::usage::SummaryUsage;
+
+// Upper bound on reduce rounds, in case partial summaries stop shrinking
+const MAX_REDUCE_ROUNDS: usize = 4;
+
+/// A summary and the LLM usage of every completion it took
+pub struct Summary {
+    pub text: String,
+    pub usage: SummaryUsage,
+}
+
+/// Summarizes page content, falling back to map-reduce when it does not fit in
+/// a single prompt.
+///
+/// Content that fits in one chunk is summarized directly. Otherwise every chunk
+/// is summarized in parallel (bounded by `max_concurrency`) and the partial
+/// summaries are reduced into one final summary, recursively if the partials
+/// themselves are still too large.
+///
+/// When `on_delta` is given, the final completion (the single prompt or the
+/// last reduce step) is streamed through it as the model produces it. Each
+/// completion is charged to `billing` as soon as it returns and priced at the
+/// model that produced it; the returned usage is the sum of them.
+pub async fn summarize(
+    llm: &dyn LlmProvider,
+    billing: &Billing,
+    content: &str,
+    config: &SummarizerConfig,
+    site_url: Option<&str>,
+    on_delta: Option<DeltaCallback<'_>>,
+) -> Result<Summary> {
+    let chunks = chunk_text(content, config.chunk_size, config.chunk_overlap);
+
+    if chunks.len() <= 1 {
+        println!("Content fits in a single prompt ({} chars)", char_len(content));
+        let prompt = build_prompt(content);
+        return complete(llm, billing, &prompt, site_url, on_delta).await;
+    }
+
+    println!("Content split into {} chunks for map-reduce summarization", chunks.len());
+    let mut usages = Vec::new();
+    let mut partials = map_chunks(llm, billing, &chunks, config, site_url, &mut usages).await?;
+
+    for round in 1..=MAX_REDUCE_ROUNDS {
+        let combined = join_partials(&partials);
+        if char_len(&combined) <= config.chunk_size || round == MAX_REDUCE_ROUNDS {
+            break;
+        }
+
+        // The partial summaries are still too large for one prompt, so summarize them again
+        let chunks = chunk_text(&combined, config.chunk_size, config.chunk_overlap);
+        println!("Reduce round {}: re-summarizing {} chunks of partial summaries", round, chunks.len());
+        partials = map_chunks(llm, billing, &chunks, config, site_url, &mut usages).await?;
+    }
+
+    println!("Reducing {} partial summaries", partials.len());
+    let prompt = build_reduce_prompt(&join_partials(&partials));
+    let summary = complete(llm, billing, &prompt, site_url, on_delta).await?;
+    usages.push(summary.usage);
+    let usage = SummaryUsage::sum(&usages).expect("the final completion was just added");
+    Ok(Summary { text: summary.text, usage })
+}
+
+/// Summarizes every chunk, keeping at most `max_concurrency` requests in flight,
+/// and adds the usage of each completion to `usages`
+async fn map_chunks(
+    llm: &dyn LlmProvider,
+    billing: &Billing,
+    chunks: &[String],
+    config: &SummarizerConfig,
+    site_url: Option<&str>,
+    usages: &mut Vec<SummaryUsage>,
+) -> Result<Vec<String>> {
+    let total = chunks.len();
+    let permits = Semaphore::new(config.max_concurrency);
+
+    let tasks = chunks.iter().enumerate().map(|(i, chunk)| {
+        let permits = &permits;
+        async move {
+            let _permit = permits.acquire().await
+                .map_err(|e| AppError::llm(format!("Summarization aborted: {}", e)))?;
+            println!("Summarizing chunk {}/{} ({} chars)", i + 1, total, char_len(chunk));
+            let prompt = build_chunk_prompt(chunk, i + 1, total);
+            complete(llm, billing, &prompt, site_url, None).await
+        }
+    });
+
+    // Results come back in chunk order regardless of completion order
+    let completions = try_join_all(tasks).await?;
+    Ok(completions.into_iter()
+        .map(|summary| {
+            usages.push(summary.usage);
+            summary.text
+        })
+        .collect())
+}
+
+/// Makes one LLM call, unless the client's quota is used up, and charges it
+async fn complete(
+    llm: &dyn LlmProvider,
+    billing: &Billing,
+    prompt: &str,
+    site_url: Option<&str>,
+    on_delta: Option<DeltaCallback<'_>>,
+) -> Result<Summary> {
+    billing.check_quota()?;
+    let request = CompletionRequest {
+        system: SYSTEM_PROMPT,
+        prompt,
+        site_url,
+        site_name: None,
+    };
+
+    let completion = match on_delta {
+        Some(on_delta) => llm.complete_stream(&request, on_delta).await?,
+        None => llm.complete(&request).await?,
+    };
+    let usage = billing.charge(completion.model.as_deref().unwrap_or(llm.model()), completion.usage).await;
+    Ok(Summary { text: completion.text, usage })
+}
+
+fn join_partials(partials: &[String]) -> String {
+    partials.iter()
+        .map(|partial| partial.trim())
+        .collect::<Vec<_>>()
+        .join("\n\n---\n\n")
+}
+
+fn build_chunk_prompt(chunk: &str, index: usize, total: usize) -> String {
+    let mut result = String::with_capacity(chunk.len() + 250);
+    result.push_str(&format!(
+        "The following is part {} of {} of a webpage. Summarize the key points, facts and important details of this part in Markdown bullet points. Do not add an introduction or conclusion; other parts are summarized separately:\n\n",
+        index, total
+    ));
+    result.push_str(chunk);
+    result
+}
+
+fn build_reduce_prompt(partials: &str) -> String {
+    let mut result = String::with_capacity(partials.len() + 250);
+    result.push_str("The following are summaries of consecutive parts of one webpage, separated by `---`. Combine them into a single concise summary formatted in Markdown. Merge duplicate points, keep the important details, and use headers and bullet points to make the summary structured and readable:\n\n");
+    result.push_str(partials);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+    use crate::config::{ModelPrice, PricingConfig};
+    use crate::limits::ClientLimiter;
+    use crate::llm::{Completion, TokenUsage};
+    use crate::testing;
+
+    /// LLM that answers chunk prompts with one model and reduce prompts with another
+    struct RoutedLlm;
+
+    #[async_trait]
+    impl LlmProvider for RoutedLlm {
+        fn name(&self) -> &'static str {
+            "routed"
+        }
+
+        fn model(&self) -> &str {
+            "router"
+        }
+
+        async fn complete(&self, request: &CompletionRequest<'_>) -> Result<Completion> {
+            let model = if request.prompt.starts_with("The following is part") { "chunk-model" } else { "reduce-model" };
+            Ok(Completion {
+                text: "- A point".to_string(),
+                usage: TokenUsage { prompt_tokens: 1000, completion_tokens: 100, total_tokens: 1100 },
+                model: Some(model.to_string()),
+            })
+        }
+    }
+
+    fn billing() -> Billing {
+        let pricing = PricingConfig {
+            prices: [
+                ("chunk-model".to_string(), ModelPrice { prompt: 1.0, completion: 2.0 }),
+                ("reduce-model".to_string(), ModelPrice { prompt: 10.0, completion: 20.0 }),
+            ].into(),
+        };
+        Billing::new(Arc::new(ClientLimiter::new(pricing)), testing::client("ci"))
+    }
+
+    fn config() -> SummarizerConfig {
+        SummarizerConfig { chunk_size: 200, chunk_overlap: 0, max_concurrency: 2 }
+    }
+
+    #[tokio::test]
+    async fn map_reduce_prices_every_call_at_its_own_model() {
+        let content = "A sentence about the page. ".repeat(20);
+        let chunks = chunk_text(&content, 200, 0).len();
+        assert!(chunks > 1);
+
+        let summary = summarize(&RoutedLlm, &billing(), &content, &config(), None, None).await.unwrap();
+        assert_eq!(summary.text, "- A point");
+        assert_eq!(summary.usage.model, "mixed");
+        assert_eq!(summary.usage.tokens.total_tokens, 1100 * (chunks as u64 + 1));
+        // Each chunk costs $0.0012 and the reduce step $0.012
+        let expected = chunks as f64 * 0.0012 + 0.012;
+        assert!((summary.usage.cost_usd.unwrap() - expected).abs() < 1e-12);
+    }
+
+    #[tokio::test]
+    async fn single_prompt_is_priced_at_the_reported_model() {
+        let summary = summarize(&RoutedLlm, &billing(), "A short page.", &config(), None, None).await.unwrap();
+        assert_eq!(summary.usage.model, "reduce-model");
+        assert_eq!(summary.usage.tokens.total_tokens, 1100);
+        assert!((summary.usage.cost_usd.unwrap() - 0.012).abs() < 1e-12);
+    }
+}
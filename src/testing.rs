@@ -0,0 +1,169 @@
+//! Shared fixtures for unit tests that need a whole `AppState`
+
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use crate::auth::{ApiClient, KeyStore};
+use crate::cache::MemoryCache;
+use crate::config::*;
+use crate::error::{AppError, Result};
+use crate::jobs::JobManager;
+use crate::limits::ClientLimiter;
+use crate::llm::{Completion, CompletionRequest, LlmProvider, TokenUsage};
+use crate::scraper::{Extractor, Fetcher};
+use crate::singleflight::SingleFlight;
+use crate::api::routes::create_router;
+use crate::AppState;
+
+/// LLM that answers every prompt with the same summary after a delay, or
+/// fails when it has no summary
+pub struct FakeLlm {
+    pub summary: Option<String>,
+    pub delay: Duration,
+    pub calls: AtomicUsize,
+    /// Completions currently running, and the most that ever ran at once
+    pub active: AtomicUsize,
+    pub peak: AtomicUsize,
+}
+
+impl FakeLlm {
+    pub fn replying(summary: &str) -> Self {
+        FakeLlm {
+            summary: Some(summary.to_string()),
+            delay: Duration::ZERO,
+            calls: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn failing() -> Self {
+        FakeLlm { summary: None, ..Self::replying("") }
+    }
+
+    pub fn with_delay(self, delay: Duration) -> Self {
+        FakeLlm { delay, ..self }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for FakeLlm {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn model(&self) -> &str {
+        "fake-model"
+    }
+
+    async fn complete(&self, _request: &CompletionRequest<'_>) -> Result<Completion> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(active, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        let text = self.summary.clone().ok_or_else(|| AppError::llm("The fake LLM failed"))?;
+        let usage = TokenUsage { prompt_tokens: 10, completion_tokens: 5, total_tokens: 15 };
+        Ok(Completion { text, usage, model: None })
+    }
+}
+
+/// Configuration with the defaults of every setting, authentication off and
+/// fetches of local test servers allowed
+pub fn config() -> Config {
+    Config {
+        server_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+        request_timeout: Duration::from_secs(90),
+        llm: LlmConfig {
+            provider: LlmProviderKind::Local,
+            base_url: "http://127.0.0.1:9/v1".to_string(),
+            api_key: None,
+            model: "fake-model".to_string(),
+            max_tokens: 100,
+            temperature: 0.0,
+        },
+        pricing: PricingConfig::default(),
+        summarizer: SummarizerConfig { chunk_size: 24_000, chunk_overlap: 500, max_concurrency: 4 },
+        jobs: JobConfig { workers: 4, timeout: Duration::from_secs(600), retention: Duration::from_secs(3600) },
+        batch: BatchConfig { default_concurrency: 4, max_concurrency: 16, max_urls: 500 },
+        feed: FeedConfig { default_items: 10, max_items: 50, concurrency: 4 },
+        cache: CacheConfig {
+            backend: CacheBackend::Memory,
+            ttl: Duration::from_secs(3600),
+            capacity: 100,
+            max_bytes: 1024 * 1024,
+            path: PathBuf::new(),
+        },
+        fetch: FetchConfig { allowlist: vec!["127.0.0.1".to_string()], max_body_bytes: 1024 * 1024 },
+        extract: ExtractConfig { extra_noise_selectors: None, max_input_bytes: 1024 * 1024 },
+        auth: AuthConfig { keys: Vec::new(), disabled: true },
+        limits: LimitsConfig {
+            defaults: ClientLimits { requests_per_minute: 0, burst: 1, daily_tokens: 0, monthly_tokens: 0 },
+        },
+    }
+}
+
+/// Application state built from `config` around the given LLM
+pub fn state_with(config: Config, llm: Arc<dyn LlmProvider>) -> AppState {
+    AppState {
+        fetcher: Arc::new(Fetcher::new(&config.fetch).unwrap()),
+        extractor: Arc::new(Extractor::new(&config.extract).unwrap()),
+        cache: Arc::new(MemoryCache::new(config.cache.capacity, config.cache.max_bytes, config.cache.ttl)),
+        jobs: Arc::new(JobManager::new(&config.jobs)),
+        keys: Arc::new(KeyStore::new(&config.auth, &config.limits)),
+        limits: Arc::new(ClientLimiter::new(config.pricing.clone())),
+        inflight: Arc::new(SingleFlight::new(Duration::from_secs(90))),
+        config: Arc::new(config),
+        llm,
+    }
+}
+
+pub fn state(llm: Arc<dyn LlmProvider>) -> AppState {
+    state_with(config(), llm)
+}
+
+/// Serves the API router for `state` on a local port and returns its base URL
+pub async fn serve(state: AppState) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(state)).await.unwrap() });
+    format!("http://{}", addr)
+}
+
+/// Serves fixed HTTP responses by request path on a local port and returns its
+/// base URL; other paths get a 404
+pub async fn serve_pages(pages: Vec<(&'static str, String)>) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let mut request = [0u8; 4096];
+            let read = socket.read(&mut request).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&request[..read]);
+            let path = request.split_whitespace().nth(1).unwrap_or("/");
+            let response = pages.iter()
+                .find(|(page, _)| *page == path)
+                .map_or_else(|| response("404 Not Found", "text/plain", "", "missing"), |(_, response)| response.clone());
+            let _ = socket.write_all(response.as_bytes()).await;
+        }
+    });
+    format!("http://{}", addr)
+}
+
+/// A complete HTTP response that closes the connection
+pub fn response(status: &str, content_type: &str, headers: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n{}\r\n{}",
+        status, content_type, body.len(), headers, body,
+    )
+}
+
+/// A client with the scrape scope and no limits
+pub fn client(name: &str) -> ApiClient {
+    ApiClient { name: name.to_string(), scopes: vec![ApiScope::Scrape], limits: config().limits.defaults }
+}
@@ -0,0 +1,91 @@
+use unicode_normalization::UnicodeNormalization;
+
+// Characters that are invisible in rendered text and only break up words.
+// Zero-width joiners are kept: emoji sequences and several scripts need them.
+const INVISIBLE_CHARS: &[char] = &[
+    '\u{00AD}', // soft hyphen
+    '\u{180E}', // Mongolian vowel separator
+    '\u{200B}', // zero-width space
+    '\u{2060}', // word joiner
+    '\u{FEFF}', // zero-width no-break space / stray byte order mark
+];
+
+/// Decodes named and numeric HTML entities such as `&amp;`, `&eacute;` and `&#8217;`.
+///
+/// Text taken from the parsed DOM is already decoded by the HTML parser; this
+/// is for text that arrives still escaped, such as feed entries or JSON-LD
+/// values. Decoding DOM text again would corrupt pages that show entities
+/// literally.
+pub fn decode_entities(text: &str) -> String {
+    html_escape::decode_html_entities(text).into_owned()
+}
+
+/// Applies Unicode NFC normalization and drops soft hyphens and zero-width
+/// characters. Non-breaking and other Unicode spaces become plain spaces.
+/// Line structure is left untouched, so this is safe for Markdown and code.
+pub fn clean_chars(text: &str) -> String {
+    text.nfc()
+        .filter(|c| !INVISIBLE_CHARS.contains(c))
+        .map(|c| if c.is_whitespace() && c != '\n' && c != '\t' { ' ' } else { c })
+        .collect()
+}
+
+/// Normalizes extracted text for word counting and prompts.
+///
+/// On top of `clean_chars`, runs of spaces are collapsed and lines are
+/// trimmed. Single line breaks are kept, and two or more consecutive line
+/// breaks become exactly one blank line, so paragraph boundaries survive.
+pub fn normalize_text(text: &str) -> String {
+    let cleaned = clean_chars(text);
+
+    let mut normalized = String::with_capacity(cleaned.len());
+    let mut blank_lines = 0;
+    for line in cleaned.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if !normalized.is_empty() {
+            normalized.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        normalized.push_str(&line);
+        blank_lines = 0;
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_named_and_numeric_entities() {
+        assert_eq!(decode_entities("AT&amp;T"), "AT&T");
+        assert_eq!(decode_entities("caf&eacute;"), "café");
+        assert_eq!(decode_entities("It&#8217;s &#x2014; &lt;b&gt;"), "It\u{2019}s \u{2014} <b>");
+    }
+
+    #[test]
+    fn keeps_bare_ampersands() {
+        assert_eq!(decode_entities("Fish & chips; peas &unknown; AT&T"), "Fish & chips; peas &unknown; AT&T");
+    }
+
+    #[test]
+    fn removes_invisible_characters_and_normalizes_to_nfc() {
+        assert_eq!(clean_chars("hy\u{00AD}phen\u{200B}ated\u{FEFF}"), "hyphenated");
+        // The combining acute accent composes with the preceding letter
+        assert_eq!(clean_chars("cafe\u{0301}"), "caf\u{00E9}");
+        // Zero-width joiners hold emoji sequences together
+        assert_eq!(clean_chars("\u{1F469}\u{200D}\u{1F4BB}"), "\u{1F469}\u{200D}\u{1F4BB}");
+        assert_eq!(clean_chars("a\u{00A0}b\u{2009}c\n\td"), "a b c\n\td");
+    }
+
+    #[test]
+    fn keeps_paragraphs_while_collapsing_whitespace() {
+        let text = "  First   line  \n\n\n\n Second\u{00A0}\u{00A0}paragraph\nsame   paragraph \n \n";
+        assert_eq!(normalize_text(text), "First line\n\nSecond paragraph\nsame paragraph");
+        assert_eq!(normalize_text(" \n\u{200B}\n "), "");
+    }
+}
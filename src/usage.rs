@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+use crate::config::PricingConfig;
+use crate::llm::TokenUsage;
+
+/// The LLM tokens a summary took and what they cost
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SummaryUsage {
+    /// Model that produced the summary, as reported by the provider
+    pub model: String,
+    #[serde(flatten)]
+    pub tokens: TokenUsage,
+    /// Cost in USD; `None` when the model has no configured price
+    pub cost_usd: Option<f64>,
+}
+
+impl SummaryUsage {
+    /// Prices the tokens of a summary produced by `model`
+    pub fn new(model: &str, tokens: TokenUsage, pricing: &PricingConfig) -> Self {
+        let cost_usd = pricing.price(model).map(|price| {
+            (tokens.prompt_tokens as f64 * price.prompt + tokens.completion_tokens as f64 * price.completion) / 1_000_000.0
+        });
+        SummaryUsage { model: model.to_string(), tokens, cost_usd }
+    }
+
+    /// Adds up the usage of several summaries. The cost is only known when it
+    /// is known for all of them, and the model is `mixed` when they differ.
+    pub fn sum<'a>(usages: impl IntoIterator<Item = &'a SummaryUsage>) -> Option<SummaryUsage> {
+        usages.into_iter().cloned().reduce(|mut total, usage| {
+            if total.model != usage.model {
+                total.model = "mixed".to_string();
+            }
+            total.tokens += usage.tokens;
+            total.cost_usd = total.cost_usd.zip(usage.cost_usd).map(|(a, b)| a + b);
+            total
+        })
+    }
+}